@@ -0,0 +1,184 @@
+# bash completion for mkdesktop
+# Install with: mkdesktop completions bash > ~/.local/share/bash-completion/completions/mkdesktop
+
+_mkdesktop() {
+    local cur prev subcommand
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    subcommand="${COMP_WORDS[1]}"
+
+    local subcommands="new webapp edit show list set unset rm scan run gui validate doctor reconcile default scheme autostart shortcut flatpak override menu completions help"
+    local entry_flags="--exec --arg --env --type --url --name --icon --categories --mime --path --tooltip --on-desktop --off-desktop --wine-prefix --help -e -n -i -c -p -t -y --yes -h"
+
+    case "$prev" in
+        --icon|-i)
+            COMPREPLY=( $(compgen -f -- "$cur") )
+            return
+            ;;
+        --path|-p|--wine-prefix)
+            COMPREPLY=( $(compgen -d -- "$cur") )
+            return
+            ;;
+        --categories|-c)
+            # Only complete the category after the last semicolon
+            local done_part=""
+            if [[ "$cur" == *";"* ]]; then done_part="${cur%;*};"; fi
+            local IFS=$'\n'
+            COMPREPLY=( $(compgen -P "$done_part" -S ";" -W "$(mkdesktop --complete-categories 2>/dev/null)" -- "${cur##*;}") )
+            compopt -o nospace
+            return
+            ;;
+        --mime)
+            COMPREPLY=( $(compgen -W "$(mkdesktop --complete-mime-types 2>/dev/null)" -- "$cur") )
+            return
+            ;;
+        --all-in-category)
+            COMPREPLY=( $(compgen -W "$(mkdesktop --complete-categories 2>/dev/null)" -- "$cur") )
+            return
+            ;;
+        --match)
+            return
+            ;;
+        --exec|-e)
+            COMPREPLY=( $(compgen -c -- "$cur") )
+            return
+            ;;
+        --type)
+            COMPREPLY=( $(compgen -W "application link directory" -- "$cur") )
+            return
+            ;;
+        --browser|-b)
+            COMPREPLY=( $(compgen -c -- "$cur") )
+            return
+            ;;
+        --name|-n|--tooltip|-t|--arg|--env|--url|--delay)
+            return
+            ;;
+    esac
+
+    if [[ $COMP_CWORD -eq 1 ]]; then
+        if [[ "$cur" == -* ]]; then
+            COMPREPLY=( $(compgen -W "--help --version -h -V" -- "$cur") )
+        else
+            COMPREPLY=( $(compgen -W "$subcommands" -- "$cur") )
+        fi
+        return
+    fi
+
+    local IFS=$'\n'
+    case "$subcommand" in
+        new)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "$entry_flags" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -f -- "$cur") )
+            fi
+            ;;
+        webapp)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--name --browser --kiosk --icon --categories --tooltip --help -n -b -i -c -t -y --yes" -- "$cur") )
+            fi
+            ;;
+        edit)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "$entry_flags" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        gui)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--new --help" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        set|unset|rm)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--match --all-in-category --yes -y --help" -- "$cur") )
+            elif [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        scan)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--yes -y --help" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -d -- "$cur") )
+            fi
+            ;;
+        show|run|validate)
+            if [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        doctor)
+            COMPREPLY=( $(compgen -W "--fix --yes -y --help" -- "$cur") )
+            ;;
+        reconcile)
+            COMPREPLY=( $(compgen -W "--restore --yes -y --help" -- "$cur") )
+            ;;
+        default)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--remove --help" -- "$cur") )
+            elif [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            else
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-mime-types 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        scheme)
+            if [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "register test list" -- "$cur") )
+            elif [[ $COMP_CWORD -eq 4 && "${COMP_WORDS[2]}" == register ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        autostart)
+            if [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "enable disable list" -- "$cur") )
+            elif [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--delay --help" -- "$cur") )
+            elif [[ $COMP_CWORD -eq 3 ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        shortcut)
+            if [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "set remove list" -- "$cur") )
+            elif [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--force --help" -- "$cur") )
+            elif [[ $COMP_CWORD -eq 3 ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        flatpak)
+            if [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "fork list" -- "$cur") )
+            elif [[ "$cur" == -* && "${COMP_WORDS[2]}" == fork ]]; then
+                COMPREPLY=( $(compgen -W "--arg --env --reset --help" -- "$cur") )
+            fi
+            ;;
+        override)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--arg --env --set --reset --help" -- "$cur") )
+            fi
+            ;;
+        menu)
+            if [[ $COMP_CWORD -eq 2 ]]; then
+                COMPREPLY=( $(compgen -W "create add remove list" -- "$cur") )
+            elif [[ "$cur" == -* ]]; then
+                COMPREPLY=( $(compgen -W "--icon --tooltip --yes -i -t -y --help" -- "$cur") )
+            elif [[ $COMP_CWORD -eq 3 && "${COMP_WORDS[2]}" != create ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-menus 2>/dev/null)" -- "$cur") )
+            elif [[ $COMP_CWORD -gt 3 ]]; then
+                COMPREPLY=( $(compgen -W "$(mkdesktop --complete-entries 2>/dev/null)" -- "$cur") )
+            fi
+            ;;
+        completions)
+            COMPREPLY=( $(compgen -W "bash zsh fish" -- "$cur") )
+            ;;
+    esac
+}
+
+complete -o filenames -F _mkdesktop mkdesktop
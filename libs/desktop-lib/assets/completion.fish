@@ -0,0 +1,108 @@
+# fish completion for mkdesktop
+# Install with: mkdesktop completions fish > ~/.config/fish/completions/mkdesktop.fish
+
+set -l __mkdesktop_subcommands new webapp edit show list set unset rm scan run gui validate doctor reconcile default scheme autostart shortcut flatpak override menu completions
+
+function __mkdesktop_categories
+    # Keep whatever categories were already typed before the last semicolon
+    set -l done_part (string replace -r '[^;]*$' '' -- (commandline -ct))
+    for category in (mkdesktop --complete-categories 2>/dev/null)
+        echo "$done_part$category;"
+    end
+end
+
+complete -c mkdesktop -f
+
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a new         -d 'Make a new entry for an executable'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a webapp      -d 'Make an entry that opens a web page in its own browser window'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a edit        -d 'Change an existing entry'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a show        -d "Print an entry's desktop file"
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a list        -d 'List the desktop files managed by mkdesktop'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a set         -d "Set keys in an entry's desktop file"
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a unset       -d "Remove keys from an entry's desktop file"
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a rm          -d 'Remove an entry'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a scan        -d 'Make an entry for every executable in a folder'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a run         -d 'Launch an entry'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a gui         -d 'Start the GUI'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a validate    -d 'Check entries for problems'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a doctor      -d 'Check every entry for problems'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a reconcile   -d "Sync mkdesktop's entries with the applications menu"
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a default     -d 'Make an entry the default application for MIME types'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a scheme      -d 'Make entries open links like ourapp://'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a autostart   -d 'Start entries at login'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a shortcut    -d 'Launch entries with global keyboard shortcuts'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a flatpak     -d "Customize Flatpak apps' own launchers"
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a override    -d 'Shadow a system launcher with a changed copy'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a menu        -d 'Manage submenus of the applications menu'
+complete -c mkdesktop -n "not __fish_seen_subcommand_from $__mkdesktop_subcommands" -a completions -d 'Print a shell completion script'
+
+complete -c mkdesktop -n '__fish_seen_subcommand_from new' -F
+complete -c mkdesktop -n '__fish_seen_subcommand_from edit show set unset rm run gui validate default' -a '(mkdesktop --complete-entries 2>/dev/null)' -d 'Managed entry'
+complete -c mkdesktop -n '__fish_seen_subcommand_from completions' -a 'bash zsh fish'
+complete -c mkdesktop -n '__fish_seen_subcommand_from scan' -a '(__fish_complete_directories)'
+complete -c mkdesktop -n '__fish_seen_subcommand_from doctor' -l fix -d 'Repair what can be repaired'
+complete -c mkdesktop -n '__fish_seen_subcommand_from reconcile' -l restore -d 'Copy unbacked menu entries back instead of removing them'
+complete -c mkdesktop -n '__fish_seen_subcommand_from set unset rm' -l match           -x -d 'Every entry whose name matches a glob'
+complete -c mkdesktop -n '__fish_seen_subcommand_from set unset rm' -l all-in-category -x -a '(mkdesktop --complete-categories 2>/dev/null)' -d 'Every entry in a category'
+complete -c mkdesktop -n '__fish_seen_subcommand_from set unset rm scan doctor reconcile' -s y -l yes -d "Don't ask first"
+complete -c mkdesktop -n '__fish_seen_subcommand_from gui' -l new -d 'Open the editor for a new entry'
+complete -c mkdesktop -n '__fish_seen_subcommand_from default' -a '(mkdesktop --complete-mime-types 2>/dev/null)' -d 'MIME type'
+complete -c mkdesktop -n '__fish_seen_subcommand_from default' -l remove -d 'Stop offering the entry for these types instead'
+
+set -l __mkdesktop_scheme_subcommands register test list
+complete -c mkdesktop -n "__fish_seen_subcommand_from scheme; and not __fish_seen_subcommand_from $__mkdesktop_scheme_subcommands" -a "$__mkdesktop_scheme_subcommands"
+complete -c mkdesktop -n '__fish_seen_subcommand_from register; and __fish_seen_subcommand_from scheme' -a '(mkdesktop --complete-entries 2>/dev/null)' -d 'Managed entry'
+
+set -l __mkdesktop_autostart_subcommands enable disable list
+complete -c mkdesktop -n "__fish_seen_subcommand_from autostart; and not __fish_seen_subcommand_from $__mkdesktop_autostart_subcommands" -a "$__mkdesktop_autostart_subcommands"
+complete -c mkdesktop -n '__fish_seen_subcommand_from enable disable; and __fish_seen_subcommand_from autostart' -a '(mkdesktop --complete-entries 2>/dev/null)' -d 'Managed entry'
+complete -c mkdesktop -n '__fish_seen_subcommand_from enable; and __fish_seen_subcommand_from autostart' -l delay -x -d 'Seconds to wait after logging in'
+
+set -l __mkdesktop_shortcut_subcommands set remove list
+complete -c mkdesktop -n "__fish_seen_subcommand_from shortcut; and not __fish_seen_subcommand_from $__mkdesktop_shortcut_subcommands" -a "$__mkdesktop_shortcut_subcommands"
+complete -c mkdesktop -n '__fish_seen_subcommand_from set remove; and __fish_seen_subcommand_from shortcut' -a '(mkdesktop --complete-entries 2>/dev/null)' -d 'Managed entry'
+complete -c mkdesktop -n '__fish_seen_subcommand_from set; and __fish_seen_subcommand_from shortcut' -l force -d 'Take the keys even if something else uses them'
+
+set -l __mkdesktop_flatpak_subcommands fork list
+complete -c mkdesktop -n "__fish_seen_subcommand_from flatpak; and not __fish_seen_subcommand_from $__mkdesktop_flatpak_subcommands" -a "$__mkdesktop_flatpak_subcommands"
+complete -c mkdesktop -n '__fish_seen_subcommand_from fork; and __fish_seen_subcommand_from flatpak' -l arg -x -d 'Argument for the app'
+complete -c mkdesktop -n '__fish_seen_subcommand_from fork; and __fish_seen_subcommand_from flatpak' -l env -x -d 'Environment variable as KEY=VALUE'
+complete -c mkdesktop -n '__fish_seen_subcommand_from fork; and __fish_seen_subcommand_from flatpak' -l reset -d 'Remove the fork'
+
+complete -c mkdesktop -n '__fish_seen_subcommand_from override' -l arg -x -d 'Argument for the program'
+complete -c mkdesktop -n '__fish_seen_subcommand_from override' -l env -x -d 'Environment variable as KEY=VALUE'
+complete -c mkdesktop -n '__fish_seen_subcommand_from override' -l set -x -d 'Key=Value to set in the copy'
+complete -c mkdesktop -n '__fish_seen_subcommand_from override' -l reset -d 'Remove the override'
+
+set -l __mkdesktop_menu_subcommands create add remove list
+complete -c mkdesktop -n "__fish_seen_subcommand_from menu; and not __fish_seen_subcommand_from $__mkdesktop_menu_subcommands" -a "$__mkdesktop_menu_subcommands"
+complete -c mkdesktop -n '__fish_seen_subcommand_from add remove; and __fish_seen_subcommand_from menu' -a '(mkdesktop --complete-menus 2>/dev/null)' -d 'Menu'
+complete -c mkdesktop -n '__fish_seen_subcommand_from add remove; and __fish_seen_subcommand_from menu' -a '(mkdesktop --complete-entries 2>/dev/null)' -d 'Managed entry'
+complete -c mkdesktop -n '__fish_seen_subcommand_from create; and __fish_seen_subcommand_from menu' -s i -l icon -r -F -d 'Path to icon'
+complete -c mkdesktop -n '__fish_seen_subcommand_from create; and __fish_seen_subcommand_from menu' -s t -l tooltip -x -d 'Tooltip for the submenu'
+complete -c mkdesktop -n '__fish_seen_subcommand_from remove; and __fish_seen_subcommand_from menu' -s y -l yes -d "Don't ask before removing a whole submenu"
+
+complete -c mkdesktop -n '__fish_seen_subcommand_from webapp' -s n -l name       -x -d 'Name of the web app'
+complete -c mkdesktop -n '__fish_seen_subcommand_from webapp' -s b -l browser    -x -a '(__fish_complete_command)' -d 'Browser to use'
+complete -c mkdesktop -n '__fish_seen_subcommand_from webapp' -l kiosk              -d 'Open fullscreen with no browser controls'
+complete -c mkdesktop -n '__fish_seen_subcommand_from webapp' -s i -l icon       -r -F -d 'Path to icon'
+complete -c mkdesktop -n '__fish_seen_subcommand_from webapp' -s c -l categories -x -a '(__mkdesktop_categories)' -d 'Semicolon-separated categories'
+complete -c mkdesktop -n '__fish_seen_subcommand_from webapp' -s t -l tooltip    -x -d 'Tooltip when user hovers over the web app in launcher'
+complete -c mkdesktop -n '__fish_seen_subcommand_from webapp' -s y -l yes           -d 'Create the entry without asking about anything'
+
+set -l __mkdesktop_entry_commands '__fish_seen_subcommand_from new edit'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -s e -l exec       -x -a '(__fish_complete_command)' -d 'Command to run'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -l arg               -x -d 'Argument for the program (can be repeated)'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -l env               -x -d 'Environment variable as KEY=VALUE (can be repeated)'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -l type              -x -a 'application link directory' -d 'Kind of entry'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -l url               -x -d 'URL for a link entry'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -s n -l name       -x -d 'Name of program'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -s i -l icon       -r -F -d 'Path to icon'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -s c -l categories -x -a '(__mkdesktop_categories)' -d 'Semicolon-separated categories'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -l mime              -x -a '(mkdesktop --complete-mime-types 2>/dev/null)' -d 'MIME type the program opens (can be repeated)'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -s p -l path       -x -a '(__fish_complete_directories)' -d 'Working directory for when the program gets run'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -s t -l tooltip    -x -d 'Tooltip when user hovers over application in launcher'
+complete -c mkdesktop -n $__mkdesktop_entry_commands -l on-desktop         -d 'Put an icon for the entry on the desktop'
+complete -c mkdesktop -n '__fish_seen_subcommand_from new' -l wine-prefix -x -a '(__fish_complete_directories)' -d 'Wine prefix to run a Windows program in'
+complete -c mkdesktop -n '__fish_seen_subcommand_from edit' -l off-desktop -d "Take the entry's icon off the desktop"
+complete -c mkdesktop -n $__mkdesktop_entry_commands -s y -l yes           -d 'Create/update desktop entry without asking about anything'
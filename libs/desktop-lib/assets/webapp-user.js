@@ -0,0 +1,11 @@
+// Written by mkdesktop for a web app's own Firefox profile
+
+// Lets chrome/userChrome.css hide the tabs and toolbars
+user_pref("toolkit.legacyUserProfileCustomizations.stylesheets", true);
+
+// A web app window isn't a browser, so skip the browser's first-run chatter
+user_pref("browser.shell.checkDefaultBrowser", false);
+user_pref("browser.aboutwelcome.enabled", false);
+user_pref("browser.startup.homepage_override.mstone", "ignore");
+user_pref("datareporting.policy.dataSubmissionPolicyBypassNotification", true);
+user_pref("browser.tabs.warnOnClose", false);
@@ -0,0 +1,41 @@
+//! parse -> serialize -> parse has to settle after one round: whatever parses, writes out as something that
+//! parses to the same thing and writes out identically. Seeded from tests/corpus:
+//!
+//!     cargo fuzz run parse_roundtrip ../tests/corpus
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use std::io;
+
+use desktop_lib::desktop::DesktopEntry;
+use desktop_lib::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+
+
+fuzz_target!(|data: &[u8]| {
+    let keyfile = match KeyFile::read(&mut io::Cursor::new(data)) {
+        Ok(k)  => k,
+        Err(_) => return
+    };
+
+    let written = keyfile.to_bytes();
+    let reparsed = KeyFile::read(&mut io::Cursor::new(&written)).expect("Serialized keyfile doesn't parse");
+    assert_eq!(reparsed.to_bytes(), written);
+
+    for group in keyfile.groups() {
+        assert_eq!(reparsed.keys(group), keyfile.keys(group));
+        for key in keyfile.keys(group) {
+            assert_eq!(reparsed.get(group, key), keyfile.get(group, key));
+        }
+    }
+
+    if !keyfile.has_group(DESKTOP_ENTRY_GROUP) { return }
+
+    let mut first = Vec::new();
+    DesktopEntry::from_keyfile(&keyfile).write(&mut first).unwrap();
+
+    let entry = DesktopEntry::read(&mut io::Cursor::new(&first)).expect("Serialized entry doesn't parse");
+    let mut second = Vec::new();
+    entry.write(&mut second).unwrap();
+    assert_eq!(second, first);
+});
@@ -0,0 +1,191 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::context::Context;
+use super::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+
+
+pub const ENABLED_KEY: &str = "X-GNOME-Autostart-enabled";
+pub const DELAY_KEY: &str = "X-GNOME-Autostart-Delay";
+
+/// Keys that belong to the autostart copy rather than the entry, kept when the copy is refreshed
+const AUTOSTART_KEYS: [&str; 3] = [ENABLED_KEY, DELAY_KEY, "Hidden"];
+
+
+/// Where the user's autostart file for a desktop file ID goes
+pub fn autostart_path(context: &Context, desktop_id: &str) -> PathBuf {
+    context.autostart_dir().join(desktop_id)
+}
+
+
+/// An autostart file with this ID that came with the system, e.g. /etc/xdg/autostart/nm-applet.desktop
+pub fn system_file(context: &Context, desktop_id: &str) -> Option<PathBuf> {
+    context.system_config_dirs().iter()
+        .map(|dir| dir.join("autostart").join(desktop_id))
+        .find(|path| path.is_file())
+}
+
+
+/// Everything in the user's and the system's autostart folders, sorted and without duplicates
+pub fn desktop_ids(context: &Context) -> Vec<String> {
+    let mut folders = vec![context.autostart_dir()];
+    folders.extend(context.system_config_dirs().iter().map(|dir| dir.join("autostart")));
+
+    let mut result: Vec<String> = folders.iter()
+        .filter_map(|folder| fs::read_dir(folder).ok())
+        .flat_map(|files| files.filter_map(|f| f.ok()))
+        .filter_map(|f| f.file_name().to_str().map(String::from))
+        .filter(|name| name.ends_with(".desktop"))
+        .collect();
+    result.sort();
+    result.dedup();
+    result
+}
+
+
+/// Whether the entry starts at login: the user's file decides if there is one, the system's otherwise
+pub fn is_enabled(context: &Context, desktop_id: &str) -> bool {
+    let user_file = autostart_path(context, desktop_id);
+    let effective = if user_file.exists() { Some(user_file) } else { system_file(context, desktop_id) };
+
+    match effective.and_then(|path| KeyFile::open(&path).ok()) {
+        Some(keyfile) => {
+            let is = |key: &str, value: &str| keyfile.get(DESKTOP_ENTRY_GROUP, key).map(|v| v.eq_ignore_ascii_case(value)).unwrap_or(false);
+            !is("Hidden", "true") && !is(ENABLED_KEY, "false")
+        }
+        None => false
+    }
+}
+
+
+/// The login delay in seconds, if one is set
+pub fn delay(context: &Context, desktop_id: &str) -> Option<u32> {
+    KeyFile::open(&autostart_path(context, desktop_id)).ok()?
+        .get(DESKTOP_ENTRY_GROUP, DELAY_KEY)?
+        .parse().ok()
+}
+
+
+/// Makes the desktop file at `source` start at login by copying it into the autostart folder.
+/// A delay that was set before is kept unless a new one is given.
+pub fn enable(context: &Context, desktop_id: &str, source: &Path, delay: Option<u32>) -> io::Result<()> {
+    let destination = autostart_path(context, desktop_id);
+    let previous = KeyFile::open(&destination).ok();
+
+    let mut keyfile = KeyFile::open(source)?;
+    keyfile.unset(DESKTOP_ENTRY_GROUP, "Hidden");
+    keyfile.set(DESKTOP_ENTRY_GROUP, ENABLED_KEY, "true");
+
+    match (delay, previous.as_ref().and_then(|p| p.get_string(DESKTOP_ENTRY_GROUP, DELAY_KEY))) {
+        (Some(0), _)         => { keyfile.unset(DESKTOP_ENTRY_GROUP, DELAY_KEY); }
+        (Some(seconds), _)   => keyfile.set(DESKTOP_ENTRY_GROUP, DELAY_KEY, &seconds.to_string()),
+        (None, Some(before)) => keyfile.set(DESKTOP_ENTRY_GROUP, DELAY_KEY, &before),
+        (None, None)         => {}
+    }
+
+    fs::create_dir_all(context.autostart_dir())?;
+    fs::write(destination, keyfile.to_bytes())
+}
+
+
+/// Stops the entry from starting at login. Our own copies are just removed, but one that came with
+/// the system can't be, so it's overridden with a copy that has Hidden=true.
+pub fn disable(context: &Context, desktop_id: &str) -> io::Result<()> {
+    let destination = autostart_path(context, desktop_id);
+
+    match system_file(context, desktop_id) {
+        Some(system) => {
+            let mut keyfile = KeyFile::open(if destination.exists() { &destination } else { &system })?;
+            keyfile.set(DESKTOP_ENTRY_GROUP, "Hidden", "true");
+            fs::create_dir_all(context.autostart_dir())?;
+            fs::write(destination, keyfile.to_bytes())
+        }
+        None if destination.exists() => fs::remove_file(destination),
+        None => Ok(())
+    }
+}
+
+
+/// Rewrites the autostart copy (if there is one) from the entry's file at `source`, keeping its autostart keys.
+/// Called whenever an entry is saved so the copy doesn't go stale.
+pub fn refresh(context: &Context, desktop_id: &str, source: &Path) -> io::Result<()> {
+    move_copy(context, desktop_id, desktop_id, source)
+}
+
+
+/// For when an entry's file is renamed: the copy moves along with it
+pub fn rename_entry(context: &Context, old_id: &str, new_id: &str, source: &Path) -> io::Result<()> {
+    move_copy(context, old_id, new_id, source)?;
+    let old_copy = autostart_path(context, old_id);
+    if old_id != new_id && old_copy.exists() { fs::remove_file(old_copy)?; }
+    Ok(())
+}
+
+
+/// Takes a deleted entry's copy out of the autostart folder
+pub fn forget_entry(context: &Context, desktop_id: &str) -> io::Result<()> {
+    let copy = autostart_path(context, desktop_id);
+    if copy.exists() && system_file(context, desktop_id).is_none() { fs::remove_file(copy)?; }
+    Ok(())
+}
+
+
+fn move_copy(context: &Context, old_id: &str, new_id: &str, source: &Path) -> io::Result<()> {
+    let old_copy = match KeyFile::open(&autostart_path(context, old_id)) {
+        Ok(k) => k,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e)
+    };
+
+    let mut keyfile = KeyFile::open(source)?;
+    for key in &AUTOSTART_KEYS {
+        match old_copy.get_string(DESKTOP_ENTRY_GROUP, key) {
+            Some(value) => keyfile.set(DESKTOP_ENTRY_GROUP, key, &value),
+            None        => { keyfile.unset(DESKTOP_ENTRY_GROUP, key); }
+        }
+    }
+    fs::write(autostart_path(context, new_id), keyfile.to_bytes())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+
+    #[test]
+    fn autostart_copies_and_overrides() {
+        let root = TestDir::new("autostart");
+        let system = root.join("xdg");
+        fs::create_dir_all(system.join("autostart")).unwrap();
+        fs::write(system.join("autostart").join("applet.desktop"), "[Desktop Entry]\nName=Applet\nExec=applet\n").unwrap();
+        fs::write(root.join("tool.desktop"), "[Desktop Entry]\nName=Tool\nExec=tool\n").unwrap();
+
+        let mut context = root.context();
+        context.set_system_config_dirs(vec![system]);
+
+        // Our own entries get a copy, which goes away again
+        assert!(!is_enabled(&context, "tool.desktop"));
+        enable(&context, "tool.desktop", &root.join("tool.desktop"), Some(5)).unwrap();
+        assert!(is_enabled(&context, "tool.desktop"));
+        assert_eq!(delay(&context, "tool.desktop"), Some(5));
+
+        fs::write(root.join("tool.desktop"), "[Desktop Entry]\nName=Tool\nExec=tool --quiet\n").unwrap();
+        refresh(&context, "tool.desktop", &root.join("tool.desktop")).unwrap();
+        let copy = KeyFile::open(&autostart_path(&context, "tool.desktop")).unwrap();
+        assert_eq!(copy.get(DESKTOP_ENTRY_GROUP, "Exec"), Some("tool --quiet"));
+        assert_eq!(copy.get(DESKTOP_ENTRY_GROUP, DELAY_KEY), Some("5"));
+
+        disable(&context, "tool.desktop").unwrap();
+        assert!(!autostart_path(&context, "tool.desktop").exists());
+
+        // System ones get a Hidden=true override
+        assert!(is_enabled(&context, "applet.desktop"));
+        disable(&context, "applet.desktop").unwrap();
+        assert!(!is_enabled(&context, "applet.desktop"));
+        let source = system_file(&context, "applet.desktop").unwrap();
+        enable(&context, "applet.desktop", &source, None).unwrap();
+        assert!(is_enabled(&context, "applet.desktop"));
+    }
+}
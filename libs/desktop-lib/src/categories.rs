@@ -0,0 +1,357 @@
+/// A registered category from the Desktop Menu Specification.
+///
+/// `requires` lists alternatives: at least one of them has to be fully present alongside the category
+/// (e.g. `Midi` needs both `AudioVideo` and `Audio`). An empty list means no requirements.
+pub struct Category {
+    pub name: &'static str,
+    pub main: bool,
+    pub requires: &'static [&'static [&'static str]],
+}
+
+macro_rules! category {
+    ( main $name:expr ) => { Category { name: $name, main: true, requires: &[] } };
+    ( main $name:expr, $($req:expr),+ ) => { Category { name: $name, main: true, requires: &[$($req),+] } };
+    ( $name:expr ) => { Category { name: $name, main: false, requires: &[] } };
+    ( $name:expr, $($req:expr),+ ) => { Category { name: $name, main: false, requires: &[$($req),+] } };
+}
+
+
+pub const CATEGORIES: &[Category] = &[
+    // Main categories
+    category!(main "AudioVideo"),
+    category!(main "Audio", &["AudioVideo"]),
+    category!(main "Video", &["AudioVideo"]),
+    category!(main "Development"),
+    category!(main "Education"),
+    category!(main "Game"),
+    category!(main "Graphics"),
+    category!(main "Network"),
+    category!(main "Office"),
+    category!(main "Science"),
+    category!(main "Settings"),
+    category!(main "System"),
+    category!(main "Utility"),
+
+    // Additional categories
+    category!("Building", &["Development"]),
+    category!("Debugger", &["Development"]),
+    category!("IDE", &["Development"]),
+    category!("GUIDesigner", &["Development"]),
+    category!("Profiling", &["Development"]),
+    category!("RevisionControl", &["Development"]),
+    category!("Translation", &["Development"]),
+    category!("Calendar", &["Office"]),
+    category!("ContactManagement", &["Office"]),
+    category!("Database", &["Office"], &["Development"], &["AudioVideo"]),
+    category!("Dictionary", &["Office"], &["TextTools"]),
+    category!("Chart", &["Office"]),
+    category!("Email", &["Office"], &["Network"]),
+    category!("Finance", &["Office"]),
+    category!("FlowChart", &["Office"]),
+    category!("PDA", &["Office"]),
+    category!("ProjectManagement", &["Office"], &["Development"]),
+    category!("Presentation", &["Office"]),
+    category!("Spreadsheet", &["Office"]),
+    category!("WordProcessor", &["Office"]),
+    category!("2DGraphics", &["Graphics"]),
+    category!("VectorGraphics", &["Graphics", "2DGraphics"]),
+    category!("RasterGraphics", &["Graphics", "2DGraphics"]),
+    category!("3DGraphics", &["Graphics"]),
+    category!("Scanning", &["Graphics"]),
+    category!("OCR", &["Graphics", "Scanning"]),
+    category!("Photography", &["Graphics"], &["Office"]),
+    category!("Publishing", &["Graphics"], &["Office"]),
+    category!("Viewer", &["Graphics"], &["Office"]),
+    category!("TextTools", &["Utility"]),
+    category!("DesktopSettings", &["Settings"]),
+    category!("HardwareSettings", &["Settings"]),
+    category!("Printing", &["HardwareSettings", "Settings"]),
+    category!("PackageManager", &["Settings"]),
+    category!("Dialup", &["Network"]),
+    category!("InstantMessaging", &["Network"]),
+    category!("Chat", &["Network"]),
+    category!("IRCClient", &["Network"]),
+    category!("Feed", &["Network"]),
+    category!("FileTransfer", &["Network"]),
+    category!("HamRadio", &["Network"], &["Audio"]),
+    category!("News", &["Network"]),
+    category!("P2P", &["Network"]),
+    category!("RemoteAccess", &["Network"]),
+    category!("Telephony", &["Network"]),
+    category!("TelephonyTools", &["Utility"]),
+    category!("VideoConference", &["Network"]),
+    category!("WebBrowser", &["Network"]),
+    category!("WebDevelopment", &["Network"], &["Development"]),
+    category!("Midi", &["AudioVideo", "Audio"]),
+    category!("Mixer", &["AudioVideo", "Audio"]),
+    category!("Sequencer", &["AudioVideo", "Audio"]),
+    category!("Tuner", &["AudioVideo", "Audio"]),
+    category!("TV", &["AudioVideo", "Video"]),
+    category!("AudioVideoEditing", &["Audio"], &["Video"], &["AudioVideo"]),
+    category!("Player", &["Audio"], &["Video"], &["AudioVideo"]),
+    category!("Recorder", &["Audio"], &["Video"], &["AudioVideo"]),
+    category!("DiscBurning", &["AudioVideo"]),
+    category!("ActionGame", &["Game"]),
+    category!("AdventureGame", &["Game"]),
+    category!("ArcadeGame", &["Game"]),
+    category!("BoardGame", &["Game"]),
+    category!("BlocksGame", &["Game"]),
+    category!("CardGame", &["Game"]),
+    category!("KidsGame", &["Game"]),
+    category!("LogicGame", &["Game"]),
+    category!("RolePlaying", &["Game"]),
+    category!("Shooter", &["Game"]),
+    category!("Simulation", &["Game"]),
+    category!("SportsGame", &["Game"]),
+    category!("StrategyGame", &["Game"]),
+    category!("Art", &["Education"], &["Science"]),
+    category!("Construction", &["Education"], &["Science"]),
+    category!("Music", &["AudioVideo"], &["Education"]),
+    category!("Languages", &["Education"], &["Science"]),
+    category!("ArtificialIntelligence", &["Education"], &["Science"]),
+    category!("Astronomy", &["Education"], &["Science"]),
+    category!("Biology", &["Education"], &["Science"]),
+    category!("Chemistry", &["Education"], &["Science"]),
+    category!("ComputerScience", &["Education"], &["Science"]),
+    category!("DataVisualization", &["Education"], &["Science"]),
+    category!("Economy", &["Education"], &["Science"]),
+    category!("Electricity", &["Education"], &["Science"]),
+    category!("Geography", &["Education"], &["Science"]),
+    category!("Geology", &["Education"], &["Science"]),
+    category!("Geoscience", &["Education"], &["Science"]),
+    category!("History", &["Education"], &["Science"]),
+    category!("Humanities", &["Education"], &["Science"]),
+    category!("ImageProcessing", &["Education"], &["Science"]),
+    category!("Literature", &["Education"], &["Science"]),
+    category!("Maps", &["Education"], &["Science"], &["Utility"]),
+    category!("Math", &["Education"], &["Science"]),
+    category!("NumericalAnalysis", &["Education", "Math"], &["Science", "Math"]),
+    category!("MedicalSoftware", &["Education"], &["Science"]),
+    category!("Physics", &["Education"], &["Science"]),
+    category!("Robotics", &["Education"], &["Science"]),
+    category!("Spirituality", &["Education"], &["Science"], &["Utility"]),
+    category!("Sports", &["Education"], &["Science"]),
+    category!("ParallelComputing", &["Education", "ComputerScience"], &["Science", "ComputerScience"]),
+    category!("Amusement"),
+    category!("Archiving", &["Utility"]),
+    category!("Compression", &["Utility"]),
+    category!("Electronics"),
+    category!("Emulator", &["System"], &["Game"]),
+    category!("Engineering"),
+    category!("FileTools", &["Utility"], &["System"]),
+    category!("FileManager", &["System", "FileTools"]),
+    category!("TerminalEmulator", &["System"]),
+    category!("Filesystem", &["System"]),
+    category!("Monitor", &["System"], &["Network"]),
+    category!("Security", &["Settings"], &["System"]),
+    category!("Accessibility", &["Settings"], &["Utility"]),
+    category!("Calculator", &["Utility"]),
+    category!("Clock", &["Utility"]),
+    category!("TextEditor", &["Utility"]),
+    category!("Documentation"),
+    category!("Adult"),
+    category!("Core"),
+    category!("KDE", &["Qt"]),
+    category!("GNOME", &["GTK"]),
+    category!("XFCE", &["GTK"]),
+    category!("DDE", &["Qt"]),
+    category!("GTK"),
+    category!("Qt"),
+    category!("Motif"),
+    category!("Java"),
+    category!("ConsoleOnly"),
+];
+
+/// Reserved categories are only valid alongside OnlyShowIn=, which mkdesktop doesn't write
+pub const RESERVED_CATEGORIES: [&str; 4] = ["Screensaver", "TrayIcon", "Applet", "Shell"];
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CategoryProblem {
+    /// Not in the registry (and not an X- extension). Carries the closest registered name, if any.
+    Unknown(String, Option<&'static str>),
+    /// Registered, but none of its "requires related" alternatives are present
+    MissingRequirement(String, String),
+    Reserved(String),
+    /// Nothing from the main list, so most menus will file it under "Other"
+    NoMainCategory,
+}
+
+impl CategoryProblem {
+    pub fn describe(&self) -> String {
+        match self {
+            CategoryProblem::Unknown(name, Some(suggestion)) => format!("Unknown category \"{}\" (did you mean {}?)", name, suggestion),
+            CategoryProblem::Unknown(name, None) => format!("Unknown category \"{}\" (custom categories must start with X-)", name),
+            CategoryProblem::MissingRequirement(name, needs) => format!("{} also requires {}", name, needs),
+            CategoryProblem::Reserved(name) => format!("{} is reserved for desktop-specific entries", name),
+            CategoryProblem::NoMainCategory => String::from("No main category, so this will probably show up under \"Other\""),
+        }
+    }
+
+    /// Problems that mean the launcher won't show up where the user expects
+    pub fn is_error(&self) -> bool {
+        match self {
+            CategoryProblem::NoMainCategory => false,
+            _ => true
+        }
+    }
+}
+
+
+pub fn lookup(name: &str) -> Option<&'static Category> {
+    CATEGORIES.iter().find(|c| c.name == name)
+}
+
+
+pub fn main_categories() -> Vec<&'static str> {
+    CATEGORIES.iter().filter(|c| c.main).map(|c| c.name).collect()
+}
+
+
+pub fn all_names() -> Vec<&'static str> {
+    CATEGORIES.iter().map(|c| c.name).collect()
+}
+
+
+/// "Education;Languages;" -> ["Education", "Languages"]
+pub fn split(categories: &str) -> Vec<String> {
+    categories.split(';')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+
+/// ["Education", "Languages"] -> "Education;Languages;"
+pub fn join<S: AsRef<str>>(categories: &[S]) -> String {
+    let mut result = String::new();
+    for category in categories {
+        result += category.as_ref();
+        result += ";";
+    }
+    result
+}
+
+
+/// Registered names starting with `prefix` (case-insensitively)
+pub fn complete(prefix: &str) -> Vec<&'static str> {
+    let prefix = prefix.to_lowercase();
+    CATEGORIES.iter()
+        .map(|c| c.name)
+        .filter(|name| name.to_lowercase().starts_with(&prefix))
+        .collect()
+}
+
+
+/// Fixes the case of a registered name. Anything else comes back unchanged -- a prefix
+/// like "Termin" is only suggested (see validate), since it may not be what was meant.
+pub fn normalize(category: &str) -> String {
+    if category.starts_with("X-") { return category.to_string() }
+
+    match CATEGORIES.iter().find(|c| c.name.eq_ignore_ascii_case(category)) {
+        Some(exact) => exact.name.to_string(),
+        None        => category.to_string()
+    }
+}
+
+
+pub fn validate<S: AsRef<str>>(categories: &[S]) -> Vec<CategoryProblem> {
+    let names: Vec<&str> = categories.iter().map(|c| c.as_ref()).collect();
+    let mut problems = Vec::new();
+
+    for name in &names {
+        if name.starts_with("X-") { continue }
+
+        if RESERVED_CATEGORIES.contains(name) {
+            problems.push(CategoryProblem::Reserved(name.to_string()));
+            continue;
+        }
+
+        let category = match lookup(name) {
+            Some(c) => c,
+            None    => {
+                problems.push(CategoryProblem::Unknown(name.to_string(), suggestion(name)));
+                continue;
+            }
+        };
+
+        let satisfied = category.requires.is_empty() || category.requires.iter()
+            .any(|alternative| alternative.iter().all(|required| names.contains(required)));
+
+        if !satisfied {
+            let alternatives: Vec<String> = category.requires.iter().map(|a| a.join(" and ")).collect();
+            problems.push(CategoryProblem::MissingRequirement(name.to_string(), alternatives.join(" or ")));
+        }
+    }
+
+    if !names.iter().any(|name| lookup(name).map(|c| c.main).unwrap_or(false)) {
+        problems.push(CategoryProblem::NoMainCategory);
+    }
+
+    problems
+}
+
+
+/// What an unknown name was probably meant to be: the one category it's a prefix of, or else one a typo or two away
+fn suggestion(name: &str) -> Option<&'static str> {
+    match complete(name).as_slice() {
+        [only] if !name.is_empty() => Some(*only),
+        _ => closest(name)
+    }
+}
+
+/// Closest registered name by edit distance, as long as it's reasonably close
+fn closest(name: &str) -> Option<&'static str> {
+    let lowered = name.to_lowercase();
+
+    CATEGORIES.iter()
+        .map(|c| (c.name, edit_distance(&lowered, &c.name.to_lowercase())))
+        .filter(|(candidate, distance)| *distance <= 2.max(candidate.len() / 4))
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(candidate, _)| candidate)
+}
+
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{validate, normalize, CategoryProblem};
+
+    #[test]
+    fn categories_are_validated_against_the_registry() {
+        assert_eq!(validate(&["Education", "Languages"]), vec![]);
+        assert_eq!(validate(&["AudioVideo", "Audio", "Midi", "X-Custom"]), vec![]);
+
+        assert_eq!(
+            validate(&["Developement", "IDE"]),
+            vec![
+                CategoryProblem::Unknown("Developement".to_string(), Some("Development")),
+                CategoryProblem::MissingRequirement("IDE".to_string(), "Development".to_string()),
+                CategoryProblem::NoMainCategory,
+            ]
+        );
+
+        assert_eq!(validate(&["System", "Termin"])[0], CategoryProblem::Unknown("Termin".to_string(), Some("TerminalEmulator")));
+
+        assert_eq!(normalize("development"), "Development");
+        assert_eq!(normalize("Termin"), "Termin");
+        assert_eq!(normalize("Au"), "Au");
+    }
+}
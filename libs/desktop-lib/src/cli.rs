@@ -4,10 +4,31 @@ use std::io;
 use std::env;
 use std::process;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 
 use path_abs::PathAbs;
 
-use super::desktop::{self, DesktopEntry};
+use super::desktop::{self, DesktopEntry, EntryType};
+use super::context::Context;
+use super::icons;
+use super::icon_sources;
+use super::icon_theme::IconSearch;
+use super::categories;
+use super::completions;
+use super::keyfile::{self, DESKTOP_ENTRY_GROUP};
+use super::exec::{self, Exec};
+use super::scan;
+use super::doctor;
+use super::reconcile;
+use super::menu;
+use super::webapp::{self, Browser, WebApp};
+use super::autostart;
+use super::mime::{self, MimeApps};
+use super::desktop_icon;
+use super::shortcut::{self, Accelerator, Gnome, Kde};
+use super::wine::{self, WineApp};
+use super::packages::{self, Package};
+use super::overrides;
 
 
 macro_rules! optional_entry_field {
@@ -26,95 +47,1099 @@ macro_rules! optional_entry_field {
 }
 
 
-pub fn create_or_update(entry_result: Option<io::Result<DesktopEntry>>, arg_matches: clap::ArgMatches) {
+/// Creates a new entry for `target` (a filename), or updates `entry_result` if an entry was selected.
+/// Fields not given on the command line are asked for on stdin unless -y was passed.
+pub fn create_or_update(context: &Context, entry_result: Option<io::Result<DesktopEntry>>, target: Option<&str>, arg_matches: &clap::ArgMatches) {
     let yes   = arg_matches.is_present("yes");
 
-    let (filename, entry) = match entry_result {
-        Some(result) => match result {
-            // An entry was successfully selected -- we want to update that entry
-            Ok(e)  => (e.get_exec().to_string(), Some(e.clone())),
+    let (target, entry) = match entry_result {
+        // An entry was successfully selected -- we want to update that entry
+        Some(Ok(e)) => (None, Some(e.clone())),
+
+        // No entry was selected or it couldn't be found -- what was given must be a filename for a new entry
+        _ => (target, None)
+    };
+
+    let entry_type = entry_type_from_args(entry.as_ref(), arg_matches);
+    let application = entry_type == EntryType::Application;
+
+    match target {
+        None if application && entry.is_none() && !arg_matches.is_present("exec") => {
+            error_out("Please specify a file or an entry (see --help)")
+        }
+        Some(file) if !application => {
+            error_out(&format!("{} is only for applications ({} entries don't run anything)", file, entry_type.as_str()))
+        }
+        _ => {}
+    }
+    if entry_type == EntryType::Directory && arg_matches.is_present("on_desktop") {
+        error_out("Menu folders can't go on the desktop");
+    }
+
+    // Windows programs, shortcuts and installers get run through wine, in a prefix the user picks
+    let wine_app = match target {
+        Some(file) if application && entry.is_none() && !arg_matches.is_present("exec") && wine::WineTarget::of(Path::new(file)).is_some() => {
+            let prefix = ask_for_wine_prefix(context, arg_matches.value_of("wine_prefix"), yes);
+            WineApp::new(Path::new(&absolute_program(file)), &prefix)
+        }
+        _ => None
+    };
+    // An installed Flatpak's app ID or a snap's name, rather than a file
+    let package = match target {
+        Some(file) if application && entry.is_none() && !arg_matches.is_present("exec") && wine_app.is_none() => Package::find(context, file),
+        _ => None
+    };
+    // What the package exports for itself fills in the defaults a new entry would otherwise lack
+    let defaults = match &entry {
+        Some(entry) => Some(entry.clone()),
+        None => package.as_ref().and_then(Package::exported_entry)
+    };
+
+    // Only applications have a command
+    let mut exec = Exec::parse("");
+    if application {
+        exec = exec_from_args(entry.as_ref().map(DesktopEntry::get_exec_command), target, arg_matches);
+        if let Some(app) = &wine_app {
+            // Arguments from the command line go to the Windows program
+            let mut wine_exec = app.exec();
+            let mut args = wine_exec.args().to_vec();
+            args.extend(exec.args().iter().cloned());
+            wine_exec.set_args(args);
+            exec = wine_exec;
+        }
+        if let Some(package) = &package {
+            let mut package_exec = package.exec();
+            let mut args = package_exec.args().to_vec();
+            args.extend(exec.args().iter().cloned());
+            package_exec.set_args(args);
+            exec = package_exec;
+        }
+        apply_env_args(&mut exec, arg_matches);
+        if exec.is_empty() { error_out("Applications need a command (see --exec)") }
+    }
+    // For icon suggestions -- with wine that's the .exe rather than wine itself
+    let exec_path = match wine_app.as_ref().and_then(WineApp::executable) {
+        Some(exe) => exe,
+        None => desktop::find_program(exec.program()).unwrap_or_else(|| PathBuf::from(exec.program()))
+    };
+    // ...and there's nothing to look through next to `flatpak` or `snap`
+    let icon_exec = if package.is_some() { None } else { Some(exec_path.as_path()) };
+
+    let name = match arg_matches.value_of("name") {
+        Some(arg) => String::from(arg),
+        None      => ask_stdin_for_str("Please enter a name for the desktop entry (required)", optional_entry_field!(defaults, get_name), yes)
+    };
+    if name.is_empty() {
+        println!("A name is required");
+        process::exit(1);
+    }
+
+    let comment = match arg_matches.value_of("comment") {
+        Some(arg) => String::from(arg),
+        None      => ask_stdin_for_str("Please enter a tooltip for the desktop entry", optional_entry_field!(defaults, get_comment), yes),
+    };
+
+    let url = match arg_matches.value_of("url") {
+        _ if entry_type != EntryType::Link => String::new(),
+        Some(arg) => String::from(arg),
+        None      => ask_stdin_for_str("Please enter the URL to open (required)", optional_entry_field!(entry, get_url), yes)
+    };
+    if entry_type == EntryType::Link && url.is_empty() {
+        error_out("A URL is required");
+    }
+
+    let categories = match arg_matches.value_of("categories") {
+        _ if !application => String::new(),
+        Some(arg) => match checked_categories(arg) {
+            Ok(c)         => c,
+            Err(problems) => error_out(&problems)
+        },
+        None => loop {
+            let prompt = format!(
+                "Please enter semicolon-separated categories (main ones are {})",
+                categories::main_categories().join(", ")
+            );
+            let answer = ask_stdin_for_str(&prompt, optional_entry_field!(defaults, get_categories), yes);
+
+            // With -y the answer is what the entry already had, which shouldn't stop other changes from being made
+            match checked_categories(&answer) {
+                Ok(c)                => break c,
+                Err(problems) if yes => {
+                    println!("Warning: {}", problems);
+                    break categories::join(&categories::split(&answer));
+                }
+                Err(problems)        => println!("{}", problems)
+            }
+        }
+    };
+
+    let path = match arg_matches.value_of("path") {
+        _ if !application => String::new(),
+        Some(arg) => String::from(arg),
+        None      => {
+            // Sandboxed apps don't see the folder mkdesktop was run in, so they get no working directory
+            let default = match package {
+                Some(_) => None,
+                None => wine_app.as_ref().and_then(WineApp::working_dir).map(|dir| dir.to_string_lossy().to_string()).or_else(pwd)
+            };
+            ask_stdin_for_str("Please enter the working directory for the binary", optional_entry_field!(defaults, get_path, default), yes)
+        }
+    };
+
+    let icon = match arg_matches.value_of("icon") {
+        Some(arg) => icon_from_arg(arg),
+        None => ask_for_icon(context, &defaults, if application { icon_exec } else { None }, yes),
+    };
+
+    // Prepare new entry
+    let mut new_entry = match entry_type {
+        EntryType::Application => DesktopEntry::new(&name, &comment, &path, exec, &icon, &categories),
+        EntryType::Link        => DesktopEntry::link(&name, &comment, &url, &icon),
+        EntryType::Directory   => DesktopEntry::directory(&name, &comment, &icon),
+    };
+    // Without --mime the entry keeps the types it had; `--mime ''` clears them
+    if application {
+        match (mime_types_from_args(context, arg_matches), &entry) {
+            (Some(mime_types), _)  => new_entry.set_mime_types(&mime_types),
+            (None, Some(old_entry)) => new_entry.set_mime_types(old_entry.get_mime_types()),
+            (None, None)            => {}
+        }
+    }
+    if let Some(app) = &wine_app {
+        app.apply_to(&mut new_entry);
+    }
+    if let Some(wm_class) = package.as_ref().and(defaults.as_ref()).and_then(|e| e.get_extra_key("StartupWMClass")) {
+        new_entry.set_extra_key("StartupWMClass", &wm_class);
+    }
+    if let Some(old_entry) = &entry {
+        new_entry.keep_unedited_fields(old_entry);
+    }
+
+    // Copy the icon somewhere it won't get moved out from under us
+    match new_entry.adopt_icon(context) {
+        Ok(()) => {}
+        Err(error) => error_out(&format!("Failed to install icon {} - {}", &icon, error.description()))
+    }
+
+    // Write to disk
+    match new_entry.write_to_apps_dir(context) {
+        Ok(()) => {}
+        Err(error) => error_out(error.description())
+    }
+
+    // Delete old entry file if name was changed
+    match entry {
+        Some(old_entry) => if old_entry.filename() != new_entry.filename() {
+            if let Err(error) = desktop::rename_references(context, &old_entry.filename(), &new_entry.filename()) {
+                println!("Couldn't give everything that refers to the entry its new name - {}", error);
+            }
+            match old_entry.delete(context) {
+                Ok(()) => {}
+                Err(error) => error_out(&format!("Failed to delete old entry ({}) you probably have a duplicate now", error.description()))
+            }
+        },
+        None => {}
+    }
+
+    // From here on the desktop copy is kept in sync whenever the entry is saved
+    let id = new_entry.filename();
+    if arg_matches.is_present("on_desktop") {
+        match desktop_icon::add(context, &id, &new_entry.filepath(context)) {
+            Ok(()) => println!("Put \"{}\" on the desktop ({:?})", new_entry.get_name(), desktop_icon::icon_path(context, &id)),
+            Err(error) => error_out(&format!("Failed to put \"{}\" on the desktop - {}", new_entry.get_name(), error))
+        }
+    }
+    if arg_matches.is_present("off_desktop") {
+        if let Err(error) = desktop_icon::remove(context, &id) {
+            error_out(&format!("Failed to take \"{}\" off the desktop - {}", new_entry.get_name(), error));
+        }
+    }
+
+    clean_up_icons(context);
+}
+
+
+/// `mkdesktop new <FILE>` (or `mkdesktop new --exec <COMMAND>`)
+pub fn new(context: &Context, file: Option<&str>, arg_matches: &clap::ArgMatches) {
+    create_or_update(context, None, file, arg_matches);
+}
+
+
+/// `mkdesktop edit <ENTRY>` -- unlike the old syntax, this never falls back to creating a new entry
+pub fn edit(context: &Context, entry_result: io::Result<DesktopEntry>, arg_matches: &clap::ArgMatches) {
+    match entry_result {
+        Ok(entry)  => create_or_update(context, Some(Ok(entry)), None, arg_matches),
+        Err(error) => error_out(&error.to_string())
+    }
+}
+
+
+pub fn run(entry_result: Option<io::Result<DesktopEntry>>) {
+    match valid_entry_or_none(entry_result) {
+        Some(entry) => match entry.launch() {
+            Ok(_) => {}
+            Err(error) => {
+                println!("Failed to launch \"{}\" - {}", entry.get_name(), error);
+                process::exit(14);
+            }
+        }
+        None => error_out("Please specify an entry, either by index or by name")
+    }
+}
+
+
+/// Checks the selected entry (or all of them) and exits with an error if anything is wrong
+pub fn validate(context: &Context, entry_result: Option<io::Result<DesktopEntry>>) {
+    let entries = match valid_entry_or_none(entry_result) {
+        Some(entry) => vec![entry],
+        None => match desktop::read_desktop_files(context) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Failed to read desktop files: {}", e);
+                process::exit(20);
+            }
+        }
+    };
+
+    let mut any_problems = false;
+    for entry in entries {
+        let problems = entry.validate(context);
+        if problems.is_empty() { continue }
+
+        any_problems = true;
+        println!("{} ({})", entry.get_name(), entry.filename());
+        for problem in problems {
+            println!("\t{}", problem);
+        }
+    }
+
+    if any_problems { process::exit(13) }
+    println!("Everything looks good");
+}
+
+
+/// What set/unset/rm were pointed at: one <ENTRY>, or everything --match/--all-in-category found
+pub enum Targets {
+    One(Option<io::Result<DesktopEntry>>),
+    Many(io::Result<Vec<DesktopEntry>>),
+}
+
+
+/// `mkdesktop set <ENTRY> Key=Value...` -- changes keys in the entry's file directly, leaving everything else alone
+pub fn set(context: &Context, targets: Targets, assignments: Vec<&str>, yes: bool) {
+    if assignments.is_empty() { error_out("Please give at least one Key=Value to set") }
+
+    let mut parsed = Vec::new();
+    for assignment in assignments {
+        match keyfile::parse_assignment(assignment) {
+            Ok(key_value) => parsed.push(key_value),
+            Err(problem)  => error_out(&problem)
+        }
+    }
+
+    let entries = resolve_targets(targets, "Change", yes);
+    update_keyfiles(context, entries, |_, file| {
+        for (key, value) in &parsed {
+            file.set(DESKTOP_ENTRY_GROUP, key, value);
+        }
+    });
+}
+
+
+/// `mkdesktop unset <ENTRY> Key...`
+pub fn unset(context: &Context, targets: Targets, keys: Vec<&str>, yes: bool) {
+    if keys.is_empty() { error_out("Please give at least one key to unset") }
+
+    for key in &keys {
+        if !keyfile::is_valid_key(key) {
+            error_out(&format!("\"{}\" isn't a valid key", key));
+        }
+        if *key == "Name" || *key == "Type" {
+            error_out(&format!("{} is required and can't be unset", key));
+        }
+    }
+
+    let entries = resolve_targets(targets, "Change", yes);
+    update_keyfiles(context, entries, |entry, file| {
+        for key in &keys {
+            if !file.unset(DESKTOP_ENTRY_GROUP, key) {
+                println!("{} wasn't set for \"{}\"", key, entry.get_name());
+            }
+        }
+    });
+}
+
+
+/// Applies `change` to each entry's keyfile and writes it back, carrying on past failures
+fn update_keyfiles<F: Fn(&DesktopEntry, &mut keyfile::KeyFile)>(context: &Context, entries: Vec<DesktopEntry>, change: F) {
+    let mut any_failed = false;
+
+    for entry in entries {
+        let mut file = match entry.keyfile(context) {
+            Ok(f) => f,
+            Err(error) => {
+                println!("Failed to read {:?} - {}", entry.filepath(context), error);
+                any_failed = true;
+                continue;
+            }
+        };
+        change(&entry, &mut file);
+
+        match entry.write_keyfile(context, &file) {
+            Ok(new_entry) => for problem in new_entry.validate(context) {
+                println!("Warning ({}): {}", new_entry.get_name(), problem);
+            },
+            Err(error) => {
+                println!("Failed to update entry \"{}\" - {}", entry.get_name(), error);
+                any_failed = true;
+            }
+        }
+    }
+
+    if any_failed { process::exit(15) }
+}
+
+
+/// Turns Targets into entries. Batches are listed and need confirming unless -y was passed;
+/// an empty result means there's nothing to do.
+fn resolve_targets(targets: Targets, action: &str, yes: bool) -> Vec<DesktopEntry> {
+    let entries = match targets {
+        Targets::One(entry_result) => match valid_entry_or_none(entry_result) {
+            Some(entry) => return vec![entry],
+            None => error_out("Please specify an entry, either by index or by name")
+        },
+        Targets::Many(Ok(entries)) => entries,
+        Targets::Many(Err(e)) => {
+            println!("Failed to read desktop files: {}", e);
+            process::exit(20);
+        }
+    };
+
+    if entries.is_empty() {
+        println!("No entries matched");
+        return entries;
+    }
+
+    for entry in &entries {
+        println!("  {} ({})", entry.get_name(), entry.filename());
+    }
+    if yes || confirm(&format!("{} these {} entries?", action, entries.len())) {
+        entries
+    }
+    else {
+        println!("Nothing was changed");
+        Vec::new()
+    }
+}
+
+
+/// `mkdesktop doctor [--fix]` -- reports what's wrong with every entry, and repairs it with --fix
+pub fn doctor(context: &Context, fix: bool, yes: bool) {
+    let findings = match doctor::examine(context) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Failed to read desktop files: {}", e);
+            process::exit(20);
+        }
+    };
+
+    if findings.is_empty() {
+        println!("Everything looks good");
+        return;
+    }
+
+    let mut unresolved = 0;
+    let mut last_file = None;
+    for finding in &findings {
+        if last_file != Some(&finding.file) {
+            println!("{} ({})", finding.name, finding.file.display());
+            last_file = Some(&finding.file);
+        }
+        match &finding.fix {
+            Some(f) => println!("\t{} (fix: {})", finding.problem, f.describe()),
+            None    => println!("\t{}", finding.problem)
+        }
+
+        // A fix can make later ones moot (there's nothing left to clear once the entry is removed)
+        if !fix || finding.fix.is_none() || !finding.file.exists() {
+            if finding.file.exists() { unresolved += 1 }
+            continue;
+        }
+        if !yes && !confirm(&format!("\t{}?", capitalize_first(&finding.fix.as_ref().unwrap().describe()))) {
+            unresolved += 1;
+            continue;
+        }
+
+        match doctor::apply(context, finding) {
+            Ok(()) => println!("\tFixed"),
+            Err(error) => {
+                println!("\tCouldn't fix it - {}", error);
+                unresolved += 1;
+            }
+        }
+    }
+
+    if fix { clean_up_icons(context) }
+    if unresolved > 0 {
+        if !fix { println!("Run `mkdesktop doctor --fix` to repair these") }
+        process::exit(13);
+    }
+}
+
+
+/// `mkdesktop reconcile` -- syncs mkdesktop's own files with what's registered in the applications menu
+pub fn reconcile(context: &Context, restore: bool, yes: bool) {
+    let orphans = match reconcile::find_orphans(context) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Failed to read desktop files: {}", e);
+            process::exit(20);
+        }
+    };
+
+    if orphans.is_empty() {
+        println!("Everything is in sync");
+        return;
+    }
+
+    for orphan in &orphans {
+        println!("  {}", orphan.describe());
+    }
+    let question = if restore { "Register the missing entries and restore the unbacked ones?" }
+                   else       { "Register the missing entries and remove the unbacked ones from the menu?" };
+    if !yes && !confirm(question) {
+        println!("Nothing was changed");
+        return;
+    }
+
+    let mut any_failed = false;
+    for orphan in &orphans {
+        if let Err(error) = reconcile::repair(context, orphan, restore) {
+            println!("Couldn't repair {} - {}", orphan.path().display(), error);
+            any_failed = true;
+        }
+    }
+
+    clean_up_icons(context);
+    if any_failed { process::exit(12) }
+}
+
+
+/// `mkdesktop webapp <URL>` -- an entry that opens the page in a browser window of its own
+pub fn webapp(context: &Context, url: &str, arg_matches: &clap::ArgMatches) {
+    let yes = arg_matches.is_present("yes");
+
+    let browser = match arg_matches.value_of("browser") {
+        Some(program) => match Browser::from_program(program) {
+            Some(browser) => browser,
+            None => error_out(&format!("Don't know how to open app windows with {} (use a Chromium-based browser or Firefox)", program))
+        },
+        None => match Browser::detect() {
+            Some(browser) => browser,
+            None => error_out("Couldn't find a Chromium-based browser or Firefox on $PATH (see --browser)")
+        }
+    };
+
+    let name = match arg_matches.value_of("name") {
+        Some(arg) => String::from(arg),
+        None      => ask_stdin_for_str("Please enter a name for the web app (required)", None, yes)
+    };
+    if name.is_empty() {
+        error_out("A name is required");
+    }
+
+    let app = WebApp {
+        name: name,
+        url: webapp::normalize_url(url),
+        browser: browser,
+        kiosk: arg_matches.is_present("kiosk"),
+    };
+
+    let categories = match checked_categories(arg_matches.value_of("categories").unwrap_or("Network")) {
+        Ok(c)         => c,
+        Err(problems) => error_out(&problems)
+    };
+
+    // Without an icon of its own the app gets the browser's
+    let icon = match arg_matches.value_of("icon") {
+        Some(arg) => icon_from_arg(arg),
+        None => {
+            let browser_icon = Path::new(&app.browser.program).file_name().map(|n| n.to_string_lossy().to_string());
+            ask_stdin_for_str("Please enter the path to an icon", browser_icon, yes)
+        }
+    };
+
+    let mut entry = app.to_entry(context, arg_matches.value_of("comment").unwrap_or_default(), &icon, &categories);
+    if entry.filepath(context).exists() {
+        error_out(&format!("There's already an entry called \"{}\" (see `mkdesktop edit`)", app.name));
+    }
+
+    if let Err(error) = app.prepare_profile(context) {
+        error_out(&format!("Failed to create browser profile in {} - {}", app.profile_dir(context).display(), error));
+    }
+    match entry.adopt_icon(context) {
+        Ok(()) => {}
+        Err(error) => error_out(&format!("Failed to install icon {} - {}", &icon, error.description()))
+    }
+    match entry.write_to_apps_dir(context) {
+        Ok(()) => println!("Created \"{}\", opening {} with {}", app.name, app.url, app.browser.program),
+        Err(error) => error_out(error.description())
+    }
+    for problem in entry.validate(context) {
+        println!("Warning: {}", problem);
+    }
+}
+
+
+/// `mkdesktop autostart enable <ENTRY>`
+pub fn autostart_enable(context: &Context, selector: &str, delay: Option<&str>) {
+    let delay = match delay {
+        Some(seconds) => match seconds.parse::<u32>() {
+            Ok(s)  => Some(s),
+            Err(_) => error_out(&format!("\"{}\" isn't a number of seconds", seconds))
+        },
+        None => None
+    };
+
+    let (id, source) = autostart_target(context, selector);
+    match autostart::enable(context, &id, &source, delay) {
+        Ok(()) => println!("{} will start at login", id),
+        Err(error) => error_out(&format!("Failed to enable {} - {}", id, error))
+    }
+}
+
+
+/// `mkdesktop autostart disable <ENTRY>`
+pub fn autostart_disable(context: &Context, selector: &str) {
+    let (id, _) = autostart_target(context, selector);
+    match autostart::disable(context, &id) {
+        Ok(()) => println!("{} won't start at login", id),
+        Err(error) => error_out(&format!("Failed to disable {} - {}", id, error))
+    }
+}
+
+
+/// `mkdesktop autostart list`
+pub fn autostart_list(context: &Context) {
+    let ids = autostart::desktop_ids(context);
+    if ids.is_empty() { println!("Nothing starts at login") }
+
+    for id in ids {
+        let path = autostart::autostart_path(context, &id);
+        let file = if path.exists() { Some(path) } else { autostart::system_file(context, &id) };
+        let name = file.and_then(|f| keyfile::KeyFile::open(&f).ok())
+            .and_then(|k| k.get_string(DESKTOP_ENTRY_GROUP, "Name"))
+            .unwrap_or_else(|| id.clone());
+
+        let state = match (autostart::is_enabled(context, &id), autostart::delay(context, &id)) {
+            (false, _)            => String::from("disabled"),
+            (true, Some(seconds)) => format!("starts {}s after login", seconds),
+            (true, None)          => String::from("starts at login"),
+        };
+        println!("{} ({}) - {}", name, id, state);
+    }
+}
+
+
+/// Our entries by index or name, or anything already in an autostart folder by desktop file ID (with or without .desktop).
+/// Comes with the file an autostart copy would be made from.
+fn autostart_target(context: &Context, selector: &str) -> (String, PathBuf) {
+    let error = match desktop::select(context, selector) {
+        Ok(ref entry) if entry.get_type() != EntryType::Application => error_out("Only applications can start at login"),
+        Ok(entry) => return (entry.filename(), entry.filepath(context)),
+        Err(error) => error
+    };
+
+    let id = if selector.ends_with(".desktop") { selector.to_string() } else { format!("{}.desktop", selector) };
+    let user_file = autostart::autostart_path(context, &id);
+    match autostart::system_file(context, &id) {
+        Some(path) => (id, path),
+        None if user_file.exists() => (id, user_file),
+        None => error_out(error.description())
+    }
+}
+
+
+/// `mkdesktop default <ENTRY> <MIME>...` -- makes the entry the default for the types in mimeapps.list,
+/// adding them to its MimeType= first if they aren't there. With --remove the entry stops being offered for them.
+/// Without any types, lists what the entry opens.
+pub fn default_app(context: &Context, entry_result: io::Result<DesktopEntry>, inputs: Vec<&str>, remove: bool) {
+    let entry = match entry_result {
+        Ok(ref e) if e.get_type() != EntryType::Application => error_out("Only applications can open files"),
+        Ok(e) => e,
+        Err(error) => error_out(error.description())
+    };
+    if inputs.is_empty() { return print_mime_types(context, &entry) }
+
+    let mime_types: Vec<String> = inputs.iter().map(|input| checked_mime_type(context, input)).collect();
+
+    if remove {
+        let mut list = open_mimeapps(context);
+        for mime_type in &mime_types {
+            list.remove_association(mime_type, &entry.filename());
+            println!("\"{}\" won't be offered for {}", entry.get_name(), mime_type);
+        }
+        save_mimeapps(context, &list);
+    }
+    else {
+        make_default(context, &entry, None, &mime_types);
+    }
+}
+
+
+/// Makes the entry the default for the types. A default only counts if the entry says it can open the type,
+/// so they're added to its MimeType= first, along with a new Exec if one is given.
+fn make_default(context: &Context, entry: &DesktopEntry, exec: Option<&Exec>, mime_types: &[String]) {
+    let mut declared = mime::split(entry.get_mime_types());
+    let missing: Vec<String> = mime_types.iter().filter(|t| !declared.contains(t)).cloned().collect();
+
+    if !missing.is_empty() || exec.is_some() {
+        let mut file = match entry.keyfile(context) {
+            Ok(f) => f,
+            Err(error) => error_out(&format!("Failed to read {:?} - {}", entry.filepath(context), error))
+        };
+        if let Some(exec) = exec { file.set(DESKTOP_ENTRY_GROUP, "Exec", &exec.to_string()) }
+        if !missing.is_empty() {
+            declared.extend(missing);
+            file.set(DESKTOP_ENTRY_GROUP, "MimeType", &mime::join(&declared));
+        }
+        if let Err(error) = entry.write_keyfile(context, &file) {
+            println!("Failed to update entry \"{}\" - {}", entry.get_name(), error);
+            process::exit(15);
+        }
+    }
+
+    let mut list = open_mimeapps(context);
+    for mime_type in mime_types {
+        list.set_default(mime_type, &entry.filename());
+        println!("\"{}\" now opens {}", entry.get_name(), mime_type);
+    }
+    save_mimeapps(context, &list);
+}
+
+
+fn open_mimeapps(context: &Context) -> MimeApps {
+    match MimeApps::open(context) {
+        Ok(list) => list,
+        Err(error) => error_out(&format!("Failed to read mimeapps.list - {}", error))
+    }
+}
+
+fn save_mimeapps(context: &Context, list: &MimeApps) {
+    if let Err(error) = list.save(context) {
+        println!("Failed to save mimeapps.list - {}", error);
+        process::exit(15);
+    }
+}
+
+
+fn print_mime_types(context: &Context, entry: &DesktopEntry) {
+    let mime_types = mime::split(entry.get_mime_types());
+    if mime_types.is_empty() { println!("\"{}\" doesn't open any file types", entry.get_name()) }
+
+    let list = MimeApps::open(context).ok();
+    for mime_type in mime_types {
+        let is_default = list.as_ref().and_then(|l| l.default_for(&mime_type)) == Some(entry.filename());
+        if is_default { println!("{} (default)", mime_type) }
+        else          { println!("{}", mime_type) }
+    }
+}
+
+
+/// `mkdesktop scheme register <SCHEME> <ENTRY>` -- makes the entry open scheme:// links
+pub fn scheme_register(context: &Context, scheme: &str, entry_result: io::Result<DesktopEntry>) {
+    let entry = match entry_result {
+        Ok(ref e) if e.get_type() != EntryType::Application => error_out("Only applications can open links"),
+        Ok(e) => e,
+        Err(error) => error_out(error.description())
+    };
+
+    // Be forgiving about "ourapp:" and "ourapp://"
+    let scheme = scheme.trim_end_matches('/').trim_end_matches(':');
+    if !mime::is_valid_scheme(scheme) { error_out(&format!("\"{}\" isn't a valid URL scheme", scheme)) }
+
+    // The link has to reach the program, which only happens through %u/%U
+    let mut exec = entry.get_exec_command().clone();
+    let changed = exec.ensure_takes_urls();
+    if changed { println!("Changed the command to {}", exec) }
+
+    make_default(context, &entry, if changed { Some(&exec) } else { None }, &[mime::scheme_mime_type(scheme)]);
+}
+
+
+/// `mkdesktop scheme test <URL>` -- which launcher opens the URL and what it would run. Exits with 16 if there isn't one.
+pub fn scheme_test(context: &Context, url: &str) {
+    let scheme = match mime::url_scheme(url) {
+        Some(s) => s,
+        None => error_out(&format!("\"{}\" doesn't start with a scheme like ourapp:", url))
+    };
+
+    // file: URLs go by the type of the file rather than the scheme
+    let mime_type = if scheme == "file" {
+        match mime::mime_types_for_filename(context, url).into_iter().next() {
+            Some(t) => t,
+            None => error_out(&format!("Couldn't find a MIME type for {}", url))
+        }
+    }
+    else {
+        mime::scheme_mime_type(&scheme)
+    };
+
+    let (id, path) = match mime::find_handler(context, &mime_type, &mime::current_desktops()) {
+        Some(handler) => handler,
+        None => {
+            println!("Nothing opens {}", mime_type);
+            process::exit(16);
+        }
+    };
+
+    let entry = match keyfile::KeyFile::open(&path) {
+        Ok(file) => DesktopEntry::from_keyfile(&file),
+        Err(error) => error_out(&format!("Failed to read {:?} - {}", path, error))
+    };
+
+    let exec = entry.get_exec_command();
+    let command = Exec::new(exec.program(), exec.open_args(url));
+    println!("{} ({}) opens {}", entry.get_name(), id, mime_type);
+    println!("\trunning {}", command);
+}
+
 
-            // An entry couldn't be parsed from what was given -- it must be a filename for a new entry
-            Err(_) => match arg_matches.value_of("FILE_OR_ENTRY") {
-                Some(f) => (f.to_string(), None),
-                None    => error_out("Please specify a file or an entry (see --help)")
-            }
-        }
+/// `mkdesktop scheme list` -- the schemes the user has picked a handler for
+pub fn scheme_list(context: &Context) {
+    let list = open_mimeapps(context);
+    let schemes: Vec<String> = list.mime_types(mime::DEFAULT_APPLICATIONS).into_iter()
+        .filter(|t| t.starts_with("x-scheme-handler/"))
+        .collect();
+    if schemes.is_empty() { println!("No link handlers have been set") }
+
+    for mime_type in schemes {
+        let handlers = list.get(mime::DEFAULT_APPLICATIONS, &mime_type).join(", ");
+        println!("{}: {}", mime_type.trim_start_matches("x-scheme-handler/"), handlers);
+    }
+}
 
-        // No file parameter was specified
-        None => error_out("Please specify a file or an entry (see --help)")
-    };
 
-    // TODO this breaks when loading up an existing entry
-    let exec_path = match PathAbs::new(&filename).expect("Couldn't get file path").absolute() {
-        Ok(f)  => f,
-        Err(e) => error_out(&format!("Failed to open {} - {}", &filename, e))
+/// `mkdesktop shortcut set <ENTRY> <KEYS>` -- a global keyboard shortcut that launches the entry, registered
+/// with GNOME (when its settings daemon's schemas are installed) and KDE (when it's the desktop or has been).
+/// Exits with 17 if something else already has the keys, unless forced.
+pub fn shortcut_set(context: &Context, entry_result: io::Result<DesktopEntry>, keys: &str, force: bool) {
+    let entry = match entry_result {
+        Ok(ref e) if e.get_type() == EntryType::Directory => error_out("Menu folders can't have shortcuts"),
+        Ok(e) => e,
+        Err(error) => error_out(error.description())
+    };
+    let accelerator = match Accelerator::parse(keys) {
+        Some(a) => a,
+        None => error_out(&format!("\"{}\" isn't a key combination like Ctrl+Alt+T or <Super>t", keys))
     };
 
-    let exec = String::from(exec_path.as_path().to_str().expect("Failed to turn exec path into string"));
+    let id = entry.filename();
+    let gnome = Gnome::open(context);
+    let mut kde = if Kde::in_use(context) { Some(open_kde(context)) } else { None };
+    if gnome.is_none() && kde.is_none() {
+        error_out("Neither GNOME's nor KDE's shortcut settings were found");
+    }
 
-    let name = match arg_matches.value_of("name") {
-        Some(arg) => String::from(arg),
-        None      => ask_stdin_for_str("Please enter a name for the desktop entry (required)", optional_entry_field!(entry, get_name), yes)
-    };
-    if name.is_empty() {
-        println!("A name is required");
-        process::exit(1);
+    if !force {
+        let taken_by = gnome.as_ref().and_then(|g| g.taken_by(&accelerator, &id))
+            .or_else(|| kde.as_ref().and_then(|k| k.taken_by(&accelerator, &id)))
+            .or_else(|| shortcut::khotkeys_taken_by(context, &accelerator));
+        if let Some(other) = taken_by {
+            eprintln!("{} is already used by \"{}\" (--force to take it anyway)", accelerator, other);
+            process::exit(17);
+        }
     }
 
-    let comment = match arg_matches.value_of("comment") {
-        Some(arg) => String::from(arg),
-        None      => ask_stdin_for_str("Please enter a tooltip for the desktop entry", optional_entry_field!(entry, get_comment), yes),
+    if let Some(ref gnome) = gnome {
+        if let Err(error) = gnome.set(&id, entry.get_name(), &accelerator) {
+            error_out(&format!("Failed to set the GNOME shortcut - {}", error));
+        }
+    }
+    if let Some(ref mut kde) = kde {
+        kde.set(&id, entry.get_name(), &accelerator);
+        save_kde(kde);
+    }
+    println!("{} launches \"{}\"", accelerator, entry.get_name());
+}
+
+
+/// `mkdesktop shortcut remove <ENTRY>`
+pub fn shortcut_remove(context: &Context, entry_result: io::Result<DesktopEntry>) {
+    let entry = match entry_result {
+        Ok(e) => e,
+        Err(error) => error_out(error.description())
     };
 
-    let categories = match arg_matches.value_of("categories") {
-        Some(arg) => String::from(arg),
-        None      => ask_stdin_for_str("Please enter semicolon-separated categories", optional_entry_field!(entry, get_categories), yes),
+    if let Err(error) = shortcut::forget_entry(context, &entry.filename()) {
+        error_out(&format!("Failed to remove the shortcut for \"{}\" - {}", entry.get_name(), error));
+    }
+    println!("\"{}\" has no shortcut anymore", entry.get_name());
+}
+
+
+/// `mkdesktop shortcut list` -- our entries that have a shortcut, and what it is on each desktop
+pub fn shortcut_list(context: &Context) {
+    let gnome = Gnome::open(context);
+    let kde = open_kde(context);
+
+    let entries = match desktop::read_desktop_files(context) {
+        Ok(entries) => entries,
+        Err(error) => {
+            eprintln!("Failed to read desktop files - {}", error);
+            process::exit(20);
+        }
     };
+    let mut any = false;
+    for entry in entries {
+        let id = entry.filename();
+        let mut keys = Vec::new();
+        if let Some(a) = gnome.as_ref().and_then(|g| g.get(&id)) { keys.push(format!("{} (GNOME)", a)) }
+        if let Some(a) = kde.get(&id) { keys.push(format!("{} (KDE)", a)) }
+        if keys.is_empty() { continue }
 
-    let path = match arg_matches.value_of("path") {
-        Some(arg) => String::from(arg),
-        None      => ask_stdin_for_str("Please enter the working directory for the binary", optional_entry_field!(entry, get_path, pwd()), yes),
+        any = true;
+        println!("{} ({}) - {}", entry.get_name(), id, keys.join(", "));
+    }
+    if !any { println!("No entries have shortcuts") }
+}
+
+
+fn open_kde(context: &Context) -> Kde {
+    match Kde::open(context) {
+        Ok(kde) => kde,
+        Err(error) => error_out(&format!("Failed to read kglobalshortcutsrc - {}", error))
+    }
+}
+
+fn save_kde(kde: &Kde) {
+    if let Err(error) = kde.save() {
+        error_out(&format!("Failed to save kglobalshortcutsrc - {}", error));
+    }
+}
+
+
+/// `mkdesktop flatpak list`
+pub fn flatpak_list(context: &Context) {
+    let apps = packages::FlatpakApp::installed(context);
+    if apps.is_empty() {
+        println!("No Flatpak apps are installed");
+        return;
+    }
+    for app in apps {
+        let forked = if overrides::source(context, &format!("{}.desktop", app.id)).is_some() { ", forked" } else { "" };
+        println!("{} ({}, {}{})", app.id, app.branch, if app.user { "user" } else { "system" }, forked);
+    }
+}
+
+
+/// `mkdesktop flatpak fork <APP_ID>` -- with --reset, the override is removed instead
+pub fn flatpak_fork(context: &Context, id: &str, args: Vec<&str>, env: Vec<&str>, reset: bool) {
+    let desktop_id = format!("{}.desktop", id);
+    if reset {
+        match overrides::remove(context, &desktop_id) {
+            Ok(true)   => println!("Removed the override, {} is back to its own launcher", id),
+            Ok(false)  => println!("{} isn't forked", id),
+            Err(error) => error_out(&format!("Failed to remove {:?} - {}", overrides::override_path(context, &desktop_id), error))
+        }
+        return;
+    }
+
+    let app = match packages::FlatpakApp::find(context, id) {
+        Some(app) => app,
+        None => error_out(&format!("No Flatpak app {} is installed", id))
     };
+    let mut variables = Vec::new();
+    for value in env {
+        match exec::parse_env_var(value) {
+            Some(variable) => variables.push(variable),
+            None => error_out(&format!("\"{}\" isn't of the form KEY=VALUE", value))
+        }
+    }
+    let args: Vec<String> = args.into_iter().map(String::from).collect();
 
-    let icon = match arg_matches.value_of("icon") {
-        Some(arg) => match PathAbs::new(arg).expect("Couldn't get icon path").absolute() {
-            Ok(f)  => String::from(f.as_path().to_str().expect("Failed to turn icon path into string")),
-            Err(e) => error_out(&format!("Failed to open {} - {}", arg, e))
+    match app.fork(context, &args, &variables) {
+        Ok(path) => println!("Forked {} into {:?}, which now shadows the original", id, path),
+        Err(error) => error_out(&format!("Failed to fork {} - {}", id, error))
+    }
+}
+
+
+/// `mkdesktop override <DESKTOP_ID>` -- with --reset, the override is removed instead.
+/// The ID may leave out .desktop.
+pub fn override_launcher(context: &Context, id: &str, args: Vec<&str>, env: Vec<&str>, assignments: Vec<&str>, reset: bool) {
+    let desktop_id = if id.ends_with(".desktop") { id.to_string() } else { format!("{}.desktop", id) };
+    if reset {
+        match overrides::remove(context, &desktop_id) {
+            Ok(true)   => println!("Removed the override, {} is back to the original", desktop_id),
+            Ok(false)  => println!("{} isn't overridden", desktop_id),
+            Err(error) => error_out(&format!("Failed to remove {:?} - {}", overrides::override_path(context, &desktop_id), error))
         }
-        None => ask_stdin_for_str("Please enter the path to an icon", optional_entry_field!(entry, get_icon), yes),
+        return;
+    }
+
+    let source = match overrides::system_file(context, &desktop_id) {
+        Some(path) => path,
+        None => error_out(&format!("No launcher with the ID {} is installed for the whole system", desktop_id))
     };
+    let mut variables = Vec::new();
+    for value in env {
+        match exec::parse_env_var(value) {
+            Some(variable) => variables.push(variable),
+            None => error_out(&format!("\"{}\" isn't of the form KEY=VALUE", value))
+        }
+    }
+    let mut parsed = Vec::new();
+    for assignment in assignments {
+        match keyfile::parse_assignment(assignment) {
+            Ok(key_value) => parsed.push(key_value),
+            Err(problem)  => error_out(&problem)
+        }
+    }
+    let args: Vec<String> = args.into_iter().map(String::from).collect();
 
-    // Prepare new entry
-    let new_entry = DesktopEntry::new(
-        &name,
-        &comment,
-        &path,
-        &exec,
-        &icon,
-        &categories,
-    );
+    let written = overrides::write(context, &desktop_id, &source, |file| {
+        overrides::customize_commands(file, &args, &variables);
+        for (key, value) in &parsed {
+            file.set(DESKTOP_ENTRY_GROUP, key, value);
+        }
+    });
+    match written {
+        Ok(path) => println!("Copied {:?} into {:?}, which now shadows it", source, path),
+        Err(error) => error_out(&format!("Failed to override {} - {}", desktop_id, error))
+    }
+}
 
-    // Write to disk
-    match new_entry.write_to_apps_dir() {
-        Ok(()) => {}
-        Err(error) => error_out(error.description())
+
+/// `mkdesktop override` with no ID
+pub fn override_list(context: &Context) {
+    let overridden = overrides::list(context);
+    if overridden.is_empty() { println!("No launchers are overridden") }
+
+    for (id, source) in overridden {
+        let state = match overrides::upstream(context, &id) {
+            Some(overrides::Upstream::Changed) => " (changed since)",
+            Some(overrides::Upstream::Removed) => " (not installed anymore)",
+            _ => ""
+        };
+        println!("{} - copied from {:?}{}", id, source, state);
     }
+}
 
-    // Delete old entry file if name was changed
-    match entry {
-        Some(old_entry) => if old_entry.filename() != new_entry.filename() {
-            match old_entry.delete() {
-                Ok(()) => {}
-                Err(error) => error_out(&format!("Failed to delete old entry ({}) you probably have a duplicate now", error.description()))
+
+/// `mkdesktop menu create <MENU>`
+pub fn menu_create(context: &Context, name: &str, comment: Option<&str>, icon: Option<&str>) {
+    if name.trim().is_empty() { error_out("Please give the menu a name") }
+
+    match menu::create(context, name.trim(), comment.unwrap_or_default(), icon.unwrap_or_default()) {
+        Ok(created) => println!("Created menu \"{}\" ({})", created.get_name(), created.filepath(context).display()),
+        Err(error)  => error_out(&format!("Failed to create menu - {}", error))
+    }
+}
+
+
+/// `mkdesktop menu add <MENU> <ENTRY>...`
+pub fn menu_add(context: &Context, menu_name: &str, selectors: Vec<&str>) {
+    let mut target = select_menu(context, menu_name);
+
+    for selector in selectors {
+        let entry = match desktop::select(context, selector) {
+            Ok(e) => e,
+            Err(error) => error_out(error.description())
+        };
+        if entry.get_type() == EntryType::Directory {
+            error_out(&format!("\"{}\" is a menu folder, not something to put in a menu", entry.get_name()));
+        }
+
+        if target.add(&entry.filename()) { println!("Added \"{}\" to {}", entry.get_name(), target.get_name()) }
+        else                             { println!("\"{}\" is already in {}", entry.get_name(), target.get_name()) }
+    }
+
+    if let Err(error) = target.save(context) {
+        println!("Failed to save menu \"{}\" - {}", target.get_name(), error);
+        process::exit(15);
+    }
+}
+
+
+/// `mkdesktop menu remove <MENU> [ENTRY]...` -- takes entries out of the menu, or removes the whole menu when none are given
+pub fn menu_remove(context: &Context, menu_name: &str, selectors: Vec<&str>, yes: bool) {
+    let mut target = select_menu(context, menu_name);
+
+    if selectors.is_empty() {
+        if !yes && !confirm(&format!("Remove the menu \"{}\"? Its entries are kept.", target.get_name())) {
+            println!("Nothing was changed");
+            return;
+        }
+        if let Err(error) = target.delete(context) {
+            println!("Failed to remove menu \"{}\" - {}", target.get_name(), error);
+            process::exit(12);
+        }
+        return;
+    }
+
+    for selector in selectors {
+        // Entries that are already gone can still be taken out by their desktop file ID
+        let id = match desktop::select(context, selector) {
+            Ok(entry) => entry.filename(),
+            Err(_) if target.contains(selector) => selector.to_string(),
+            Err(error) => error_out(error.description())
+        };
+
+        if !target.remove(&id) { println!("{} isn't in {}", id, target.get_name()) }
+    }
+
+    if let Err(error) = target.save(context) {
+        println!("Failed to save menu \"{}\" - {}", target.get_name(), error);
+        process::exit(15);
+    }
+}
+
+
+/// `mkdesktop menu list` -- every menu with the entries in it
+pub fn menu_list(context: &Context) {
+    let menus = match menu::read_menus(context) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Failed to read menus: {}", e);
+            process::exit(20);
+        }
+    };
+    let entries = desktop::read_desktop_files(context).unwrap_or_default();
+
+    if menus.is_empty() {
+        println!("No menus yet (see `mkdesktop menu create`)");
+    }
+    for m in menus {
+        println!("{} ({})", m.get_name(), m.filename());
+        for id in m.get_entries() {
+            match entries.iter().find(|e| &e.filename() == id) {
+                Some(entry) => println!("\t{} ({})", entry.get_name(), id),
+                None        => println!("\t{} (missing)", id)
             }
-        },
-        None => {}
+        }
+    }
+}
+
+
+fn select_menu(context: &Context, name: &str) -> menu::Menu {
+    match menu::select(context, name) {
+        Ok(m) => m,
+        Err(error) => error_out(&error.to_string())
+    }
+}
+
+
+fn capitalize_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None        => String::new()
     }
 }
 
 
-pub fn status(entry_result: Option<io::Result<DesktopEntry>>) {
+pub fn status(context: &Context, entry_result: Option<io::Result<DesktopEntry>>) {
     match valid_entry_or_none(entry_result) {
         //
         // When an entry is selected,
@@ -122,7 +1147,13 @@ pub fn status(entry_result: Option<io::Result<DesktopEntry>>) {
         //
         Some(entry) => {
             let mut stdout = io::stdout();
-            println!("# {:?}", entry.filepath());
+            println!("# {:?}", entry.filepath(context));
+            if let Some((kind, id)) = packages::wrapped_by(entry.get_exec_command()) {
+                match Package::find_installed(context, kind, &id) {
+                    Some(package) => println!("# {}", package.describe()),
+                    None          => println!("# {} {} isn't installed", kind.describe(), id)
+                }
+            }
             match entry.write(&mut stdout) {
                 Ok(()) => {}
                 Err(error) => {
@@ -136,7 +1167,7 @@ pub fn status(entry_result: Option<io::Result<DesktopEntry>>) {
         //   print an overview of every desktop entry managed by mkdesktop
         //
         None => {
-            let desktop_files = match desktop::read_desktop_files() {
+            let desktop_files = match desktop::read_desktop_files(context) {
                 Ok(x) => x,
                 Err(e) => {
                     println!("Failed to read desktop files: {}", e);
@@ -154,17 +1185,325 @@ pub fn status(entry_result: Option<io::Result<DesktopEntry>>) {
 }
 
 
-pub fn remove(entry_result: Option<io::Result<DesktopEntry>>) {
-    match valid_entry_or_none(entry_result) {
-        Some(entry) => match entry.delete() {
+pub fn remove(context: &Context, targets: Targets, yes: bool) {
+    let entries = resolve_targets(targets, "Remove", yes);
+    if entries.is_empty() { return }
+
+    let mut any_failed = false;
+    for entry in entries {
+        match entry.delete(context) {
             Ok(()) => {}
             Err(error) => {
-                println!("Failed to delete entry \"{}\" - {}", entry.get_name(), error.description());
-                process::exit(12);
+                println!("Failed to delete entry \"{}\" - {}", entry.get_name(), error);
+                any_failed = true;
             }
         }
-        None => error_out("Please specify an entry, either by index or by name")
     }
+
+    clean_up_icons(context);
+    if any_failed { process::exit(12) }
+}
+
+
+/// `mkdesktop scan <DIR>` -- one new entry per executable in the folder that doesn't have one yet
+pub fn scan(context: &Context, dir: &str, yes: bool) {
+    let candidates = match scan::scan(context, Path::new(dir)) {
+        Ok(x) => x,
+        Err(error) => error_out(&format!("Failed to scan {} - {}", dir, error))
+    };
+
+    if candidates.is_empty() {
+        println!("No new executables in {}", dir);
+        return;
+    }
+
+    for candidate in &candidates {
+        if candidate.icon.is_empty() { println!("  {} ({:?})", candidate.name, candidate.executable) }
+        else                         { println!("  {} ({:?}, icon {})", candidate.name, candidate.executable, candidate.icon) }
+    }
+    if !yes && !confirm(&format!("Create these {} entries?", candidates.len())) {
+        println!("Nothing was created");
+        return;
+    }
+
+    let mut any_failed = false;
+    for mut candidate in candidates {
+        let program = candidate.executable.to_string_lossy().to_string();
+        let folder = candidate.executable.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+
+        let mut entry = DesktopEntry::new(&candidate.name, "", &folder, Exec::new(&program, Vec::new()), "", "");
+        if entry.filepath(context).exists() {
+            println!("Skipping {} - there's already an entry called \"{}\"", program, candidate.name);
+            continue;
+        }
+        candidate.find_icon(context);
+        entry.set_icon(&candidate.icon);
+
+        if let Err(error) = entry.adopt_icon(context) {
+            println!("Couldn't install icon for \"{}\" - {}", candidate.name, error);
+        }
+
+        match entry.write_to_apps_dir(context) {
+            Ok(()) => println!("Created \"{}\"", candidate.name),
+            Err(error) => {
+                println!("Failed to create entry \"{}\" - {}", candidate.name, error);
+                any_failed = true;
+            }
+        }
+    }
+
+    clean_up_icons(context);
+    if any_failed { process::exit(11) }
+}
+
+
+fn confirm(question: &str) -> bool {
+    let answer = ask_stdin_for_str(&format!("{} (y/n)", question), None, false);
+    answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes")
+}
+
+
+/// Works out the command from --exec, <FILE>, --arg and anything after `--`.
+/// --exec/<FILE> replace the program (and --exec can carry arguments of its own); --arg and `--`
+/// arguments are added after those, or replace the existing entry's arguments if neither was given.
+/// Only a program that came from the command line gets made absolute -- an existing entry's Exec is left as it is.
+fn exec_from_args(existing: Option<&Exec>, target: Option<&str>, arg_matches: &clap::ArgMatches) -> Exec {
+    let mut extra_args: Vec<String> = Vec::new();
+    for name in &["arg", "ARGS"] {
+        if let Some(values) = arg_matches.values_of(name) {
+            extra_args.extend(values.map(String::from));
+        }
+    }
+
+    let given = match (arg_matches.value_of("exec"), target) {
+        (Some(command), _) => Exec::parse(command),
+        (None, Some(file)) => Exec::new(file, Vec::new()),
+        (None, None)       => {
+            let mut exec = match existing {
+                Some(existing) => existing.clone(),
+                None           => error_out("Please specify a file or an entry (see --help)")
+            };
+            if !extra_args.is_empty() { exec.set_args(extra_args) }
+            return exec;
+        }
+    };
+    if given.is_empty() { error_out("--exec needs a program") }
+
+    let mut args = given.args().to_vec();
+    args.extend(extra_args);
+    let mut exec = Exec::new(&absolute_program(given.program()), args);
+
+    // A new command keeps the entry's environment unless it brought its own `env` prefix
+    exec.set_env(match existing {
+        Some(existing) if given.env().is_empty() => existing.env().clone(),
+        _ => given.env().clone()
+    });
+    exec
+}
+
+
+/// --type, or --url implying a link, or whatever the entry already is
+fn entry_type_from_args(existing: Option<&DesktopEntry>, arg_matches: &clap::ArgMatches) -> EntryType {
+    match arg_matches.value_of("type") {
+        Some(name) => match EntryType::parse(name) {
+            Some(t) => t,
+            None    => error_out(&format!("Unknown type \"{}\" (try application, link or directory)", name))
+        },
+        None if arg_matches.is_present("url") => EntryType::Link,
+        None => existing.map(DesktopEntry::get_type).unwrap_or(EntryType::Application)
+    }
+}
+
+
+/// --env KEY=VALUE sets a variable, --env KEY (no =) removes it
+fn apply_env_args(exec: &mut Exec, arg_matches: &clap::ArgMatches) {
+    let values = match arg_matches.values_of("env") {
+        Some(values) => values,
+        None         => return
+    };
+
+    for value in values {
+        if !value.contains('=') {
+            if !exec.remove_env_var(value) { println!("{} wasn't set", value) }
+            continue;
+        }
+
+        match exec::parse_env_var(value) {
+            Some((key, val)) => exec.set_env_var(&key, &val),
+            None => error_out(&format!("\"{}\" isn't of the form KEY=VALUE", value))
+        }
+    }
+}
+
+
+/// --mime values as a MimeType= list, or None if none were given. File extensions are looked up,
+/// and each value may itself be a list (text/plain;text/markdown).
+fn mime_types_from_args(context: &Context, arg_matches: &clap::ArgMatches) -> Option<String> {
+    let values = arg_matches.values_of("mime")?;
+
+    let mut mime_types = Vec::new();
+    for input in values.flat_map(|value| mime::split(value)) {
+        let mime_type = checked_mime_type(context, &input);
+        if !mime_types.contains(&mime_type) { mime_types.push(mime_type) }
+    }
+    Some(mime::join(&mime_types))
+}
+
+
+/// A MIME type, or the one found for a file extension (saying which one was picked)
+fn checked_mime_type(context: &Context, input: &str) -> String {
+    match mime::resolve(context, input) {
+        Ok(mime_type) => {
+            if !input.contains('/') { println!("Using {} for \"{}\"", mime_type, input) }
+            mime_type
+        }
+        Err(problem) => error_out(&problem)
+    }
+}
+
+
+/// Paths (anything with a slash, or a file in the current directory) are made absolute so the entry works from
+/// anywhere. Bare program names are left for the launcher to find on $PATH.
+fn absolute_program(program: &str) -> String {
+    if !program.contains('/') && !Path::new(program).is_file() {
+        return program.to_string();
+    }
+
+    match PathAbs::new(program).expect("Couldn't get file path").absolute() {
+        Ok(f)  => String::from(f.as_path().to_str().expect("Failed to turn exec path into string")),
+        Err(e) => error_out(&format!("Failed to open {} - {}", program, e))
+    }
+}
+
+
+/// --wine-prefix, or a prompt offering the prefixes that were found (default: $WINEPREFIX or ~/.wine).
+/// A folder that isn't a prefix yet is fine -- wine sets it up the first time it runs there.
+fn ask_for_wine_prefix(context: &Context, given: Option<&str>, yes: bool) -> PathBuf {
+    let answer = match given {
+        Some(prefix) => prefix.to_string(),
+        None => {
+            let prefixes = wine::find_prefixes(context);
+            if !yes && !prefixes.is_empty() {
+                println!("Found some Wine prefixes:");
+                for (i, prefix) in prefixes.iter().enumerate() {
+                    println!("  [{}] {}", i, prefix.display());
+                }
+            }
+
+            let default = wine::default_prefix(context).to_string_lossy().to_string();
+            let answer = ask_stdin_for_str("Please enter the Wine prefix to run it in (or a number from above)", Some(default), yes);
+            match answer.parse::<usize>() {
+                Ok(index) if index < prefixes.len() => return prefixes[index].clone(),
+                _ => answer
+            }
+        }
+    };
+
+    match PathAbs::new(&answer).expect("Couldn't get prefix path").absolute() {
+        Ok(path) => path.as_path().to_path_buf(),
+        Err(e) => error_out(&format!("Failed to use {} as a Wine prefix - {}", answer, e))
+    }
+}
+
+
+/// What --icon was given: a path (anything with a / in it, or a file that's there) is made absolute,
+/// anything else is kept as a theme icon name.
+fn icon_from_arg(arg: &str) -> String {
+    if !arg.contains('/') && !Path::new(arg).is_file() {
+        return arg.to_string();
+    }
+
+    match PathAbs::new(arg).expect("Couldn't get icon path").absolute() {
+        Ok(f)  => String::from(f.as_path().to_str().expect("Failed to turn icon path into string")),
+        Err(e) => error_out(&format!("Failed to open {} - {}", arg, e))
+    }
+}
+
+
+/// Prompts for an icon, offering whatever icon_sources can find around the executable (when there is one).
+/// The user can type one of the suggestion numbers instead of a path.
+/// With -y nobody sees the suggestions, so the existing icon (or none) is kept instead.
+fn ask_for_icon(context: &Context, entry: &Option<DesktopEntry>, exec: Option<&Path>, yes: bool) -> String {
+    let existing = optional_entry_field!(entry, get_icon).unwrap_or_default();
+
+    let suggestions = match exec {
+        Some(exec) if existing.is_empty() && !yes => icon_sources::suggest_icons(exec, &IconSearch::new(context), &context.icon_cache_dir()),
+        _ => Vec::new()
+    };
+    let default = match suggestions.first() {
+        Some(suggestion) => Some(suggestion.icon.clone()),
+        None             => Some(existing)
+    };
+
+    if !yes && !suggestions.is_empty() {
+        println!("Found some icons:");
+        for (i, suggestion) in suggestions.iter().enumerate() {
+            println!("  [{}] {} (from {})", i, suggestion.icon, suggestion.source.describe());
+        }
+    }
+
+    let answer = ask_stdin_for_str("Please enter the path to an icon (or a number from above)", default, yes);
+
+    match answer.parse::<usize>() {
+        Ok(index) if index < suggestions.len() => suggestions[index].icon.clone(),
+        _ => answer
+    }
+}
+
+
+/// Completes/fixes up category names against the registry, printing what was changed and any warnings.
+/// Problems that would keep the launcher out of the expected menu come back as an error message.
+fn checked_categories(input: &str) -> Result<String, String> {
+    let mut names = Vec::new();
+    for name in categories::split(input) {
+        let normalized = categories::normalize(&name);
+        if normalized != name { println!("Using category {} for \"{}\"", normalized, name) }
+        names.push(normalized);
+    }
+    if names.is_empty() { return Ok(String::new()) }
+
+    let mut errors = Vec::new();
+    for problem in categories::validate(&names) {
+        if problem.is_error() { errors.push(problem.describe()) }
+        else                  { println!("Warning: {}", problem.describe()) }
+    }
+
+    if errors.is_empty() { Ok(categories::join(&names)) }
+    else                 { Err(errors.join("\n")) }
+}
+
+
+pub fn print_completion_script(shell: Option<&str>) {
+    let shell = shell.unwrap_or_default();
+    match completions::script(shell) {
+        Some(script) => print!("{}", script),
+        None => error_out(&format!("Unsupported shell \"{}\" (try one of: {})", shell, completions::SHELLS.join(", ")))
+    }
+}
+
+
+/// Used by the completion scripts -- one candidate per line, and quiet on errors
+pub fn print_entry_candidates(context: &Context) {
+    if let Ok(candidates) = completions::entry_candidates(context) {
+        for candidate in candidates { println!("{}", candidate) }
+    }
+}
+
+
+pub fn print_menu_candidates(context: &Context) {
+    if let Ok(candidates) = completions::menu_candidates(context) {
+        for candidate in candidates { println!("{}", candidate) }
+    }
+}
+
+
+pub fn print_mime_candidates(context: &Context) {
+    for candidate in completions::mime_candidates(context) { println!("{}", candidate) }
+}
+
+
+pub fn print_category_candidates() {
+    for candidate in completions::category_candidates() { println!("{}", candidate) }
 }
 
 
@@ -176,6 +1515,15 @@ fn pwd() -> Option<String> {
 }
 
 
+/// Removing leftover icons is best-effort; the entry itself was already dealt with
+fn clean_up_icons(context: &Context) {
+    match icons::remove_orphaned_icons(context) {
+        Ok(()) => {}
+        Err(error) => println!("Couldn't clean up unused icons - {}", error.description())
+    }
+}
+
+
 fn valid_entry_or_none(entry: Option<io::Result<DesktopEntry>>) -> Option<DesktopEntry> {
     match entry {
         Some(result) => match result {
@@ -0,0 +1,51 @@
+use std::io;
+
+use super::desktop::read_desktop_files;
+use super::context::Context;
+use super::categories;
+use super::menu;
+use super::mime;
+
+include!(concat!(env!("OUT_DIR"), "/completion.bash.rs"));
+include!(concat!(env!("OUT_DIR"), "/completion.zsh.rs"));
+include!(concat!(env!("OUT_DIR"), "/completion.fish.rs"));
+
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+
+/// The completion script for the given shell. The scripts call back into
+/// `mkdesktop --complete-entries`, `--complete-categories`, `--complete-menus` and `--complete-mime-types` for the dynamic parts.
+pub fn script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(COMPLETION_BASH),
+        "zsh"  => Some(COMPLETION_ZSH),
+        "fish" => Some(COMPLETION_FISH),
+        _      => None
+    }
+}
+
+
+/// Everything FILE_OR_ENTRY can be completed to: entry names, then their indices
+pub fn entry_candidates(context: &Context) -> io::Result<Vec<String>> {
+    let entries = read_desktop_files(context)?;
+
+    let mut result: Vec<String> = entries.iter().map(|entry| entry.get_name().to_string()).collect();
+    result.extend((0..entries.len()).map(|i| i.to_string()));
+
+    Ok(result)
+}
+
+
+pub fn menu_candidates(context: &Context) -> io::Result<Vec<String>> {
+    Ok(menu::read_menus(context)?.iter().map(|m| m.get_name().to_string()).collect())
+}
+
+
+pub fn mime_candidates(context: &Context) -> Vec<String> {
+    mime::known_mime_types(context)
+}
+
+
+pub fn category_candidates() -> Vec<&'static str> {
+    categories::all_names()
+}
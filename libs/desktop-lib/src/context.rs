@@ -0,0 +1,302 @@
+use std::io;
+use std::fs;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use gio::{SettingsBackend, SettingsSchemaSource};
+
+
+/// Puts desktop files into the applications menu and takes them out again
+pub trait Registrar {
+    /// Installs a desktop file into the menu (a copy ends up in the context's applications_dir)
+    fn register(&self, desktop_file: &Path) -> io::Result<()>;
+    /// Undoes register, going by the desktop file's filename
+    fn unregister(&self, filename: &str) -> io::Result<()>;
+}
+
+
+/// Registers through xdg-desktop-menu, or whatever $MKDESKTOP_REGISTRAR names
+/// (anything taking the same install/uninstall arguments will do)
+pub struct CommandRegistrar {
+    program: String,
+}
+
+impl CommandRegistrar {
+    pub fn new(program: &str) -> CommandRegistrar {
+        CommandRegistrar { program: program.to_string() }
+    }
+
+    pub fn from_env() -> CommandRegistrar {
+        match env::var("MKDESKTOP_REGISTRAR") {
+            Ok(ref program) if !program.is_empty() => CommandRegistrar::new(program),
+            _ => CommandRegistrar::new("xdg-desktop-menu")
+        }
+    }
+
+    /// Only failing to run the program counts as an error; xdg-desktop-menu's exit status never did
+    fn run(&self, args: &[&str]) -> io::Result<()> {
+        Command::new(&self.program).args(args).status()?;
+        Ok(())
+    }
+}
+
+impl Registrar for CommandRegistrar {
+    fn register(&self, desktop_file: &Path) -> io::Result<()> {
+        let path = match desktop_file.to_str() {
+            Some(s) => s,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "Failed to turn path into string"))
+        };
+        self.run(&["install", path])
+    }
+
+    fn unregister(&self, filename: &str) -> io::Result<()> {
+        self.run(&["uninstall", filename])
+    }
+}
+
+
+/// Where GSettings come from: the schemas keys are looked up in, and what stores them (None for the default, dconf)
+#[derive(Clone)]
+pub struct GSettingsSource {
+    pub schemas: SettingsSchemaSource,
+    pub backend: Option<SettingsBackend>,
+}
+
+
+/// Where entries live and how they get into the menu.
+/// Everything that reads or writes entries goes through one of these, so it can be pointed somewhere else.
+pub struct Context {
+    data_home: PathBuf,
+    config_home: PathBuf,
+    /// $XDG_CONFIG_DIRS, for files that come with the system
+    config_dirs: Vec<PathBuf>,
+    /// $XDG_DATA_DIRS
+    data_dirs: Vec<PathBuf>,
+    /// $HOME, which user-dirs.dirs paths are relative to
+    home: PathBuf,
+    /// $XDG_CACHE_HOME
+    cache_home: PathBuf,
+    /// None keeps away from GSettings altogether
+    gsettings: Option<GSettingsSource>,
+    registrar: Box<dyn Registrar>,
+}
+
+impl Context {
+    /// `data_home` plays the part of $XDG_DATA_HOME: entries are kept in data_home/mkdesktop
+    /// and registered copies are expected in data_home/applications.
+    /// `config_home` plays the part of $XDG_CONFIG_HOME, where menu fragments go.
+    pub fn new(data_home: &Path, config_home: &Path, registrar: Box<dyn Registrar>) -> Context {
+        Context {
+            data_home: data_home.to_path_buf(),
+            config_home: config_home.to_path_buf(),
+            config_dirs: Vec::new(),
+            data_dirs: Vec::new(),
+            home: dirs::home_dir().unwrap_or_default(),
+            cache_home: dirs::cache_dir().unwrap_or_default(),
+            gsettings: None,
+            registrar: registrar,
+        }
+    }
+
+    /// $XDG_DATA_HOME (or ~/.local/share), $XDG_CONFIG_HOME (or ~/.config), xdg-desktop-menu and the user's GSettings
+    pub fn from_env() -> Context {
+        let data_home = dirs::data_dir().expect("Couldn't figure out data directory.");
+        let config_home = dirs::config_dir().expect("Couldn't figure out config directory.");
+        let mut result = Context::new(&data_home, &config_home, Box::new(CommandRegistrar::from_env()));
+        result.set_system_config_dirs(search_path("XDG_CONFIG_DIRS", "/etc/xdg"));
+        result.set_system_data_dirs(search_path("XDG_DATA_DIRS", "/usr/local/share:/usr/share"));
+        result.set_gsettings(SettingsSchemaSource::get_default().map(|schemas| GSettingsSource { schemas: schemas, backend: None }));
+        result
+    }
+
+
+    pub fn data_home(&self) -> &Path { &self.data_home }
+    pub fn config_home(&self) -> &Path { &self.config_home }
+    pub fn system_config_dirs(&self) -> &[PathBuf] { &self.config_dirs }
+    pub fn system_data_dirs(&self) -> &[PathBuf] { &self.data_dirs }
+    pub fn home(&self) -> &Path { &self.home }
+    pub fn cache_home(&self) -> &Path { &self.cache_home }
+    pub fn gsettings(&self) -> Option<&GSettingsSource> { self.gsettings.as_ref() }
+
+    /// A context from `new` doesn't look at any system folders (or GSettings) until given some
+    pub fn set_system_config_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.config_dirs = dirs;
+    }
+
+    pub fn set_system_data_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.data_dirs = dirs;
+    }
+
+    pub fn set_home(&mut self, home: &Path) {
+        self.home = home.to_path_buf();
+    }
+
+    pub fn set_cache_home(&mut self, cache_home: &Path) {
+        self.cache_home = cache_home.to_path_buf();
+    }
+
+    pub fn set_gsettings(&mut self, gsettings: Option<GSettingsSource>) {
+        self.gsettings = gsettings;
+    }
+
+    pub fn registrar(&self) -> &dyn Registrar { &*self.registrar }
+
+
+    /// Where mkdesktop keeps its desktop files. Created if it doesn't exist yet.
+    pub fn data_dir(&self) -> PathBuf {
+        let result = self.data_home.join("mkdesktop");
+        fs::create_dir_all(&result).expect("Couldn't create directory to put desktop file in");
+        result
+    }
+
+    /// Where registered desktop files end up
+    pub fn applications_dir(&self) -> PathBuf {
+        self.data_home.join("applications")
+    }
+
+    /// Where .directory files (menu folders) are looked up by menus
+    pub fn desktop_directories_dir(&self) -> PathBuf {
+        self.data_home.join("desktop-directories")
+    }
+
+    /// Where web apps keep their browser profiles, one folder per app
+    pub fn webapp_profiles_dir(&self) -> PathBuf {
+        self.data_home.join("mkdesktop-webapps")
+    }
+
+    /// Where icons pulled out of executables are kept until they get installed into the icon theme
+    pub fn icon_cache_dir(&self) -> PathBuf {
+        self.cache_home.join("mkdesktop").join("icons")
+    }
+
+    /// Where menu fragments are merged into the applications menu from
+    pub fn menus_dir(&self) -> PathBuf {
+        self.config_home.join("menus").join("applications-merged")
+    }
+
+    /// The user's default applications and MIME associations
+    pub fn mimeapps_list(&self) -> PathBuf {
+        self.config_home.join("mimeapps.list")
+    }
+
+    /// Where entries that start at login go
+    pub fn autostart_dir(&self) -> PathBuf {
+        self.config_home.join("autostart")
+    }
+
+    /// A folder from user-dirs.dirs the way xdg-user-dir finds it, e.g. "DESKTOP" for $XDG_DESKTOP_DIR.
+    /// The file is a shell script, but only "$HOME/..." and absolute paths are allowed in it.
+    pub fn user_dir(&self, name: &str) -> Option<PathBuf> {
+        let text = fs::read_to_string(self.config_home.join("user-dirs.dirs")).ok()?;
+        let variable = format!("XDG_{}_DIR", name);
+
+        // Like sourcing it, the last assignment wins
+        text.lines().rev()
+            .find_map(|line| {
+                let mut parts = line.trim().splitn(2, '=');
+                if parts.next()? != variable { return None }
+                let mut value = String::new();
+                let mut chars = parts.next()?.trim().trim_matches('"').chars();
+                while let Some(c) = chars.next() {
+                    value.push(if c == '\\' { chars.next()? } else { c });
+                }
+
+                if let Some(rest) = value.strip_prefix("$HOME") { Some(self.home.join(rest.trim_start_matches('/'))) }
+                else if value.starts_with('/') { Some(PathBuf::from(value)) }
+                else { None }
+            })
+    }
+
+    /// Where icons on the desktop go, $HOME/Desktop if user-dirs.dirs doesn't say
+    pub fn desktop_folder(&self) -> PathBuf {
+        self.user_dir("DESKTOP").unwrap_or_else(|| self.home.join("Desktop"))
+    }
+
+    /// Where the registered copy of one of our files is (or would be)
+    pub fn registered_path(&self, filename: &str) -> PathBuf {
+        if is_directory_file(Path::new(filename)) { self.desktop_directories_dir().join(filename) }
+        else                                      { self.applications_dir().join(filename) }
+    }
+
+
+    /// xdg-desktop-menu only takes .directory files together with entries to put in them,
+    /// so those are copied into place directly instead of going through the registrar
+    pub fn register(&self, desktop_file: &Path) -> io::Result<()> {
+        if !is_directory_file(desktop_file) { return self.registrar.register(desktop_file) }
+
+        let filename = match desktop_file.file_name() {
+            Some(name) => name,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Directory file has no filename"))
+        };
+        fs::create_dir_all(self.desktop_directories_dir())?;
+        fs::copy(desktop_file, self.desktop_directories_dir().join(filename))?;
+        Ok(())
+    }
+
+    pub fn unregister(&self, filename: &str) -> io::Result<()> {
+        if !is_directory_file(Path::new(filename)) { return self.registrar.unregister(filename) }
+
+        let path = self.desktop_directories_dir().join(filename);
+        if path.exists() { fs::remove_file(path)?; }
+        Ok(())
+    }
+}
+
+
+/// A colon-separated list of folders from the environment, or `default` when it's unset or empty (per the basedir spec)
+fn search_path(var: &str, default: &str) -> Vec<PathBuf> {
+    let value = env::var(var).ok().filter(|v| !v.is_empty()).unwrap_or_else(|| default.to_string());
+    value.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from).collect()
+}
+
+
+fn is_directory_file(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("directory")
+}
+
+
+/// A throwaway folder for unit tests, removed again when it goes out of scope (also when an assertion fails).
+/// It derefs to its path.
+#[cfg(test)]
+pub struct TestDir {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TestDir {
+    /// $TMPDIR/mkdesktop-<name>-<pid>, created empty
+    pub fn new(name: &str) -> TestDir {
+        let path = env::temp_dir().join(format!("mkdesktop-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path: path }
+    }
+
+    /// A context kept inside the folder: data/ is $XDG_DATA_HOME, config/ is $XDG_CONFIG_HOME, cache/ is $XDG_CACHE_HOME
+    /// and home/ is $HOME. Registering does nothing.
+    pub fn context(&self) -> Context {
+        let mut context = Context::new(&self.path.join("data"), &self.path.join("config"), Box::new(CommandRegistrar::new("true")));
+        context.set_home(&self.path.join("home"));
+        context.set_cache_home(&self.path.join("cache"));
+        context
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+    fn deref(&self) -> &Path { &self.path }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path { &self.path }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
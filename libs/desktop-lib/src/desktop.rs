@@ -3,22 +3,76 @@ extern crate lazy_static;
 
 use std::io;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::env;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
 
 use regex::{Regex, RegexBuilder};
 
+use super::icons;
+use super::icon_theme;
+use super::categories;
+use super::keyfile::{self, KeyFile, DESKTOP_ENTRY_GROUP};
+use super::exec::Exec;
+use super::context::Context;
+use super::menu;
+use super::autostart;
+use super::mime;
+use super::desktop_icon;
+use super::shortcut;
+
+
+/// The Type= key. Each type has its own keys: Exec (and friends) for Application, URL for Link,
+/// and neither for Directory, which describes a menu folder and is saved as a .directory file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryType {
+    Application,
+    Link,
+    Directory,
+}
+
+impl EntryType {
+    pub const ALL: [EntryType; 3] = [EntryType::Application, EntryType::Link, EntryType::Directory];
+
+    /// Takes the Type= value, or the lowercase names used on the command line
+    pub fn parse(name: &str) -> Option<EntryType> {
+        EntryType::ALL.iter().cloned().find(|t| t.as_str().eq_ignore_ascii_case(name))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryType::Application => "Application",
+            EntryType::Link        => "Link",
+            EntryType::Directory   => "Directory",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EntryType::Directory => "directory",
+            _                    => "desktop",
+        }
+    }
+}
+
 
 #[derive(Clone)]
 pub struct DesktopEntry {
     name: String,
-    shortcut_type: String,
+    shortcut_type: EntryType,
     comment: String,
     path: String,
-    exec: String,
+    exec: Exec,
+    /// Only for Type=Link
+    url: String,
     icon: String,
     terminal: bool,
     categories: String,
+    /// The types the application can open, as a MimeType= list (only for applications)
+    mime_types: String,
+    /// Keys we don't have fields for (X- keys, localized names, ...), kept so rewriting the file doesn't drop them
+    extra_keys: Vec<(String, String)>,
 }
 
 impl DesktopEntry {
@@ -26,32 +80,69 @@ impl DesktopEntry {
     pub fn blank() -> DesktopEntry {
         DesktopEntry {
             name: String::new(),
-            shortcut_type: String::from("Application"),
+            shortcut_type: EntryType::Application,
             comment: String::new(),
             path: String::new(),
-            exec: String::new(),
+            exec: Exec::parse(""),
+            url: String::new(),
             icon: String::new(),
             terminal: false,
             categories: String::new(),
+            mime_types: String::new(),
+            extra_keys: Vec::new(),
         }
     }
 
 
+    /// Directory entries get a .directory extension, everything else .desktop
     pub fn filename(&self) -> String {
-        name_to_filename(&self.name)
+        let filename = name_to_filename(&self.name);
+        match self.shortcut_type {
+            EntryType::Directory => format!("{}.{}", filename.trim_end_matches(".desktop"), self.shortcut_type.extension()),
+            _                    => filename
+        }
     }
 
-    pub fn filepath(&self) -> PathBuf {
-        name_to_desktop_file_path(&self.name)
+    pub fn filepath(&self, context: &Context) -> PathBuf {
+        context.data_dir().join(self.filename())
     }
 
 
+    pub fn get_type(&self) -> EntryType { return self.shortcut_type; }
     pub fn get_name(&self) -> &str { return &self.name; }
     pub fn get_comment(&self) -> &str { return &self.comment; }
     pub fn get_path(&self) -> &str { return &self.path; }
-    pub fn get_exec(&self) -> &str { return &self.exec; }
+    pub fn get_exec(&self) -> String { return self.exec.to_string(); }
+    pub fn get_exec_command(&self) -> &Exec { return &self.exec; }
+    pub fn get_env(&self) -> &BTreeMap<String, String> { return self.exec.env(); }
+    pub fn get_url(&self) -> &str { return &self.url; }
     pub fn get_icon(&self) -> &str { return &self.icon; }
     pub fn get_categories(&self) -> &str { return &self.categories; }
+    pub fn get_mime_types(&self) -> &str { return &self.mime_types; }
+
+    /// A file path or a theme icon name, like Icon=
+    pub fn set_icon(&mut self, icon: &str) {
+        self.icon = icon.to_string();
+    }
+
+    /// Takes a MimeType= list (see mime::join)
+    pub fn set_mime_types(&mut self, mime_types: &str) {
+        self.mime_types = mime_types.to_string();
+    }
+
+    /// A key without a field of its own (StartupWMClass, X- keys, ...), unescaped
+    pub fn get_extra_key(&self, key: &str) -> Option<String> {
+        self.extra_keys.iter().find(|(k, _)| k == key).map(|(_, value)| keyfile::unescape_value(value))
+    }
+
+    /// Sets a key without a field of its own, replacing what was there
+    pub fn set_extra_key(&mut self, key: &str, value: &str) {
+        let value = keyfile::escape_value(value);
+        match self.extra_keys.iter_mut().find(|(k, _)| k == key) {
+            Some(pair) => pair.1 = value,
+            None       => self.extra_keys.push((key.to_string(), value))
+        }
+    }
 
 
     /// Creates a new entry with the given fields
@@ -59,78 +150,79 @@ impl DesktopEntry {
         name: &str,
         comment: &str,
         path: &str,
-        exec: &str,
+        exec: Exec,
         icon: &str,
         categories: &str,
     ) -> DesktopEntry {
         DesktopEntry {
             name: name.to_string(),
-            shortcut_type: String::from("Application"),
+            shortcut_type: EntryType::Application,
             comment: comment.to_string(),
             path: path.to_string(),
-            exec: exec.to_string(),
+            exec: exec,
+            url: String::new(),
             icon: icon.to_string(),
             terminal: false,
             categories: categories.to_string(),
+            mime_types: String::new(),
+            extra_keys: Vec::new(),
         }
     }
 
 
-    /// Parses DesktopEntry from input stream
-    pub fn read(input: &mut io::BufRead) -> DesktopEntry {
-        lazy_static! {
-            static ref CATEGORY_REGEX: Regex = RegexBuilder::new(r"^\[([^\]]+)\]")
-                .build().unwrap();
+    /// A Type=Link entry, which opens `url` with whatever handles it
+    pub fn link(name: &str, comment: &str, url: &str, icon: &str) -> DesktopEntry {
+        DesktopEntry {
+            name: name.to_string(),
+            shortcut_type: EntryType::Link,
+            comment: comment.to_string(),
+            url: url.to_string(),
+            icon: icon.to_string(),
+            ..DesktopEntry::blank()
+        }
+    }
 
-            static ref ATTR_REGEX: Regex = RegexBuilder::new(r"^([^\[#]+)=([^#]+)(#.*)?$")
-                .build().unwrap();
 
-            static ref BOOL_REGEX: Regex = RegexBuilder::new(r"true")
-                .case_insensitive(true)
-                .build().unwrap();
+    /// A Type=Directory entry, describing a menu folder
+    pub fn directory(name: &str, comment: &str, icon: &str) -> DesktopEntry {
+        DesktopEntry {
+            name: name.to_string(),
+            shortcut_type: EntryType::Directory,
+            comment: comment.to_string(),
+            icon: icon.to_string(),
+            ..DesktopEntry::blank()
         }
+    }
 
-        let mut result = DesktopEntry::blank();
-        let mut line = String::new();
-        let mut currently_reading_desktop_entry = false;
 
-        while let Ok(bytes_read) = input.read_line(&mut line) {
-            if bytes_read == 0 { break }
+    /// Parses DesktopEntry from input stream. Fails with the position of the first malformed line.
+    pub fn read(input: &mut io::BufRead) -> io::Result<DesktopEntry> {
+        Ok(DesktopEntry::from_keyfile(&KeyFile::read(input)?))
+    }
 
-            match CATEGORY_REGEX.captures(&line) {
-                Some(category_cap) => {
-                    let category = category_cap.get(1).unwrap().as_str();
-                    currently_reading_desktop_entry = category == "Desktop Entry";
-                }
-                None => {}
-            }
 
-            if !currently_reading_desktop_entry { line.clear(); continue }
+    /// Picks the [Desktop Entry] group out of an already parsed file
+    pub fn from_keyfile(keyfile: &KeyFile) -> DesktopEntry {
+        let mut result = DesktopEntry::blank();
 
-            let caps = match ATTR_REGEX.captures(&line) {
-                Some(c) => c,
-                None    => {
-                    line.clear();
-                    continue;
-                }
-            };
-
-            let field = caps.get(1).unwrap().as_str().trim();
-            let value = caps.get(2).unwrap().as_str().trim();
-
-            match field {
-                "Type"       => { result.shortcut_type = String::from(value) }
-                "Name"       => { result.name          = String::from(value) }
-                "Comment"    => { result.comment       = String::from(value) }
-                "Path"       => { result.path          = String::from(value) }
-                "Exec"       => { result.exec          = String::from(value) }
-                "Icon"       => { result.icon          = String::from(value) }
-                "Terminal"   => { result.terminal      = BOOL_REGEX.captures(&value).is_some() }
-                "Categories" => { result.categories    = String::from(value) }
-                _ => {}
+        for key in keyfile.keys(DESKTOP_ENTRY_GROUP) {
+            let raw = keyfile.get(DESKTOP_ENTRY_GROUP, key).unwrap_or_default();
+            let value = keyfile::unescape_value(raw);
+
+            match key {
+                "Type"       => { result.shortcut_type = EntryType::parse(&value).unwrap_or(EntryType::Application) }
+                "Name"       => { result.name          = value }
+                "Comment"    => { result.comment       = value }
+                "Path"       => { result.path          = value }
+                "Exec"       => { result.exec          = Exec::parse(&value) }
+                "URL"        => { result.url           = value }
+                "Icon"       => { result.icon          = value }
+                "Terminal"   => { result.terminal      = value.eq_ignore_ascii_case("true") }
+                "Categories" => { result.categories    = value }
+                "MimeType"   => { result.mime_types    = value }
+                "Version" | "Actions" => {}
+                _ => { result.extra_keys.push((key.to_string(), raw.to_string())) }
             }
-
-            line.clear();
         }
 
         result
@@ -139,6 +231,12 @@ impl DesktopEntry {
 
     /// Gets you a nice string representation (doesn't include all info)
     pub fn display(&self) -> String {
+        match self.shortcut_type {
+            EntryType::Link      => return format!("{}\n\topen {}", self.name, self.url),
+            EntryType::Directory => return format!("{}\n\t(menu folder)", self.name),
+            EntryType::Application => {}
+        }
+
         if self.path.is_empty() {
             format!(
                 "{}\n\texec {}",
@@ -159,61 +257,234 @@ impl DesktopEntry {
 
     pub fn write(&self, output: &mut io::Write) -> io::Result<()> {
         make_desktop(
+            self.shortcut_type,
             &self.name,
             &self.comment,
             &self.path,
-            &self.exec,
+            &self.exec.to_string(),
+            &self.url,
             &self.icon,
             self.terminal,
             &self.categories,
+            &self.mime_types,
+            &self.extra_keys,
             output
         )
     }
 
 
-    /// Makes sure the DesktopEntry is registered as a shortcut.
-    /// This is called by write_to_apps_dir and is probably useless to call directly.
-    pub fn save(&self) -> io::Result<()> {
-        let filepath = self.filepath();
-        let path = match filepath.to_str() {
-            Some(s) => s,
-            None => return Err(io::Error::new(io::ErrorKind::Other, "Failed to turn path into string"))
-        };
+    /// Runs the entry's command the way a launcher would (minus the field codes like %f).
+    /// Links are handed to xdg-open.
+    pub fn launch(&self) -> io::Result<Child> {
+        match self.shortcut_type {
+            EntryType::Link => {
+                if self.url.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "URL is empty"));
+                }
+                return Command::new("xdg-open").arg(&self.url).spawn();
+            }
+            EntryType::Directory => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Menu folders can't be launched"));
+            }
+            EntryType::Application => {}
+        }
 
-        match Command::new("xdg-desktop-menu").args(&["install", path]).status() {
-            Ok(_)  => Ok(()),
-            Err(e) => return Err(e)
+        if self.exec.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Exec is empty"));
         }
+
+        let mut command = Command::new(self.exec.program());
+        command.args(self.exec.launch_args());
+        command.envs(self.exec.env());
+
+        if !self.path.is_empty() {
+            command.current_dir(&self.path);
+        }
+
+        command.spawn()
+    }
+
+
+    /// Problems that would keep this entry from working or from showing up where it should.
+    /// An empty result means the entry looks fine.
+    pub fn validate(&self, context: &Context) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.name.is_empty() {
+            problems.push(String::from("Name is empty"));
+        }
+
+        if self.shortcut_type == EntryType::Link && self.url.is_empty() {
+            problems.push(String::from("URL is empty"));
+        }
+
+        if !self.icon.is_empty() && icon_theme::find_icon(context, &self.icon, 48).is_none() {
+            problems.push(format!("Icon \"{}\" couldn't be found", self.icon));
+        }
+
+        // The rest only means something for applications
+        if self.shortcut_type != EntryType::Application { return problems }
+
+        if self.exec.is_empty() {
+            problems.push(String::from("Exec is empty"));
+        }
+        else if find_program(self.exec.program()).is_none() {
+            problems.push(format!("Exec program \"{}\" doesn't exist or isn't on $PATH", self.exec.program()));
+        }
+
+        if !self.path.is_empty() && !Path::new(&self.path).is_dir() {
+            problems.push(format!("Working directory \"{}\" doesn't exist", self.path));
+        }
+
+        for problem in categories::validate(&categories::split(&self.categories)) {
+            if problem.is_error() { problems.push(problem.describe()) }
+        }
+
+        for mime_type in mime::split(&self.mime_types) {
+            if !mime::is_valid_mime_type(&mime_type) { problems.push(format!("MimeType \"{}\" isn't a valid MIME type", mime_type)) }
+        }
+
+        problems
+    }
+
+
+    /// Carries over what the editors don't ask about (Terminal, X- keys, ...), for when an edited entry replaces `old`.
+    /// MimeType is left to the caller, since an empty one may be meant to clear it.
+    pub fn keep_unedited_fields(&mut self, old: &DesktopEntry) {
+        self.terminal = old.terminal;
+        self.extra_keys = old.extra_keys.clone();
+    }
+
+
+    /// If the icon is a file on disk, copies it into the icon theme and points Icon= at the
+    /// installed theme name instead, so the launcher survives the original file moving.
+    pub fn adopt_icon(&mut self, context: &Context) -> io::Result<()> {
+        if self.icon.is_empty() || icons::is_managed(&self.icon) { return Ok(()) }
+
+        let source = PathBuf::from(&self.icon);
+        if !source.is_file() { return Ok(()) }
+
+        self.icon = icons::install_icon(context, &source, &self.name)?;
+        Ok(())
+    }
+
+
+    /// Makes sure the DesktopEntry is registered as a shortcut.
+    /// This is called by write_to_apps_dir and is probably useless to call directly.
+    pub fn save(&self, context: &Context) -> io::Result<()> {
+        context.register(&self.filepath(context))?;
+        desktop_icon::refresh(context, &self.filename(), &self.filepath(context))?;
+        autostart::refresh(context, &self.filename(), &self.filepath(context))
     }
 
 
     /// Writes the DesktopEntry to disk and registers it.
-    pub fn write_to_apps_dir(&self) -> io::Result<()> {
-        let mut path = data_dir();
+    pub fn write_to_apps_dir(&self, context: &Context) -> io::Result<()> {
+        let mut path = context.data_dir();
         path.push(self.filename());
 
         let mut file = fs::File::create(path)?;
         self.write(&mut file)?;
-        self.save()
+        self.save(context)
+    }
+
+
+    /// The entry's file as a raw keyfile, for changing individual keys
+    pub fn keyfile(&self, context: &Context) -> io::Result<KeyFile> {
+        KeyFile::open(&self.filepath(context))
+    }
+
+
+    /// Replaces this entry's file with `keyfile` and registers it again.
+    /// If Name changed, the file moves to match it and the entry that's now on disk is returned.
+    pub fn write_keyfile(&self, context: &Context, keyfile: &KeyFile) -> io::Result<DesktopEntry> {
+        let mut keyfile = keyfile.clone();
+        let new_entry = DesktopEntry::from_keyfile(&keyfile);
+        if new_entry.name.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "An entry needs a Name"));
+        }
+        if new_entry.filename() != self.filename() && new_entry.filepath(context).exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("There's already an entry called \"{}\"", new_entry.name)));
+        }
+
+        // Keep our own delete action pointing at the right entry
+        if new_entry.name != self.name && keyfile.has_group(DELETE_ACTION_GROUP) {
+            keyfile.set(DELETE_ACTION_GROUP, "Exec", &delete_command(&new_entry.name));
+        }
+
+        fs::write(new_entry.filepath(context), keyfile.to_bytes())?;
+        new_entry.save(context)?;
+
+        if new_entry.filename() != self.filename() {
+            if let Err(e) = rename_references(context, &self.filename(), &new_entry.filename()) {
+                eprintln!("Couldn't give everything that refers to the entry its new name - {}", e);
+            }
+            self.delete(context)?;
+        }
+        Ok(new_entry)
     }
 
 
-    pub fn delete(&self) -> io::Result<()> {
+    pub fn delete(&self, context: &Context) -> io::Result<()> {
         let filename = self.filename();
 
-        // First, uninstall the desktop entry
-        let _uninstall = Command::new("xdg-desktop-menu")
-            .args(&["uninstall", &filename])
-            .status()?;
+        // First, uninstall the desktop entry. What else refers to it is cleaned up as far as it can be,
+        // since a half-deleted entry is worse than a stale line in someone else's config.
+        context.unregister(&filename)?;
+        best_effort("the menus", menu::forget_entry(context, &filename));
+        best_effort("its autostart copy", autostart::forget_entry(context, &filename));
+        best_effort("mimeapps.list", mime::forget_entry(context, &filename));
+        best_effort("its desktop copy", desktop_icon::forget_entry(context, &filename));
+        best_effort("its keyboard shortcut", shortcut::forget_entry(context, &filename));
 
         // Next, delete the desktop entry
-        let mut path = data_dir();
+        let mut path = context.data_dir();
         path.push(filename);
         fs::remove_file(path)
     }
 }
 
 
+const DELETE_ACTION_GROUP: &str = "Desktop Action delete-shortcut";
+
+/// Quoted the way Exec wants, so names with quotes, backslashes or $ in them still get through
+fn delete_command(name: &str) -> String {
+    Exec::new("mkdesktop", vec![String::from("rm"), name.to_string()]).to_string()
+}
+
+
+/// Points what refers to an entry by its desktop file ID (menus, its desktop and autostart copies, mimeapps.list, shortcuts)
+/// at the new ID, for when a rename moved the entry to a new file. The new file has to be saved already.
+/// Each is tried even if one before it fails, and the error says which didn't make it.
+pub fn rename_references(context: &Context, old_id: &str, new_id: &str) -> io::Result<()> {
+    let source = context.data_dir().join(new_id);
+    let results = vec![
+        ("the menus", menu::rename_entry(context, old_id, new_id)),
+        ("mimeapps.list", mime::rename_entry(context, old_id, new_id)),
+        ("its desktop copy", desktop_icon::rename_entry(context, old_id, new_id, &source)),
+        ("its keyboard shortcut", shortcut::rename_entry(context, old_id, new_id, &source)),
+        ("its autostart copy", autostart::rename_entry(context, old_id, new_id, &source)),
+    ];
+
+    let failed: Vec<String> = results.into_iter()
+        .filter_map(|(what, result)| result.err().map(|e| format!("{} ({})", what, e)))
+        .collect();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, failed.join(", ")))
+    }
+}
+
+/// For config that belongs to other programs: if it can't be read or written, the entry itself
+/// is still deleted or renamed, and the user is told what was left behind
+fn best_effort(what: &str, result: io::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("Couldn't update {}: {}", what, e);
+    }
+}
+
+
 pub fn name_to_filename(name: &str) -> String {
     lazy_static! {
         static ref INVALIDS: Regex = RegexBuilder::new(r"[^\w\-\+_]+")
@@ -228,33 +499,29 @@ pub fn name_to_filename(name: &str) -> String {
 }
 
 
-pub fn name_to_desktop_file_path(name: &str) -> PathBuf {
-    let mut path = data_dir();
-    path.push(name_to_filename(name));
-    path
-}
-
+/// Resolves a program the way a launcher would: paths are checked directly, bare names are looked up on $PATH
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    if program.is_empty() { return None }
 
-pub fn data_dir() -> PathBuf {
-    let mut result = PathBuf::new();
-
-    result.push(dirs::data_dir().expect("Couldn't figure out data directory."));
-    result.push("mkdesktop");
-
-    let create_dir = result.clone();
-    fs::create_dir_all(create_dir).expect("Couldn't create directory to put desktop file in");
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return if path.is_file() { Some(path) } else { None };
+    }
 
-    result
+    let path_var = env::var_os("PATH")?;
+    env::split_paths(&path_var)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
 }
 
 
-pub fn select(selector: &str) -> io::Result<DesktopEntry> {
+pub fn select(context: &Context, selector: &str) -> io::Result<DesktopEntry> {
     lazy_static! {
         static ref INDEX_SELECTOR: Regex = RegexBuilder::new(r"[\(\)\{\}\[\]\s]*(\d+)[\(\)\{\}\[\]\s]*")
             .build().unwrap();
     }
 
-    let entries = read_desktop_files()?;
+    let entries = read_desktop_files(context)?;
 
     //
     // First, see if the selector is just an index
@@ -285,14 +552,69 @@ pub fn select(selector: &str) -> io::Result<DesktopEntry> {
 }
 
 
-pub fn read_desktop_files() -> io::Result<Vec<DesktopEntry>> {
+/// Entries matching a glob like "steam-*" (? and * only; case-insensitive). The glob is tried against the name,
+/// the filename, and the filename without "mkdesktop-" and ".desktop" (so "Steam Game" is also "steam-game").
+pub fn select_matching(context: &Context, pattern: &str) -> io::Result<Vec<DesktopEntry>> {
+    let pattern = pattern.to_lowercase();
+    Ok(read_desktop_files(context)?.into_iter()
+        .filter(|entry| {
+            let filename = entry.filename().to_lowercase();
+            let slug = filename.trim_start_matches("mkdesktop-").trim_end_matches(".desktop");
+            glob_matches(&pattern, &entry.name.to_lowercase()) || glob_matches(&pattern, &filename) || glob_matches(&pattern, slug)
+        })
+        .collect())
+}
+
+
+pub fn select_in_category(context: &Context, category: &str) -> io::Result<Vec<DesktopEntry>> {
+    let category = categories::normalize(category);
+    Ok(read_desktop_files(context)?.into_iter()
+        .filter(|entry| categories::split(&entry.categories).iter().any(|c| *c == category))
+        .collect())
+}
+
+
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Classic two-pointer wildcard match, backtracking to the last * on a mismatch
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        }
+        else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, t));
+            p += 1;
+        }
+        else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        }
+        else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+
+/// Problems go to stderr, where they can't end up in --complete-entries output. A file that doesn't parse
+/// is still read the forgiving way, so it can be listed and removed -- `mkdesktop doctor` says what's wrong with it.
+pub fn read_desktop_files(context: &Context) -> io::Result<Vec<DesktopEntry>> {
     let mut result = Vec::<DesktopEntry>::new();
 
-    for direntry_result in fs::read_dir(data_dir())? {
+    for direntry_result in fs::read_dir(context.data_dir())? {
         let direntry = match direntry_result {
             Ok(x) => x,
             Err(e) => {
-                println!("ERROR READING DESKTOP FILE: {}", e);
+                eprintln!("ERROR READING DESKTOP FILE: {}", e);
                 continue;
             }
         };
@@ -300,13 +622,26 @@ pub fn read_desktop_files() -> io::Result<Vec<DesktopEntry>> {
         let file = match fs::File::open(direntry.path()) {
             Ok(x) => x,
             Err(e) => {
-                println!("Couldn't open {:?} - {}", direntry.path(), e);
+                eprintln!("Couldn't open {:?} - {}", direntry.path(), e);
                 continue;
             }
         };
         let mut reader = io::BufReader::new(file);
 
-        result.push(DesktopEntry::read(&mut reader));
+        match DesktopEntry::read(&mut reader) {
+            Ok(entry) => result.push(entry),
+            Err(e) => {
+                eprintln!("Couldn't parse {:?} - {} (see `mkdesktop doctor`)", direntry.path(), e);
+
+                // Only when it's where its name says, since that's the file deleting it removes
+                let lenient = KeyFile::open_lenient(&direntry.path()).map(|k| DesktopEntry::from_keyfile(&k));
+                if let Ok(entry) = lenient {
+                    if !entry.name.is_empty() && direntry.file_name().to_str() == Some(entry.filename().as_str()) {
+                        result.push(entry);
+                    }
+                }
+            }
+        }
     }
 
     Ok(result)
@@ -314,13 +649,17 @@ pub fn read_desktop_files() -> io::Result<Vec<DesktopEntry>> {
 
 
 pub fn make_desktop(
+    entry_type: EntryType,
     name: &str,
     comment: &str,
     path: &str,
     exec: &str,
+    url: &str,
     icon: &str,
     terminal: bool,
     categories: &str,
+    mime_types: &str,
+    extra_keys: &[(String, String)],
     output: &mut io::Write
 ) -> io::Result<()> {
     /*
@@ -344,26 +683,45 @@ pub fn make_desktop(
     # Describes the categories in which this entry should be shown
     Categories=Education;Languages;Java;
     */
+    let escape = keyfile::escape_value;
+
     output.write_fmt(format_args!("[Desktop Entry]\n"))?;
-    output.write_fmt(format_args!("Type=Application\n"))?;
+    output.write_fmt(format_args!("Type={}\n", entry_type.as_str()))?;
     output.write_fmt(format_args!("Version=1.0\n"))?;
-    output.write_fmt(format_args!("Name={}\n", name))?;
-    output.write_fmt(format_args!("Exec={}\n", exec))?;
+    output.write_fmt(format_args!("Name={}\n", escape(name)))?;
+
+    match entry_type {
+        EntryType::Application => output.write_fmt(format_args!("Exec={}\n", escape(exec)))?,
+        EntryType::Link        => output.write_fmt(format_args!("URL={}\n", escape(url)))?,
+        EntryType::Directory   => {}
+    }
+
+    // Path, Categories, MimeType, Terminal and Actions are only defined for applications
+    let application = entry_type == EntryType::Application;
 
-    if !comment.is_empty()    { output.write_fmt(format_args!("Comment={}\n", comment))?       }
-    if !path.is_empty()       { output.write_fmt(format_args!("Path={}\n", path))?             }
-    if !icon.is_empty()       { output.write_fmt(format_args!("Icon={}\n", icon))?             }
-    if !categories.is_empty() { output.write_fmt(format_args!("Categories={}\n", categories))? }
+    if !comment.is_empty()                   { output.write_fmt(format_args!("Comment={}\n", escape(comment)))?       }
+    if application && !path.is_empty()       { output.write_fmt(format_args!("Path={}\n", escape(path)))?             }
+    if !icon.is_empty()                      { output.write_fmt(format_args!("Icon={}\n", escape(icon)))?             }
+    if application && !categories.is_empty() { output.write_fmt(format_args!("Categories={}\n", escape(categories)))? }
+    if application && !mime_types.is_empty() { output.write_fmt(format_args!("MimeType={}\n", escape(mime_types)))? }
 
-    if terminal { output.write_fmt(format_args!("Terminal=true\n"))? }
-    else        { output.write_fmt(format_args!("Terminal=false\n"))? }
+    if application {
+        if terminal { output.write_fmt(format_args!("Terminal=true\n"))? }
+        else        { output.write_fmt(format_args!("Terminal=false\n"))? }
+    }
+
+    for (key, value) in extra_keys {
+        output.write_fmt(format_args!("{}={}\n", key, value))?;
+    }
 
     // Actions
-    output.write_fmt(format_args!("Actions=delete-shortcut\n"))?;
+    if application {
+        output.write_fmt(format_args!("Actions=delete-shortcut\n"))?;
 
-    output.write_fmt(format_args!("\n[Desktop Action delete-shortcut]\n"))?;
-    output.write_fmt(format_args!("Name=Delete Shortcut\n"))?;
-    output.write_fmt(format_args!("Exec={} --rm \"{}\"\n", env!("CARGO_PKG_NAME"), name))?;
+        output.write_fmt(format_args!("\n[Desktop Action delete-shortcut]\n"))?;
+        output.write_fmt(format_args!("Name=Delete Shortcut\n"))?;
+        output.write_fmt(format_args!("Exec={}\n", escape(&delete_command(name))))?;
+    }
 
     // Done -- flush output
     output.flush()?;
@@ -373,7 +731,7 @@ pub fn make_desktop(
 
 #[cfg(test)]
 mod test {
-    use super::DesktopEntry;
+    use super::{DesktopEntry, EntryType, glob_matches};
     use std::io;
 
     #[test]
@@ -400,14 +758,23 @@ Terminal=false
 # Describes the categories in which this entry should be shown
 Categories=Education;Languages;Java;";
         let mut stream = io::Cursor::new(desktop_string);
-        let desktop_entry = DesktopEntry::read(&mut stream);
+        let desktop_entry = DesktopEntry::read(&mut stream).unwrap();
 
-        assert_eq!(desktop_entry.shortcut_type, "Application");
+        assert_eq!(desktop_entry.shortcut_type, EntryType::Application);
         assert_eq!(desktop_entry.comment, "Flash card based learning tool");
         assert_eq!(desktop_entry.path, "/opt/jmemorise");
-        assert_eq!(desktop_entry.exec, "jmemorize");
+        assert_eq!(desktop_entry.get_exec(), "jmemorize");
         assert_eq!(desktop_entry.icon, "jmemorize");
         assert_eq!(desktop_entry.terminal, false);
         assert_eq!(desktop_entry.categories, "Education;Languages;Java;");
     }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("steam-*", "steam-half-life"));
+        assert!(glob_matches("*.desktop", "mkdesktop-steam.desktop"));
+        assert!(glob_matches("g?me*s", "games"));
+        assert!(!glob_matches("steam-*", "my-steam-game"));
+        assert!(!glob_matches("a?c", "ac"));
+    }
 }
@@ -0,0 +1,112 @@
+extern crate gio;
+
+use std::io;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use gio::prelude::*;
+
+use super::context::Context;
+
+
+/// Where the desktop copy for a desktop file ID goes
+pub fn icon_path(context: &Context, desktop_id: &str) -> PathBuf {
+    context.desktop_folder().join(desktop_id)
+}
+
+pub fn is_on_desktop(context: &Context, desktop_id: &str) -> bool {
+    icon_path(context, desktop_id).is_file()
+}
+
+
+/// Puts a copy of the desktop file at `source` on the desktop. File managers only run desktop files
+/// there that are executable, and GNOME's desktop also wants them marked trusted.
+pub fn add(context: &Context, desktop_id: &str, source: &Path) -> io::Result<()> {
+    fs::create_dir_all(context.desktop_folder())?;
+    write_copy(&icon_path(context, desktop_id), source)
+}
+
+pub fn remove(context: &Context, desktop_id: &str) -> io::Result<()> {
+    let path = icon_path(context, desktop_id);
+    if path.exists() { fs::remove_file(path)?; }
+    Ok(())
+}
+
+
+/// Rewrites the desktop copy (if there is one) from the entry's file at `source`. Copying over it can
+/// lose the executable bit and the trusted mark, so both are set again.
+pub fn refresh(context: &Context, desktop_id: &str, source: &Path) -> io::Result<()> {
+    let path = icon_path(context, desktop_id);
+    if path.is_file() { write_copy(&path, source)?; }
+    Ok(())
+}
+
+
+/// For when an entry's file is renamed: the launcher on the desktop is swapped for one named after the new ID.
+/// gvfs keeps metadata::trusted per file, so the new one is trusted from scratch.
+pub fn rename_entry(context: &Context, old_id: &str, new_id: &str, source: &Path) -> io::Result<()> {
+    if old_id == new_id || !is_on_desktop(context, old_id) { return Ok(()) }
+    add(context, new_id, source)?;
+    remove(context, old_id)
+}
+
+
+/// Takes a deleted entry's copy off the desktop
+pub fn forget_entry(context: &Context, desktop_id: &str) -> io::Result<()> {
+    remove(context, desktop_id)
+}
+
+
+fn write_copy(path: &Path, source: &Path) -> io::Result<()> {
+    fs::copy(source, path)?;
+
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    fs::set_permissions(path, permissions)?;
+
+    trust(path);
+    Ok(())
+}
+
+
+/// Sets gvfs's metadata::trusted, which GNOME's desktop icons need before they'll launch anything.
+/// Desktops without gvfs metadata don't need it, so failing is fine.
+fn trust(path: &Path) {
+    let file = gio::File::new_for_path(path);
+    let _ = file.set_attribute_string("metadata::trusted", "true", gio::FileQueryInfoFlags::NONE, gio::NONE_CANCELLABLE);
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+
+    #[test]
+    fn desktop_copies_go_where_user_dirs_says() {
+        let root = TestDir::new("desktop-icon");
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::write(root.join("tool.desktop"), "[Desktop Entry]\nName=Tool\nExec=tool\n").unwrap();
+
+        let context = root.context();
+        assert_eq!(context.desktop_folder(), root.join("home").join("Desktop"));
+
+        fs::write(root.join("config").join("user-dirs.dirs"), "# written by xdg-user-dirs-update\nXDG_DESKTOP_DIR=\"$HOME/Bureau\"\nXDG_MUSIC_DIR=\"/srv/music\"\n").unwrap();
+        assert_eq!(context.desktop_folder(), root.join("home").join("Bureau"));
+        assert_eq!(context.user_dir("MUSIC"), Some(PathBuf::from("/srv/music")));
+
+        add(&context, "tool.desktop", &root.join("tool.desktop")).unwrap();
+        let copy = icon_path(&context, "tool.desktop");
+        assert_eq!(fs::metadata(&copy).unwrap().permissions().mode() & 0o111, 0o111);
+
+        fs::write(root.join("tool.desktop"), "[Desktop Entry]\nName=Tool\nExec=tool --quiet\n").unwrap();
+        refresh(&context, "tool.desktop", &root.join("tool.desktop")).unwrap();
+        assert!(fs::read_to_string(&copy).unwrap().contains("tool --quiet"));
+
+        rename_entry(&context, "tool.desktop", "gadget.desktop", &root.join("tool.desktop")).unwrap();
+        assert!(!copy.exists() && is_on_desktop(&context, "gadget.desktop"));
+        forget_entry(&context, "gadget.desktop").unwrap();
+        assert!(!is_on_desktop(&context, "gadget.desktop"));
+    }
+}
@@ -0,0 +1,361 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use super::desktop::{self, DesktopEntry, EntryType};
+use super::context::Context;
+use super::exec::Exec;
+use super::icon_theme;
+use super::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+use super::reconcile::{self, Orphan};
+use super::menu::{self, Menu};
+use super::packages::{self, Package};
+use super::overrides::{self, Upstream};
+
+
+/// What `mkdesktop doctor --fix` would do about a problem
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fix {
+    /// Point the key (Exec or TryExec) at a program with the same name found on $PATH
+    Relink(&'static str, PathBuf),
+    ClearField(&'static str),
+    Remove,
+    Reregister,
+    /// Take a menu entry with no source out of the menu
+    Unregister,
+    /// Take a desktop file ID that no longer exists out of a submenu
+    LeaveMenu(String),
+}
+
+impl Fix {
+    pub fn describe(&self) -> String {
+        match self {
+            Fix::Relink(key, program) => format!("point {} at {}", key, program.display()),
+            Fix::ClearField(key)      => format!("clear {}", key),
+            Fix::Remove               => String::from("remove the entry"),
+            Fix::Reregister           => String::from("register it again"),
+            Fix::Unregister           => String::from("remove it from the menu"),
+            Fix::LeaveMenu(id)        => format!("take {} out of the menu", id),
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Finding {
+    /// The desktop file in the data dir the problem is with
+    /// (or its registered copy for Fix::Unregister, the menu fragment for Fix::LeaveMenu, or an override in the applications folder)
+    pub file: PathBuf,
+    /// The entry's name, or the filename when there isn't one
+    pub name: String,
+    pub problem: String,
+    pub fix: Option<Fix>,
+}
+
+
+/// Checks every file in the data dir. Files are reported in directory order, problems in the order they're checked.
+pub fn examine(context: &Context) -> io::Result<Vec<Finding>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(context.data_dir())?
+        .filter_map(|f| f.ok())
+        .map(|f| f.path())
+        .collect();
+    paths.sort();
+
+    let mut findings = Vec::new();
+    let mut parsed = Vec::new();
+
+    for path in paths {
+        let filename = file_name(&path);
+        let mut finding = |problem: String, fix: Option<Fix>, name: &str| findings.push(Finding {
+            file: path.clone(), name: name.to_string(), problem: problem, fix: fix
+        });
+
+        let keyfile = match KeyFile::open(&path) {
+            Ok(k) => k,
+            // A file that doesn't parse may only need a small edit, so that's left for a person to decide
+            Err(ref error) if error.kind() == io::ErrorKind::InvalidData => {
+                finding(format!("Isn't a valid desktop file ({})", error), None, &filename);
+                continue;
+            }
+            Err(error) => {
+                finding(format!("Couldn't be read - {}", error), None, &filename);
+                continue;
+            }
+        };
+        // Could be a misnamed group or something else entirely that ended up here, so it isn't ours to delete
+        if !keyfile.has_group(DESKTOP_ENTRY_GROUP) {
+            finding(String::from("Isn't a desktop file (no [Desktop Entry] group)"), None, &filename);
+            continue;
+        }
+
+        let entry = DesktopEntry::from_keyfile(&keyfile);
+        if entry.get_name().is_empty() {
+            finding(String::from("Has no Name"), Some(Fix::Remove), &filename);
+            continue;
+        }
+        let name = entry.get_name().to_string();
+
+        let exec = entry.get_exec_command();
+        match entry.get_type() {
+            EntryType::Application if exec.is_empty() => {
+                finding(String::from("Exec is empty"), Some(Fix::Remove), &name);
+            }
+            // The program may be on a drive that isn't mounted, or about to be reinstalled, so without
+            // a replacement to point at, the entry is only reported
+            EntryType::Application if desktop::find_program(exec.program()).is_none() => {
+                let fix = relink_candidate(exec.program()).map(|program| Fix::Relink("Exec", program));
+                finding(format!("Exec program \"{}\" doesn't exist or isn't on $PATH", exec.program()), fix, &name);
+            }
+            EntryType::Link if entry.get_url().is_empty() => {
+                finding(String::from("URL is empty"), Some(Fix::Remove), &name);
+            }
+            _ => {}
+        }
+
+        // `flatpak run` itself is there, but the app may have been uninstalled since
+        if let Some((kind, id)) = packages::wrapped_by(&exec) {
+            if Package::find_installed(context, kind, &id).is_none() {
+                finding(format!("{} {} isn't installed", kind.describe(), id), None, &name);
+            }
+        }
+
+        if let Some(try_exec) = keyfile.get_string(DESKTOP_ENTRY_GROUP, "TryExec") {
+            if desktop::find_program(&try_exec).is_none() {
+                let fix = match relink_candidate(&try_exec) {
+                    Some(program) => Fix::Relink("TryExec", program),
+                    None          => Fix::ClearField("TryExec")
+                };
+                finding(format!("TryExec \"{}\" doesn't exist, so launchers hide the entry", try_exec), Some(fix), &name);
+            }
+        }
+
+        if !entry.get_path().is_empty() && !Path::new(entry.get_path()).is_dir() {
+            finding(format!("Working directory \"{}\" doesn't exist", entry.get_path()), Some(Fix::ClearField("Path")), &name);
+        }
+
+        if !entry.get_icon().is_empty() && icon_theme::find_icon(context, entry.get_icon(), 48).is_none() {
+            finding(format!("Icon \"{}\" couldn't be found", entry.get_icon()), Some(Fix::ClearField("Icon")), &name);
+        }
+
+        // Registering installs a copy, which goes stale if the file is changed without reinstalling it
+        match fs::read(context.registered_path(&filename)) {
+            Ok(contents) => if Some(contents) != fs::read(&path).ok() {
+                finding(String::from("The copy registered with the menu is out of date"), Some(Fix::Reregister), &name);
+            },
+            Err(_) => finding(String::from("Isn't registered with the menu"), Some(Fix::Reregister), &name)
+        }
+
+        parsed.push((path.clone(), entry));
+    }
+
+    findings.extend(duplicates(&parsed));
+
+    // Unregistered entries were already caught above, this is the other direction
+    for orphan in reconcile::find_orphans(context)? {
+        if let Orphan::Unbacked(path) = orphan {
+            findings.push(Finding {
+                name: file_name(&path),
+                file: path,
+                problem: String::from("Is in the menu but mkdesktop has no entry for it"),
+                fix: Some(Fix::Unregister),
+            });
+        }
+    }
+
+    for menu in menu::read_menus(context)? {
+        for id in menu.get_entries() {
+            if !context.data_dir().join(id).exists() && !context.applications_dir().join(id).exists() {
+                findings.push(Finding {
+                    file: menu.filepath(context),
+                    name: menu.get_name().to_string(),
+                    problem: format!("Includes {}, which doesn't exist anymore", id),
+                    fix: Some(Fix::LeaveMenu(id.clone())),
+                });
+            }
+        }
+    }
+
+    // Overrides don't follow their originals, so an update can leave them behind
+    for (id, source) in overrides::list(context) {
+        let problem = match overrides::upstream(context, &id) {
+            Some(Upstream::Changed) => format!("Overrides {:?}, which has changed since (run `mkdesktop override` again to start over from it)", source),
+            Some(Upstream::Removed) => format!("Overrides {:?}, which isn't installed anymore (`mkdesktop override --reset` removes it)", source),
+            _ => continue
+        };
+        findings.push(Finding {
+            file: overrides::override_path(context, &id),
+            name: id,
+            problem: problem,
+            fix: None,
+        });
+    }
+
+    Ok(findings)
+}
+
+
+/// Entries sharing a Name, or whose names turn into the same desktop file ID.
+/// One of each group is kept, preferring the file that's named the way mkdesktop would name it.
+fn duplicates(entries: &[(PathBuf, DesktopEntry)]) -> Vec<Finding> {
+    let mut result = Vec::new();
+    let mut by_name: HashMap<&str, &Path> = HashMap::new();
+    let mut by_id: HashMap<String, &Path> = HashMap::new();
+
+    let mut entries: Vec<&(PathBuf, DesktopEntry)> = entries.iter().collect();
+    entries.sort_by_key(|(path, entry)| file_name(path) != entry.filename());
+
+    for (path, entry) in entries {
+        if let Some(first) = by_name.get(entry.get_name()) {
+            result.push(Finding {
+                file: path.clone(),
+                name: entry.get_name().to_string(),
+                problem: format!("Has the same name as {}", file_name(first)),
+                fix: Some(Fix::Remove),
+            });
+            continue;
+        }
+        by_name.insert(entry.get_name(), path);
+
+        if let Some(first) = by_id.get(&entry.filename()) {
+            result.push(Finding {
+                file: path.clone(),
+                name: entry.get_name().to_string(),
+                problem: format!("Saves to {}, the same file as the entry in {}", entry.filename(), file_name(first)),
+                fix: Some(Fix::Remove),
+            });
+            continue;
+        }
+        by_id.insert(entry.filename(), path);
+    }
+
+    result
+}
+
+
+/// A program that was moved or reinstalled elsewhere -- same filename, but found on $PATH
+fn relink_candidate(program: &str) -> Option<PathBuf> {
+    if !program.contains('/') { return None }
+    let name = Path::new(program).file_name()?.to_str()?;
+    desktop::find_program(name)
+}
+
+
+pub fn apply(context: &Context, finding: &Finding) -> io::Result<()> {
+    let fix = match &finding.fix {
+        Some(fix) => fix,
+        None      => return Ok(())
+    };
+
+    match fix {
+        Fix::Remove => {
+            // Only unregister when it's ours to unregister
+            let filename = file_name(&finding.file);
+            if context.registered_path(&filename).exists() {
+                context.unregister(&filename)?;
+            }
+            fs::remove_file(&finding.file)
+        }
+        Fix::Reregister => context.register(&finding.file),
+        Fix::Unregister => reconcile::repair(context, &Orphan::Unbacked(finding.file.clone()), false),
+        Fix::LeaveMenu(id) => {
+            let mut menu = Menu::parse(&fs::read_to_string(&finding.file)?)?;
+            menu.remove(id);
+            menu.save(context)
+        }
+        Fix::ClearField(key) => {
+            let mut keyfile = KeyFile::open(&finding.file)?;
+            keyfile.unset(DESKTOP_ENTRY_GROUP, key);
+            rewrite(context, &finding.file, &keyfile)
+        }
+        Fix::Relink(key, program) => {
+            let mut keyfile = KeyFile::open(&finding.file)?;
+            let program = program.to_string_lossy();
+
+            if *key == "Exec" {
+                let mut exec = Exec::parse(&keyfile.get_string(DESKTOP_ENTRY_GROUP, "Exec").unwrap_or_default());
+                exec.set_program(&program);
+                keyfile.set(DESKTOP_ENTRY_GROUP, "Exec", &exec.to_string());
+            }
+            else {
+                keyfile.set(DESKTOP_ENTRY_GROUP, key, &program);
+            }
+            rewrite(context, &finding.file, &keyfile)
+        }
+    }
+}
+
+
+fn rewrite(context: &Context, path: &Path, keyfile: &KeyFile) -> io::Result<()> {
+    fs::write(path, keyfile.to_bytes())?;
+    context.register(path)
+}
+
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+
+    fn fixes_for(findings: &[Finding], name: &str) -> Vec<Option<Fix>> {
+        findings.iter().filter(|f| f.name == name).map(|f| f.fix.clone()).collect()
+    }
+
+    #[test]
+    fn moved_programs_are_relinked_and_missing_things_cleared() {
+        let root = TestDir::new("doctor");
+        let context = root.context();
+        let sh = desktop::find_program("sh").expect("sh is needed for this test");
+
+        let tool = context.data_dir().join("mkdesktop-Tool.desktop");
+        fs::write(&tool, "[Desktop Entry]\nType=Application\nName=Tool\nExec=/nowhere/sh -c true\nTryExec=/nowhere/sh\n\
+                          Path=/nowhere\nIcon=mkdesktop-no-such-icon\n").unwrap();
+        fs::write(context.data_dir().join("mkdesktop-Other.desktop"), "[Desktop Entry]\nType=Application\nName=Other\nExec=sh\n\
+                                                                       TryExec=mkdesktop-no-such-program\n").unwrap();
+
+        let findings = examine(&context).unwrap();
+        assert_eq!(fixes_for(&findings, "Tool"), vec![
+            Some(Fix::Relink("Exec", sh.clone())),
+            Some(Fix::Relink("TryExec", sh.clone())),
+            Some(Fix::ClearField("Path")),
+            Some(Fix::ClearField("Icon")),
+            Some(Fix::Reregister),
+        ]);
+        assert_eq!(fixes_for(&findings, "Other"), vec![Some(Fix::ClearField("TryExec")), Some(Fix::Reregister)]);
+
+        for finding in &findings {
+            apply(&context, finding).unwrap();
+        }
+        let keyfile = KeyFile::open(&tool).unwrap();
+        assert_eq!(keyfile.get_string(DESKTOP_ENTRY_GROUP, "Exec"), Some(format!("{} -c true", sh.display())));
+        assert_eq!(keyfile.get_string(DESKTOP_ENTRY_GROUP, "TryExec"), Some(sh.to_string_lossy().to_string()));
+        assert_eq!(keyfile.get_string(DESKTOP_ENTRY_GROUP, "Path"), None);
+        assert_eq!(keyfile.get_string(DESKTOP_ENTRY_GROUP, "Icon"), None);
+        let other = KeyFile::open(&context.data_dir().join("mkdesktop-Other.desktop")).unwrap();
+        assert_eq!(other.get_string(DESKTOP_ENTRY_GROUP, "TryExec"), None);
+    }
+
+    #[test]
+    fn duplicates_keep_the_canonically_named_file() {
+        let entry = |name: &str| DesktopEntry::new(name, "", "", Exec::parse("tool"), "", "");
+        let entries = vec![
+            (PathBuf::from("copy-of-tool.desktop"), entry("Tool")),
+            (PathBuf::from("mkdesktop-Tool.desktop"), entry("Tool")),
+            (PathBuf::from("mkdesktop-Test-Tool.desktop"), entry("Test Tool")),
+            (PathBuf::from("other.desktop"), entry("Test.Tool")),
+        ];
+
+        let findings = duplicates(&entries);
+        let found: Vec<(String, String)> = findings.iter().map(|f| (file_name(&f.file), f.problem.clone())).collect();
+        assert_eq!(found, vec![
+            (String::from("copy-of-tool.desktop"), String::from("Has the same name as mkdesktop-Tool.desktop")),
+            (String::from("other.desktop"), String::from("Saves to mkdesktop-Test-Tool.desktop, the same file as the entry in mkdesktop-Test-Tool.desktop")),
+        ]);
+        assert!(findings.iter().all(|f| f.fix == Some(Fix::Remove)));
+    }
+}
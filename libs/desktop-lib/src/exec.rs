@@ -0,0 +1,270 @@
+use std::fmt;
+use std::collections::BTreeMap;
+
+
+/// Characters that force an argument to be quoted, per the desktop entry spec
+const RESERVED: &[char] = &[
+    ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(', ')', '`',
+];
+
+
+/// An Exec= command line split into the program and its arguments.
+/// Field codes like %f are kept as ordinary arguments; any other % is a literal one,
+/// written out as %% and read back as a single %.
+/// Environment variables are written as an `env VAR=value ...` prefix, since Exec has no other way to set them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exec {
+    env: BTreeMap<String, String>,
+    program: String,
+    args: Vec<String>,
+}
+
+impl Exec {
+    pub fn new(program: &str, args: Vec<String>) -> Exec {
+        Exec {
+            env: BTreeMap::new(),
+            program: program.to_string(),
+            args: args,
+        }
+    }
+
+
+    /// Splits an Exec value the way launchers do: on whitespace, with double quotes grouping
+    /// and \", \`, \$ and \\ escaping inside quotes. An unterminated quote runs to the end.
+    /// Outside quotes a backslash escapes the next character too, like GLib allows
+    /// (Wine writes paths as `C:\\Program\ Files`).
+    pub fn parse(command: &str) -> Exec {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut in_word = false;
+        let mut in_quotes = false;
+        let mut chars = command.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    in_quotes = !in_quotes;
+                    in_word = true;
+                }
+                '\\' => {
+                    word.push(chars.next().unwrap_or('\\'));
+                    in_word = true;
+                }
+                c if c.is_whitespace() && !in_quotes => {
+                    if in_word { words.push(word.clone()) }
+                    word.clear();
+                    in_word = false;
+                }
+                c => {
+                    word.push(c);
+                    in_word = true;
+                }
+            }
+        }
+        if in_word { words.push(word) }
+
+        for word in words.iter_mut().filter(|word| !is_field_code(word)) {
+            *word = word.replace("%%", "%");
+        }
+
+        // Pull `env A=1 B=2 program` apart, but leave env alone if it's used with options (env -i ...)
+        let mut env = BTreeMap::new();
+        if words.first().map(String::as_str) == Some("env") {
+            let assignments = words[1..].iter().take_while(|word| parse_env_var(word).is_some()).count();
+            let rest = words.get(1 + assignments);
+
+            if assignments > 0 && rest.map(|word| !word.starts_with('-')).unwrap_or(false) {
+                for word in words.drain(0..1 + assignments).skip(1) {
+                    let (key, value) = parse_env_var(&word).unwrap();
+                    env.insert(key, value);
+                }
+            }
+        }
+
+        let mut words = words.into_iter();
+        Exec {
+            env: env,
+            program: words.next().unwrap_or_default(),
+            args: words.collect(),
+        }
+    }
+
+
+    pub fn env(&self) -> &BTreeMap<String, String> { &self.env }
+    pub fn program(&self) -> &str { &self.program }
+    pub fn args(&self) -> &[String] { &self.args }
+
+    pub fn is_empty(&self) -> bool { self.program.is_empty() }
+
+
+    pub fn set_program(&mut self, program: &str) {
+        self.program = program.to_string();
+    }
+
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn set_env(&mut self, env: BTreeMap<String, String>) {
+        self.env = env;
+    }
+
+    pub fn set_env_var(&mut self, key: &str, value: &str) {
+        self.env.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove_env_var(&mut self, key: &str) -> bool {
+        self.env.remove(key).is_some()
+    }
+
+
+    /// The arguments a launcher would pass when nothing is being opened:
+    /// the field codes are dropped.
+    pub fn launch_args(&self) -> Vec<String> {
+        self.args.iter()
+            .filter(|arg| !is_field_code(arg))
+            .cloned()
+            .collect()
+    }
+
+
+    /// The arguments a launcher would pass when opening `target` (a URL or a file path)
+    pub fn open_args(&self, target: &str) -> Vec<String> {
+        self.args.iter()
+            .filter_map(|arg| match arg.as_str() {
+                "%u" | "%U" | "%f" | "%F" => Some(target.to_string()),
+                _ if is_field_code(arg)   => None,
+                _                         => Some(arg.clone())
+            })
+            .collect()
+    }
+
+
+    /// Whether launchers will hand the program URLs (rather than just local files, or nothing)
+    pub fn takes_urls(&self) -> bool {
+        self.args.iter().any(|arg| arg == "%u" || arg == "%U")
+    }
+
+    /// Makes the command take a URL: %f/%F become %u/%U, and %u is added if there's neither.
+    /// Returns whether anything changed.
+    pub fn ensure_takes_urls(&mut self) -> bool {
+        if self.takes_urls() { return false }
+
+        for arg in self.args.iter_mut() {
+            match arg.as_str() {
+                "%f" => { *arg = String::from("%u"); return true }
+                "%F" => { *arg = String::from("%U"); return true }
+                _    => {}
+            }
+        }
+        self.args.push(String::from("%u"));
+        true
+    }
+}
+
+impl fmt::Display for Exec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.program.is_empty() { return Ok(()) }
+
+        if !self.env.is_empty() {
+            write!(f, "env ")?;
+            for (key, value) in &self.env {
+                write!(f, "{} ", quote(&format!("{}={}", key, value)))?;
+            }
+        }
+
+        write!(f, "{}", quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Splits "KEY=value", making sure KEY is something a shell would accept as a variable name
+pub fn parse_env_var(assignment: &str) -> Option<(String, String)> {
+    let mut parts = assignment.splitn(2, '=');
+    let key = parts.next()?;
+    let value = parts.next()?;
+
+    let valid_start = key.chars().next().map(|c| c == '_' || c.is_ascii_alphabetic()).unwrap_or(false);
+    if !valid_start || !key.chars().all(|c| c == '_' || c.is_ascii_alphanumeric()) { return None }
+
+    Some((key.to_string(), value.to_string()))
+}
+
+
+/// %f, %F, %u, %U and friends (including the deprecated ones)
+pub fn is_field_code(arg: &str) -> bool {
+    let mut chars = arg.chars();
+    chars.next() == Some('%')
+        && chars.next().map(|c| "fFuUdDnNickvm".contains(c)).unwrap_or(false)
+        && chars.next().is_none()
+}
+
+
+/// Quotes `word` for Exec, doubling any % unless the whole word is a field code
+fn quote(word: &str) -> String {
+    let word = &if is_field_code(word) { word.to_string() } else { word.replace('%', "%%") };
+    if !word.is_empty() && !word.contains(RESERVED) && !word.contains(char::is_whitespace) { return word.to_string() }
+
+    let mut result = String::from("\"");
+    for c in word.chars() {
+        if c == '"' || c == '`' || c == '$' || c == '\\' { result.push('\\') }
+        result.push(c);
+    }
+    result.push('"');
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Exec;
+
+    #[test]
+    fn exec_round_trips_quoted_arguments() {
+        let exec = Exec::parse(r#"/opt/My App/run "--title=Hello \"World\"" --private-window %U"#);
+        assert_eq!(exec.program(), "/opt/My");
+        assert_eq!(exec.args(), &["App/run", "--title=Hello \"World\"", "--private-window", "%U"]);
+
+        let exec = Exec::new("/opt/My App/run", vec![String::from("it's"), String::from("%f"), String::from("100%")]);
+        assert_eq!(exec.to_string(), r#""/opt/My App/run" "it's" %f 100%%"#);
+        assert_eq!(Exec::parse(&exec.to_string()), exec);
+        assert_eq!(exec.launch_args(), vec!["it's", "100%"]);
+        assert_eq!(exec.open_args("/tmp/a b"), vec!["it's", "/tmp/a b", "100%"]);
+
+        let exec = Exec::parse("convert -resize 50%% %f");
+        assert_eq!(exec.args(), &["-resize", "50%", "%f"]);
+        assert_eq!(exec.to_string(), "convert -resize 50%% %f");
+
+        assert!(Exec::parse("   ").is_empty());
+    }
+
+    #[test]
+    fn exec_can_be_made_to_take_urls() {
+        let mut exec = Exec::parse("editor --new-window %F");
+        assert!(exec.ensure_takes_urls());
+        assert_eq!(exec.to_string(), "editor --new-window %U");
+        assert!(!exec.ensure_takes_urls());
+
+        let mut exec = Exec::parse("ourapp");
+        assert!(exec.ensure_takes_urls());
+        assert_eq!(exec.open_args("ourapp://open?id=1"), vec!["ourapp://open?id=1"]);
+    }
+
+    #[test]
+    fn exec_parses_env_prefix() {
+        let exec = Exec::parse(r#"env GDK_SCALE=2 "WINEPREFIX=/home/me/My Games" wine game.exe"#);
+        assert_eq!(exec.env().get("GDK_SCALE").map(String::as_str), Some("2"));
+        assert_eq!(exec.env().get("WINEPREFIX").map(String::as_str), Some("/home/me/My Games"));
+        assert_eq!(exec.program(), "wine");
+        assert_eq!(exec.to_string(), r#"env GDK_SCALE=2 "WINEPREFIX=/home/me/My Games" wine game.exe"#);
+
+        // Options to env itself aren't something we can model, so that stays a plain command
+        let exec = Exec::parse("env -i A=1 prog");
+        assert_eq!(exec.program(), "env");
+        assert!(exec.env().is_empty());
+    }
+}
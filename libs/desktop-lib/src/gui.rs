@@ -1,4 +1,3 @@
-extern crate clap;
 extern crate gtk;
 extern crate gdk_pixbuf;
 extern crate glib;
@@ -7,25 +6,37 @@ extern crate inotify;
 
 use gio::prelude::*;
 use gtk::prelude::*;
-use gtk::{ApplicationWindow, Window, Dialog, HeaderBar, FileChooserButton, Image, Label, Button, Continue};
+use gtk::{ApplicationWindow, Window, Dialog, HeaderBar, FileChooserButton, Image, Label, Button, Continue, Inhibit};
 use gdk_pixbuf::Pixbuf;
 use glib::GString;
 use glib::MainContext;
+use glib::ToValue;
 
 use inotify::{EventMask, WatchMask, Inotify};
 
 use std::io;
-use std::process::{self, Command};
-use std::path::Path;
+use std::process;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::thread;
 use std::rc::Rc;
-use std::cell::Cell;
-
-use super::desktop::{DesktopEntry, data_dir, read_desktop_files};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use super::desktop::{self, DesktopEntry, EntryType, read_desktop_files};
+use super::context::Context;
+use super::menu;
+use super::autostart;
+use super::mime::{self, MimeApps};
+use super::icons;
+use super::icon_theme::{self, IconSearch};
+use super::icon_sources;
+use super::categories;
+use super::exec::{self, Exec};
 
 include!(concat!(env!("OUT_DIR"), "/new-entry.glade.rs"));
 include!(concat!(env!("OUT_DIR"), "/error-dialog.glade.rs"));
+include!(concat!(env!("OUT_DIR"), "/icon-picker.glade.rs"));
 
 include!(concat!(env!("OUT_DIR"), "/list-entries.glade.rs"));
 include!(concat!(env!("OUT_DIR"), "/entry.glade.rs"));
@@ -50,6 +61,15 @@ fn set_icon_preview<P: AsRef<Path>>(image: &Image, preview_filename: P, size: i3
 }
 
 
+/// Like set_icon_preview, but takes whatever is in an entry's Icon= (a path or a theme icon name)
+fn set_entry_icon_preview(context: &Context, image: &Image, icon: &str, size: i32) {
+    match icon_theme::find_icon(context, icon, size) {
+        Some(path) => set_icon_preview(image, path, size),
+        None       => image.set_visible(false)
+    }
+}
+
+
 fn error_dialog(message: &str) -> Dialog {
     let builder = gtk::Builder::new_from_string(ERROR_DIALOG_GLADE);
 
@@ -65,7 +85,275 @@ fn error_dialog(message: &str) -> Dialog {
 }
 
 
+/// Lets the user search for an icon in their icon theme. Returns the icon name, or None on cancel.
+fn icon_theme_picker<W: IsA<Window>>(parent: &W, context: &Context) -> Option<String> {
+    let builder = gtk::Builder::new_from_string(ICON_PICKER_GLADE);
+
+    let dialog:  Dialog           = builder.get_object("icon_picker_dialog").unwrap();
+    let search:  gtk::SearchEntry = builder.get_object("icon_search_entry").unwrap();
+    let flowbox: gtk::FlowBox     = builder.get_object("icons_flowbox").unwrap();
+
+    dialog.set_transient_for(Some(parent));
+
+    // Flowbox children are in the same order as this list, so a child's index is its name's index
+    let icon_names = Rc::new(IconSearch::new(context).list_icon_names(Some("Applications")));
+    for name in icon_names.iter() {
+        let image = Image::new_from_icon_name(Some(name), gtk::IconSize::Dialog);
+        image.set_tooltip_text(Some(name));
+        flowbox.add(&image);
+    }
+
+    let search_text = Rc::new(RefCell::new(String::new()));
+
+    let filter_names = icon_names.clone();
+    let filter_text = search_text.clone();
+    flowbox.set_filter_func(Some(Box::new(move |child: &gtk::FlowBoxChild| {
+        match filter_names.get(child.get_index() as usize) {
+            Some(name) => name.to_lowercase().contains(filter_text.borrow().as_str()),
+            None       => false
+        }
+    })));
+
+    let filtered_flowbox = flowbox.clone();
+    search.connect_search_changed(move |entry| {
+        let text = match entry.get_text() {
+            Some(text) => text.to_lowercase(),
+            None       => String::new()
+        };
+        search_text.replace(text);
+        filtered_flowbox.invalidate_filter();
+    });
+
+    // Double-clicking an icon picks it
+    let activated_dialog = dialog.clone();
+    flowbox.connect_child_activated(move |_, _| {
+        activated_dialog.response(gtk::ResponseType::Ok);
+    });
+
+    dialog.show_all();
+    let response = gtk::ResponseType::from(dialog.run());
+
+    let selected = if response == gtk::ResponseType::Ok {
+        match flowbox.get_selected_children().first() {
+            Some(child) => icon_names.get(child.get_index() as usize).cloned(),
+            None        => None
+        }
+    }
+    else {
+        None
+    };
+
+    dialog.destroy();
+    selected
+}
+
+
+/// Fills the suggested icons dropdown with whatever icon_sources finds around the command's executable.
+/// Looking can mean reading a big .exe or unpacking an AppImage, so it happens off the main thread.
+fn refresh_icon_suggestions(combo: &gtk::ComboBoxText, label: &Label, command: &str, icons: IconSearch, cache: PathBuf) {
+    combo.remove_all();
+    combo.set_visible(false);
+    label.set_visible(false);
+
+    let exec = Exec::parse(command);
+    if exec.is_empty() { return }
+    let program = desktop::find_program(exec.program()).unwrap_or_else(|| PathBuf::from(exec.program()));
+
+    let (tx, rx) = MainContext::channel(glib::PRIORITY_DEFAULT);
+    thread::spawn(move || {
+        let _ = tx.send(icon_sources::suggest_icons(&program, &icons, &cache));
+    });
+
+    let combo = combo.clone();
+    let label = label.clone();
+    rx.attach(None, move |suggestions: Vec<icon_sources::IconSuggestion>| {
+        // An earlier, slower search may have filled it in the meantime
+        combo.remove_all();
+        for suggestion in &suggestions {
+            let description = format!("{} (from {})", suggestion.icon, suggestion.source.describe());
+            combo.append(Some(&suggestion.icon), &description);
+        }
+
+        combo.set_visible(!suggestions.is_empty());
+        label.set_visible(!suggestions.is_empty());
+        Continue(false)
+    });
+}
+
+
+/// The entry type picked in the editor's type combo box, Application if there's nothing picked
+fn selected_type(combo: &gtk::ComboBoxText) -> EntryType {
+    combo.get_active_id()
+        .and_then(|id| EntryType::parse(&id))
+        .unwrap_or(EntryType::Application)
+}
+
+
+/// Shows the editor rows that apply to `entry_type` and hides the rest
+fn show_fields_for_type(builder: &gtk::Builder, entry_type: EntryType) {
+    const APPLICATION_ONLY: [&str; 11] = [
+        "exec_label", "exec_entry", "path_label", "path_chooser", "categories_label", "categories_box", "env_label", "env_box",
+        "autostart_label", "autostart_switch", "mime_box",
+    ];
+    const LINK_ONLY: [&str; 2] = ["url_label", "url_entry"];
+
+    let set_visible = |id: &str, visible: bool| {
+        let widget: gtk::Widget = builder.get_object(id).expect("Editor widget is missing");
+        widget.set_visible(visible);
+    };
+
+    for id in &APPLICATION_ONLY { set_visible(id, entry_type == EntryType::Application) }
+    for id in &LINK_ONLY        { set_visible(id, entry_type == EntryType::Link) }
+
+    // Suggestions come from the command, so they're shown again once there's one to look at
+    if entry_type != EntryType::Application {
+        set_visible("icon_suggestions_label", false);
+        set_visible("icon_suggestions_combo", false);
+    }
+}
+
+
+/// Rows of the editor's environment table, skipping ones with no variable name
+fn env_from_store(store: &gtk::ListStore) -> Result<Vec<(String, String)>, String> {
+    let mut result = Vec::new();
+
+    let iter = match store.get_iter_first() {
+        Some(iter) => iter,
+        None       => return Ok(result)
+    };
+    loop {
+        let key: String = store.get_value(&iter, 0).get().unwrap_or_default();
+        let value: String = store.get_value(&iter, 1).get().unwrap_or_default();
+
+        if !key.is_empty() {
+            match exec::parse_env_var(&format!("{}={}", key, value)) {
+                Some(pair) => result.push(pair),
+                None => return Err(format!("\"{}\" isn't a valid environment variable name", key))
+            }
+        }
+
+        if !store.iter_next(&iter) { break }
+    }
+
+    Ok(result)
+}
+
+
+/// Rows of the editor's file type table, with whether each should default to this entry
+fn mime_types_from_store(store: &gtk::ListStore) -> Vec<(String, bool)> {
+    let mut result = Vec::new();
+
+    let iter = match store.get_iter_first() {
+        Some(iter) => iter,
+        None       => return result
+    };
+    loop {
+        let mime_type: String = store.get_value(&iter, 0).get().unwrap_or_default();
+        let is_default: bool = store.get_value(&iter, 1).get().unwrap_or(false);
+        result.push((mime_type, is_default));
+
+        if !store.iter_next(&iter) { break }
+    }
+
+    result
+}
+
+
+/// Makes mimeapps.list match the Default column for the entry, including types taken out of the table
+fn apply_mime_defaults(context: &Context, desktop_id: &str, rows: &[(String, bool)]) -> io::Result<()> {
+    let mut list = MimeApps::open(context)?;
+
+    for mime_type in list.mime_types_for(mime::DEFAULT_APPLICATIONS, desktop_id) {
+        if !rows.iter().any(|(t, is_default)| *t == mime_type && *is_default) {
+            list.unset_default(&mime_type, desktop_id);
+        }
+    }
+    for (mime_type, is_default) in rows {
+        if *is_default && list.default_for(mime_type).as_ref().map(String::as_str) != Some(desktop_id) {
+            list.set_default(mime_type, desktop_id);
+        }
+    }
+
+    list.save(context)
+}
+
+
+/// Adds a removable chip for the category (unless it's already there)
+fn add_category_chip(chips: &gtk::FlowBox, selected: &Rc<RefCell<Vec<String>>>, warning: &Label, category: &str) {
+    if selected.borrow().iter().any(|c| c == category) { return }
+    selected.borrow_mut().push(category.to_string());
+
+    let chip = gtk::Box::new(gtk::Orientation::Horizontal, 2);
+    let label = Label::new(Some(category));
+    let remove_button = Button::new_from_icon_name(Some("window-close-symbolic"), gtk::IconSize::Menu);
+    remove_button.set_relief(gtk::ReliefStyle::None);
+    remove_button.set_tooltip_text(Some("Remove category"));
+
+    chip.pack_start(&label, false, false, 0);
+    chip.pack_start(&remove_button, false, false, 0);
+
+    let removed_category = category.to_string();
+    let removed_from = selected.clone();
+    let removed_warning = warning.clone();
+    remove_button.connect_clicked(move |button| {
+        removed_from.borrow_mut().retain(|c| *c != removed_category);
+        update_category_warning(&removed_warning, &removed_from.borrow());
+
+        // button -> chip box -> FlowBoxChild
+        if let Some(flowbox_child) = button.get_parent().and_then(|chip| chip.get_parent()) {
+            flowbox_child.destroy();
+        }
+    });
+
+    chips.add(&chip);
+    chip.show_all();
+
+    update_category_warning(warning, &selected.borrow());
+}
+
+
+fn update_category_warning(warning: &Label, selected: &[String]) {
+    let problems: Vec<String> = categories::validate(selected)
+        .iter()
+        .map(|problem| problem.describe())
+        .collect();
+
+    warning.set_text(&problems.join("\n"));
+    warning.set_visible(!problems.is_empty());
+}
+
+
+/// Completion for every registered category name
+fn category_completion() -> gtk::EntryCompletion {
+    let store = gtk::ListStore::new(&[gtk::Type::String]);
+    for name in categories::all_names() {
+        store.insert_with_values(None, &[0], &[&name]);
+    }
+
+    let completion = gtk::EntryCompletion::new();
+    completion.set_model(Some(&store));
+    completion.set_text_column(0);
+    completion.set_inline_completion(true);
+    completion
+}
+
+
+/// Completion for every MIME type shared-mime-info knows about
+fn mime_completion(context: &Context) -> gtk::EntryCompletion {
+    let store = gtk::ListStore::new(&[gtk::Type::String]);
+    for name in mime::known_mime_types(context) {
+        store.insert_with_values(None, &[0], &[&name]);
+    }
+
+    let completion = gtk::EntryCompletion::new();
+    completion.set_model(Some(&store));
+    completion.set_text_column(0);
+    completion
+}
+
+
 fn setup_list_ui(
+    context: &Rc<Context>,
     entries_result: io::Result<Vec<DesktopEntry>>,
     entries_container: &gtk::Container,
     deleted_entry: Rc<Cell<Option<DesktopEntry>>>,
@@ -114,34 +402,30 @@ fn setup_list_ui(
         categories_label.set_text(entry.get_categories());
         comment_label.set_text(entry.get_comment());
         name_label.set_text(entry.get_name());
-        exec_label.set_text(entry.get_exec());
+        exec_label.set_text(&entry.get_exec());
         path_label.set_text(entry.get_path());
 
-        set_icon_preview(&icon, entry.get_icon(), ICON_PREVIEW_SIZE);
+        set_entry_icon_preview(context, &icon, entry.get_icon(), ICON_PREVIEW_SIZE);
 
         // Launch button functionality
         let entry_to_launch = entry.clone();
         launch_entry.connect_clicked(move |_| {
-            match Command::new("sh")
-                .current_dir(entry_to_launch.get_path())
-                .arg("-c")
-                .arg(entry_to_launch.get_exec())
-                .spawn() {
-                    Ok(_) => {}
-                    Err(launch_error) => {
-                        let dialog = error_dialog(launch_error.description());
-                        dialog.show_all();
-                        dialog.run();
-                    }
+            match entry_to_launch.launch() {
+                Ok(_) => {}
+                Err(launch_error) => {
+                    let dialog = error_dialog(launch_error.description());
+                    dialog.show_all();
+                    dialog.run();
                 }
-            
+            }
         });
 
         // Delete button functionality
         let entry_to_delete = entry.clone();
         let saved_entry = deleted_entry.clone();
+        let delete_context = context.clone();
         delete_entry.connect_clicked(move |_| {
-            match entry_to_delete.delete() {
+            match entry_to_delete.delete(&delete_context) {
                 Ok(()) => {}
                 Err(error) => {
                     let dialog = error_dialog(error.description());
@@ -158,9 +442,10 @@ fn setup_list_ui(
 
         // Edit button functionality
         let entry_to_edit = entry.clone();
+        let edit_context = context.clone();
         edit_entry.connect_clicked(move |widget| {
             let entry = entry_to_edit.clone();
-            editor(&app_of(widget), Some(entry));
+            editor(&app_of(widget), edit_context.clone(), Some(entry));
         });
 
         entries_container.add(&entry_widget);
@@ -168,26 +453,56 @@ fn setup_list_ui(
 }
 
 
-pub fn start(entry: Option<io::Result<DesktopEntry>>, arg_matches: clap::ArgMatches) {
+/// Lists every menu with its entries under it. Rows hold an icon name, a display name and the file behind the row.
+fn fill_menu_tree(context: &Context, store: &gtk::TreeStore, tree: &gtk::TreeView) {
+    store.clear();
+
+    let menus = match menu::read_menus(context) {
+        Ok(m) => m,
+        Err(error) => {
+            eprintln!("Couldn't read menus - {}", error);
+            return;
+        }
+    };
+    let entries = read_desktop_files(context).unwrap_or_default();
+
+    for m in menus {
+        let parent = store.insert_with_values(None, None, &[0, 1, 2], &[&"folder", &m.get_name(), &m.filename()]);
+
+        for id in m.get_entries() {
+            let (icon, name) = match entries.iter().find(|e| &e.filename() == id) {
+                Some(entry) => (entry.get_icon().to_string(), entry.get_name().to_string()),
+                None        => (String::from("dialog-warning"), format!("{} (missing)", id))
+            };
+            store.insert_with_values(Some(&parent), None, &[0, 1, 2], &[&icon, &name, id]);
+        }
+    }
+
+    tree.expand_all();
+}
+
+
+/// Opens the editor for a new entry if `new` is set, the editor for `entry` if one was selected,
+/// and the list of entries otherwise.
+pub fn start(context: Context, entry: Option<io::Result<DesktopEntry>>, new: bool) {
     init();
 
+    let context = Rc::new(context);
+
     let app = gtk::Application::new(Some("me.nigelbaillie.mkdesktop"), Default::default())
         .expect("Failed to create GTK Application");
     
     glib::set_application_name("mkdesktop");
     gtk::Window::set_default_icon_name("mkdesktop");
     
-    if arg_matches.is_present("new") {
-        app.connect_activate(|app| editor(app, None));
-    }
-    else if arg_matches.is_present("status") || !arg_matches.is_present("FILE_OR_ENTRY") {
-        app.connect_activate(|app| index(app, read_desktop_files()));
+    if new {
+        app.connect_activate(move |app| editor(app, context.clone(), None));
     }
     else {
         match entry {
             Some(result) => match result {
                 Ok(e) => {
-                    app.connect_activate(move |app| editor(app, Some(e.clone())));
+                    app.connect_activate(move |app| editor(app, context.clone(), Some(e.clone())));
                 }
                 Err(error_message) => {
                     let dialog = error_dialog(error_message.description());
@@ -196,7 +511,7 @@ pub fn start(entry: Option<io::Result<DesktopEntry>>, arg_matches: clap::ArgMatc
                     process::exit(30);
                 }
             },
-            None => { app.connect_activate(|app| index(app, read_desktop_files())); }
+            None => { app.connect_activate(move |app| index(app, context.clone(), read_desktop_files(&context))); }
         }
     }
 
@@ -204,7 +519,7 @@ pub fn start(entry: Option<io::Result<DesktopEntry>>, arg_matches: clap::ArgMatc
 }
 
 
-pub fn index(app: &gtk::Application, entries_result: io::Result<Vec<DesktopEntry>>)  {
+pub fn index(app: &gtk::Application, context: Rc<Context>, entries_result: io::Result<Vec<DesktopEntry>>)  {
     /////////////////////////////////////////////////////////
     //
     //           CREATE/EXTRACT WIDGETS OF INTEREST
@@ -216,28 +531,58 @@ pub fn index(app: &gtk::Application, entries_result: io::Result<Vec<DesktopEntry
     let entries_container: gtk::Container = builder.get_object("entries_container").unwrap();
     let new_entry: Button = builder.get_object("new_entry_button").unwrap();
     let undo: Button = builder.get_object("undo_button").unwrap();
+    let menu_store: gtk::TreeStore = builder.get_object("menu_store").unwrap();
+    let menu_tree: gtk::TreeView = builder.get_object("menu_tree").unwrap();
 
     let deleted_entry: Rc<Cell<Option<DesktopEntry>>> = Rc::new(Cell::new(None));
 
     window.set_application(Some(app));
 
-    new_entry.connect_clicked(|w| {
-        editor(&app_of(w), None);
+    let new_entry_context = context.clone();
+    new_entry.connect_clicked(move |w| {
+        editor(&app_of(w), new_entry_context.clone(), None);
     });
 
     let deleted_entry_to_restore = deleted_entry.clone();
+    let undo_context = context.clone();
     undo.connect_clicked(move |button| {
         let entry = deleted_entry_to_restore.clone();
 
         match entry.take() {
-            Some(entry) => entry.write_to_apps_dir().unwrap(),
+            Some(entry) => entry.write_to_apps_dir(&undo_context).unwrap(),
             None        => error_out("BUG: Undo button was present when it shouldn't have been.")
         }
 
         button.set_visible(false);
     });
 
-    setup_list_ui(entries_result, &entries_container, deleted_entry.clone(), &undo);
+    setup_list_ui(&context, entries_result, &entries_container, deleted_entry.clone(), &undo);
+    fill_menu_tree(&context, &menu_store, &menu_tree);
+
+    // Activating an entry in the menu tree edits it
+    let menu_tree_context = context.clone();
+    menu_tree.connect_row_activated(move |tree, path, _| {
+        let store = match tree.get_model() { Some(s) => s, None => return };
+        let iter = match store.get_iter(path) { Some(i) => i, None => return };
+        if store.iter_parent(&iter).is_none() { return }
+
+        let filename: String = store.get_value(&iter, 2).get().unwrap_or_default();
+        match desktop::select(&menu_tree_context, &filename) {
+            Ok(entry) => editor(&app_of(tree), menu_tree_context.clone(), Some(entry)),
+            Err(error) => {
+                let dialog = error_dialog(error.description());
+                dialog.show_all();
+                dialog.run();
+            }
+        }
+    });
+
+    // Deleted entries can be restored with "Undo" until the window goes away,
+    // so their icons are only cleaned up once it does.
+    let cleanup_context = context.clone();
+    window.connect_destroy(move |_| {
+        let _ = icons::remove_orphaned_icons(&cleanup_context);
+    });
 
     /////////////////////////////////////////////////////////
     //
@@ -246,10 +591,12 @@ pub fn index(app: &gtk::Application, entries_result: io::Result<Vec<DesktopEntry
     /////////////////////////////////////////////////////////
 
     let (tx, rx) = MainContext::channel(glib::PRIORITY_DEFAULT);
+    let dir_to_watch = context.data_dir();
+    let menus_to_watch = context.menus_dir();
+    let _ = std::fs::create_dir_all(&menus_to_watch);
 
     thread::spawn(move || {
         let mut inotify = Inotify::init().expect("Failed to initialize inotify");
-        let dir_to_watch = data_dir();
 
         // If this fails, we'll just panic out of the thread and not get updates
         // (no big deal)
@@ -257,6 +604,10 @@ pub fn index(app: &gtk::Application, entries_result: io::Result<Vec<DesktopEntry
             dir_to_watch,
             WatchMask::CREATE | WatchMask::DELETE | WatchMask::MODIFY
         ).expect("Failed to add inotify watch");
+        inotify.add_watch(
+            menus_to_watch,
+            WatchMask::CREATE | WatchMask::DELETE | WatchMask::MODIFY
+        ).expect("Failed to add inotify watch");
 
         let mut buffer = [0u8; 4096];
         loop {
@@ -282,8 +633,9 @@ pub fn index(app: &gtk::Application, entries_result: io::Result<Vec<DesktopEntry
     // Remove all children and re-read desktop files whenever there's a filesystem change
     rx.attach(None, move |_| {
         entries_container.foreach(|child| { child.destroy(); });
-        let new_entries = read_desktop_files();
-        setup_list_ui(new_entries, &entries_container, deleted_entry.clone(), &undo.clone());
+        let new_entries = read_desktop_files(&context);
+        setup_list_ui(&context, new_entries, &entries_container, deleted_entry.clone(), &undo.clone());
+        fill_menu_tree(&context, &menu_store, &menu_tree);
         Continue(true)
     });
 
@@ -298,7 +650,7 @@ pub fn index(app: &gtk::Application, entries_result: io::Result<Vec<DesktopEntry
 }
 
 
-pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
+pub fn editor(app: &gtk::Application, context: Rc<Context>, entry: Option<DesktopEntry>) {
     let builder = gtk::Builder::new_from_string(NEW_ENTRY_GLADE);
 
 
@@ -311,14 +663,36 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
     let window:  Window            = builder.get_object("new_entry_window").unwrap();
     let chooser: FileChooserButton = builder.get_object("icon_chooser_button").unwrap();
 
+    let type_combo: gtk::ComboBoxText = builder.get_object("type_combo").unwrap();
     let name_entry: gtk::Entry = builder.get_object("name_entry").unwrap();
+    let url_entry: gtk::Entry = builder.get_object("url_entry").unwrap();
+    let autostart_switch: gtk::Switch = builder.get_object("autostart_switch").unwrap();
 
     let path_entry: FileChooserButton = builder.get_object("path_chooser").unwrap();
     let exec_entry: gtk::Entry = builder.get_object("exec_entry").unwrap();
     let icon_entry: FileChooserButton = builder.get_object("icon_chooser_button").unwrap();
+    let icon_theme_button: Button = builder.get_object("icon_theme_button").unwrap();
+    let icon_suggestions: gtk::ComboBoxText = builder.get_object("icon_suggestions_combo").unwrap();
+    let icon_suggestions_label: Label = builder.get_object("icon_suggestions_label").unwrap();
 
     let comment_entry: gtk::Entry = builder.get_object("comment_entry").unwrap();
-    let categories_entry: gtk::Entry = builder.get_object("categories_entry").unwrap();
+    let category_chips: gtk::FlowBox = builder.get_object("categories_chips").unwrap();
+    let categories_add_entry: gtk::Entry = builder.get_object("categories_add_entry").unwrap();
+    let categories_warning: Label = builder.get_object("categories_warning").unwrap();
+
+    let env_store: gtk::ListStore = builder.get_object("env_store").unwrap();
+    let env_view: gtk::TreeView = builder.get_object("env_view").unwrap();
+    let env_key_renderer: gtk::CellRendererText = builder.get_object("env_key_renderer").unwrap();
+    let env_value_renderer: gtk::CellRendererText = builder.get_object("env_value_renderer").unwrap();
+    let env_add_button: Button = builder.get_object("env_add_button").unwrap();
+    let env_remove_button: Button = builder.get_object("env_remove_button").unwrap();
+
+    let mime_store: gtk::ListStore = builder.get_object("mime_store").unwrap();
+    let mime_view: gtk::TreeView = builder.get_object("mime_view").unwrap();
+    let mime_default_renderer: gtk::CellRendererToggle = builder.get_object("mime_default_renderer").unwrap();
+    let mime_add_entry: gtk::Entry = builder.get_object("mime_add_entry").unwrap();
+    let mime_remove_button: Button = builder.get_object("mime_remove_button").unwrap();
+    let mime_warning: Label = builder.get_object("mime_warning").unwrap();
 
     let preview_icon: Image = builder.get_object("preview_icon").unwrap();
     let preview_text: Label = builder.get_object("preview_name").unwrap();
@@ -337,15 +711,43 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
     
     let mut old_entry_to_delete = None;
 
+    // Set when the icon is a theme icon name rather than a file
+    let themed_icon: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+
+    // Categories currently shown as chips, in order
+    let selected_categories: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+
     match entry {
         Some(entry) => {
+            type_combo.set_active_id(Some(entry.get_type().as_str()));
             name_entry.set_text(entry.get_name());
+            url_entry.set_text(entry.get_url());
+            autostart_switch.set_active(autostart::is_enabled(&context, &entry.filename()));
             preview_text.set_text(entry.get_name());
             path_entry.set_filename(entry.get_path());
-            exec_entry.set_text(entry.get_exec());
-            icon_entry.set_filename(entry.get_icon());
+            // The environment gets its own table, so the command is shown without the env prefix
+            let mut command = entry.get_exec_command().clone();
+            command.set_env(BTreeMap::new());
+            exec_entry.set_text(&command.to_string());
+            for (key, value) in entry.get_env() {
+                env_store.insert_with_values(None, &[0, 1], &[key, value]);
+            }
+            if entry.get_icon().contains('/') {
+                icon_entry.set_filename(entry.get_icon());
+            }
+            else if !entry.get_icon().is_empty() {
+                themed_icon.replace(Some(entry.get_icon().to_string()));
+                set_entry_icon_preview(&context, &preview_icon, entry.get_icon(), ICON_PREVIEW_SIZE);
+            }
             comment_entry.set_text(entry.get_comment());
-            categories_entry.set_text(entry.get_categories());
+            for category in categories::split(entry.get_categories()) {
+                add_category_chip(&category_chips, &selected_categories, &categories_warning, &category);
+            }
+            let defaults = MimeApps::open(&context).ok();
+            for mime_type in mime::split(entry.get_mime_types()) {
+                let is_default = defaults.as_ref().and_then(|d| d.default_for(&mime_type)) == Some(entry.filename());
+                mime_store.insert_with_values(None, &[0, 1], &[&mime_type, &is_default]);
+            }
 
             old_entry_to_delete = Some(entry);
 
@@ -379,13 +781,186 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
         preview.set_visible(true);
     });
 
+    let file_preview_icon = preview_icon.clone();
+    let file_themed_icon = themed_icon.clone();
     chooser.connect_selection_changed(move |chooser| {
         let preview_filename = match chooser.get_filename() {
             Some(filename) => filename,
             None           => return
         };
 
-        set_icon_preview(&preview_icon, preview_filename, ICON_PREVIEW_SIZE);
+        // Picking a file replaces any theme icon
+        file_themed_icon.replace(None);
+        set_icon_preview(&file_preview_icon, preview_filename, ICON_PREVIEW_SIZE);
+        file_preview_icon.set_visible(true);
+    });
+
+    let picked_themed_icon = themed_icon.clone();
+    let picked_chooser = chooser.clone();
+    let picked_preview_icon = preview_icon.clone();
+    let picker_context = context.clone();
+    icon_theme_button.connect_clicked(move |button| {
+        let icon_name = match icon_theme_picker(&window_of(button), &picker_context) {
+            Some(name) => name,
+            None       => return
+        };
+
+        picked_chooser.unselect_all();
+        set_entry_icon_preview(&picker_context, &picked_preview_icon, &icon_name, ICON_PREVIEW_SIZE);
+        picked_preview_icon.set_visible(true);
+        picked_themed_icon.replace(Some(icon_name));
+    });
+
+    // Look for icons around the executable once the user is done typing the command
+    let suggestions_combo = icon_suggestions.clone();
+    let suggestions_search = IconSearch::new(&context);
+    let suggestions_cache = context.icon_cache_dir();
+    exec_entry.connect_focus_out_event(move |entry, _| {
+        let command = entry.get_text().map(|t| t.to_string()).unwrap_or_default();
+        refresh_icon_suggestions(&suggestions_combo, &icon_suggestions_label, &command, suggestions_search.clone(), suggestions_cache.clone());
+        Inhibit(false)
+    });
+
+    let suggested_themed_icon = themed_icon.clone();
+    let suggested_chooser = chooser.clone();
+    let suggested_context = context.clone();
+    icon_suggestions.connect_changed(move |combo| {
+        let icon = match combo.get_active_id() {
+            Some(id) => id.to_string(),
+            None     => return
+        };
+
+        if icon.contains('/') {
+            // The chooser's selection_changed takes care of the preview
+            suggested_chooser.set_filename(&icon);
+        }
+        else {
+            suggested_chooser.unselect_all();
+            set_entry_icon_preview(&suggested_context, &preview_icon, &icon, ICON_PREVIEW_SIZE);
+            preview_icon.set_visible(true);
+            suggested_themed_icon.replace(Some(icon));
+        }
+    });
+
+
+    /////////////////////////////////////////////////////////
+    //
+    //                    CATEGORY CHIPS
+    //
+    /////////////////////////////////////////////////////////
+
+    categories_add_entry.set_completion(Some(&category_completion()));
+
+    let added_categories = selected_categories.clone();
+    categories_add_entry.connect_activate(move |entry| {
+        let text = entry.get_text().map(|t| t.to_string()).unwrap_or_default();
+
+        for name in categories::split(&text) {
+            let name = categories::normalize(&name);
+
+            if !name.starts_with("X-") && categories::lookup(&name).is_none() {
+                let problems = categories::validate(&[&name]);
+                categories_warning.set_text(&problems[0].describe());
+                categories_warning.set_visible(true);
+                return;
+            }
+
+            add_category_chip(&category_chips, &added_categories, &categories_warning, &name);
+        }
+
+        entry.set_text("");
+    });
+
+
+    /////////////////////////////////////////////////////////
+    //
+    //                 ENVIRONMENT VARIABLES
+    //
+    /////////////////////////////////////////////////////////
+
+    let edited_keys = env_store.clone();
+    env_key_renderer.connect_edited(move |_, path, text| {
+        if let Some(iter) = edited_keys.get_iter(&path) {
+            edited_keys.set_value(&iter, 0, &text.trim().to_value());
+        }
+    });
+
+    let edited_values = env_store.clone();
+    env_value_renderer.connect_edited(move |_, path, text| {
+        if let Some(iter) = edited_values.get_iter(&path) {
+            edited_values.set_value(&iter, 1, &text.to_value());
+        }
+    });
+
+    let added_env = env_store.clone();
+    let added_env_view = env_view.clone();
+    env_add_button.connect_clicked(move |_| {
+        let iter = added_env.insert_with_values(None, &[0, 1], &[&"", &""]);
+        // Jump straight into typing the variable name
+        if let (Some(path), Some(column)) = (added_env.get_path(&iter), added_env_view.get_column(0)) {
+            added_env_view.set_cursor(&path, Some(&column), true);
+        }
+    });
+
+    let removed_env = env_store.clone();
+    env_remove_button.connect_clicked(move |_| {
+        if let Some((_, iter)) = env_view.get_selection().get_selected() {
+            removed_env.remove(&iter);
+        }
+    });
+
+
+
+    /////////////////////////////////////////////////////////
+    //
+    //                      FILE TYPES
+    //
+    /////////////////////////////////////////////////////////
+
+    mime_add_entry.set_completion(Some(&mime_completion(&context)));
+
+    let toggled_defaults = mime_store.clone();
+    mime_default_renderer.connect_toggled(move |_, path| {
+        if let Some(iter) = toggled_defaults.get_iter(&path) {
+            let is_default: bool = toggled_defaults.get_value(&iter, 1).get().unwrap_or(false);
+            toggled_defaults.set_value(&iter, 1, &(!is_default).to_value());
+        }
+    });
+
+    let added_mime_types = mime_store.clone();
+    let lookup_context = context.clone();
+    mime_add_entry.connect_activate(move |entry| {
+        let text = entry.get_text().map(|t| t.to_string()).unwrap_or_default();
+
+        for input in mime::split(&text) {
+            let mime_type = match mime::resolve(&lookup_context, &input) {
+                Ok(t) => t,
+                Err(problem) => {
+                    mime_warning.set_text(&problem);
+                    mime_warning.set_visible(true);
+                    return;
+                }
+            };
+
+            if !mime_types_from_store(&added_mime_types).iter().any(|(t, _)| *t == mime_type) {
+                added_mime_types.insert_with_values(None, &[0, 1], &[&mime_type, &false]);
+            }
+        }
+
+        mime_warning.set_visible(false);
+        entry.set_text("");
+    });
+
+    let removed_mime_types = mime_store.clone();
+    mime_remove_button.connect_clicked(move |_| {
+        if let Some((_, iter)) = mime_view.get_selection().get_selected() {
+            removed_mime_types.remove(&iter);
+        }
+    });
+
+    let type_builder = builder.clone();
+    type_combo.connect_changed(move |combo| {
+        show_fields_for_type(&type_builder, selected_type(combo));
     });
 
     name_entry.connect_changed(move |entry| {
@@ -431,18 +1006,34 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
         () => {
             {
             // Clone components that need to be captured
+            let submitted_type = type_combo.clone();
             let submitted_name = name_entry.clone();
+            let submitted_url = url_entry.clone();
+            let submitted_autostart = autostart_switch.clone();
             let submitted_exec = exec_entry.clone();
             let submitted_path = path_entry.clone();
             let submitted_icon = icon_entry.clone();
+            let submitted_themed_icon = themed_icon.clone();
             let submitted_comment = comment_entry.clone();
-            let submitted_categories = categories_entry.clone();
+            let submitted_categories = selected_categories.clone();
+            let submitted_env = env_store.clone();
+            let submitted_mime_types = mime_store.clone();
             let to_delete = old_entry_to_delete.clone();
+            let submit_context = context.clone();
             move |widget| {
                 // TODO actual validation of input
 
+                let entry_type = selected_type(&submitted_type);
                 let name = submitted_name.get_text().expect("Please have name");
                 let exec = submitted_exec.get_text().expect("Please have command");
+                let url = submitted_url.get_text().map(|t| t.trim().to_string()).unwrap_or_default();
+
+                if entry_type == EntryType::Link && url.is_empty() {
+                    let dialog = error_dialog("A link needs a URL");
+                    dialog.show_all();
+                    dialog.run();
+                    return;
+                }
 
                 let path_path = submitted_path.get_filename();
                 let path = match path_path {
@@ -451,25 +1042,61 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
                 };
 
                 let icon_path = submitted_icon.get_filename();
-                let icon = match icon_path {
-                    Some(val) => Some(String::from(val.to_str().expect("Couldn't get string from icon path"))),
-                    None      => None
+                let icon = match (submitted_themed_icon.borrow().clone(), icon_path) {
+                    (Some(name), _)   => Some(name),
+                    (None, Some(val)) => Some(String::from(val.to_str().expect("Couldn't get string from icon path"))),
+                    (None, None)      => None
                 };
 
-                let comment    = submitted_comment.get_text();
-                let categories = submitted_categories.get_text();
-
-                let new_entry = DesktopEntry::new(
-                    &name,
-                    &comment.unwrap_or(GString::from("")),
-                    &path.unwrap_or_default(),
-                    &exec,
-                    &icon.unwrap_or_default(),
-                    &categories.unwrap_or(GString::from(""))
-                );
+                let comment    = submitted_comment.get_text().unwrap_or(GString::from(""));
+                let categories = categories::join(&submitted_categories.borrow());
+
+                // Table rows go on top of whatever env prefix was typed into the command itself
+                let mut command = Exec::parse(&exec);
+                match env_from_store(&submitted_env) {
+                    Ok(env) => for (key, value) in env { command.set_env_var(&key, &value) },
+                    Err(message) => {
+                        let dialog = error_dialog(&message);
+                        dialog.show_all();
+                        dialog.run();
+                        return;
+                    }
+                }
+
+                let icon = icon.unwrap_or_default();
+                let mut new_entry = match entry_type {
+                    EntryType::Application => DesktopEntry::new(
+                        &name,
+                        &comment,
+                        &path.unwrap_or_default(),
+                        command,
+                        &icon,
+                        &categories
+                    ),
+                    EntryType::Link      => DesktopEntry::link(&name, &comment, &url, &icon),
+                    EntryType::Directory => DesktopEntry::directory(&name, &comment, &icon),
+                };
+                if let Some(old_entry) = &to_delete {
+                    new_entry.keep_unedited_fields(old_entry);
+                }
+                // The table always has the whole list, so an emptied one clears it rather than keeping the old one
+                let mime_rows = mime_types_from_store(&submitted_mime_types);
+                let mime_types: Vec<String> = mime_rows.iter().map(|(t, _)| t.clone()).collect();
+                new_entry.set_mime_types(&mime::join(&mime_types));
+
+                // Copy the icon into the icon theme before it gets written as a theme name
+                match new_entry.adopt_icon(&submit_context) {
+                    Ok(()) => {}
+                    Err(error) => {
+                        let dialog = error_dialog(&format!("Failed to install icon: {}", error.description()));
+                        dialog.show_all();
+                        dialog.run();
+                        return;
+                    }
+                }
                 
                 // Write result and save
-                match new_entry.write_to_apps_dir() {
+                match new_entry.write_to_apps_dir(&submit_context) {
                     Ok(()) => {}
                     Err(error) => {
                         error_dialog(error.description());
@@ -481,7 +1108,12 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
                 match &to_delete {
                     Some(old_entry) => {
                         if old_entry.filename() != new_entry.filename() {
-                            match old_entry.delete() {
+                            if let Err(error) = desktop::rename_references(&submit_context, &old_entry.filename(), &new_entry.filename()) {
+                                let dialog = error_dialog(&format!("Couldn't give everything that refers to the entry its new name: {}", error.description()));
+                                dialog.show_all();
+                                dialog.run();
+                            }
+                            match old_entry.delete(&submit_context) {
                                 Ok(()) => {}
                                 Err(error) => {
                                     error_dialog(&format!(
@@ -495,6 +1127,27 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
                     None => {}
                 }
 
+                // The switch only shows for applications; the entry's autostart copy is kept up to date when it's saved
+                let id = new_entry.filename();
+                let run_at_login = entry_type == EntryType::Application && submitted_autostart.get_active();
+                if run_at_login != autostart::is_enabled(&submit_context, &id) {
+                    let result = if run_at_login { autostart::enable(&submit_context, &id, &new_entry.filepath(&submit_context), None) }
+                                 else            { autostart::disable(&submit_context, &id) };
+                    if let Err(error) = result {
+                        let dialog = error_dialog(&format!("Couldn't change whether this runs at login: {}", error.description()));
+                        dialog.show_all();
+                        dialog.run();
+                    }
+                }
+
+                if entry_type == EntryType::Application {
+                    if let Err(error) = apply_mime_defaults(&submit_context, &id, &mime_rows) {
+                        let dialog = error_dialog(&format!("Couldn't update the default applications: {}", error.description()));
+                        dialog.show_all();
+                        dialog.run();
+                    }
+                }
+
                 window_of(widget).close();
             }
             }
@@ -505,7 +1158,7 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
     name_entry.connect_activate(submit!());
     exec_entry.connect_activate(submit!());
     comment_entry.connect_activate(submit!());
-    categories_entry.connect_activate(submit!());
+    url_entry.connect_activate(submit!());
 
 
     /////////////////////////////////////////////////////////
@@ -515,6 +1168,9 @@ pub fn editor(app: &gtk::Application, entry: Option<DesktopEntry>) {
     /////////////////////////////////////////////////////////
 
     window.show_all();
+
+    // show_all() also shows the rows that don't apply to the entry's type
+    show_fields_for_type(&builder, selected_type(&type_combo));
 }
 
 
@@ -0,0 +1,607 @@
+extern crate regex;
+extern crate lazy_static;
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use regex::{Regex, RegexBuilder};
+
+use super::desktop::{self, DesktopEntry};
+use super::icons;
+use super::icon_theme::IconSearch;
+
+
+/// Where an icon suggestion came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IconSource {
+    WindowsExecutable,
+    AppImage,
+    WebApp,
+    DesktopFile,
+}
+
+impl IconSource {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            IconSource::WindowsExecutable => "Windows executable",
+            IconSource::AppImage          => "AppImage",
+            IconSource::WebApp            => "web page",
+            IconSource::DesktopFile       => "nearby .desktop file",
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct IconSuggestion {
+    pub source: IconSource,
+    /// Either a path to an image file or a theme icon name -- the same things Icon= can hold
+    pub icon: String,
+}
+
+
+/// Looks around the given executable (or web page) for icons that could go with it.
+/// Nothing here is fatal; sources that don't apply or fail just don't produce suggestions.
+/// Icons from .desktop files only count if `icons` can find them. Extracted icons are written into `cache`.
+pub fn suggest_icons(target: &Path, icons: &IconSearch, cache: &Path) -> Vec<IconSuggestion> {
+    let mut result = Vec::new();
+
+    if is_windows_executable(target) {
+        if let Ok(Some(path)) = extract_windows_icon(target, cache) {
+            result.push(suggestion(IconSource::WindowsExecutable, &path));
+        }
+    }
+
+    if is_appimage(target) {
+        if let Ok(Some(path)) = extract_appimage_icon(target, cache) {
+            result.push(suggestion(IconSource::AppImage, &path));
+        }
+    }
+
+    for path in web_app_icons(target) {
+        result.push(suggestion(IconSource::WebApp, &path));
+    }
+
+    for icon in nearby_desktop_file_icons(target, icons) {
+        result.push(IconSuggestion { source: IconSource::DesktopFile, icon: icon });
+    }
+
+    result
+}
+
+
+fn suggestion(source: IconSource, path: &Path) -> IconSuggestion {
+    IconSuggestion { source: source, icon: path.to_string_lossy().to_string() }
+}
+
+
+/// "<stem>-<hash of contents>.<extension>" inside the cache dir (created if needed), so re-extracting the same icon is harmless
+fn cache_path_for(cache: &Path, original: &Path, contents: &[u8], extension: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(cache)?;
+    let stem = original.file_stem().and_then(|s| s.to_str()).unwrap_or("icon");
+    Ok(cache.join(format!("{}-{:08x}.{}", stem, icons::content_hash(contents) as u32, extension)))
+}
+
+
+/////////////////////////////////////////////////////////
+//
+//              WINDOWS PE RESOURCES
+//
+/////////////////////////////////////////////////////////
+
+const RT_ICON: u32 = 3;
+const RT_GROUP_ICON: u32 = 14;
+
+
+pub fn is_windows_executable(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("exe") || ext.eq_ignore_ascii_case("dll"),
+        None      => false
+    }
+}
+
+
+/// Pulls the first icon group out of a PE file's resources and writes it to the cache as an .ico
+pub fn extract_windows_icon(exe: &Path, cache: &Path) -> io::Result<Option<PathBuf>> {
+    let data = fs::read(exe)?;
+
+    let ico = match pe_icon(&data) {
+        Some(ico) => ico,
+        None      => return Ok(None)
+    };
+
+    let dest = cache_path_for(cache, exe, &ico, "ico")?;
+    fs::write(&dest, &ico)?;
+    Ok(Some(dest))
+}
+
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from(bytes[0]) | u16::from(bytes[1]) << 8)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16 | u32::from(bytes[3]) << 24)
+}
+
+
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_offset: u32,
+}
+
+
+/// Walks the PE headers to the resource section and builds an .ico from the first RT_GROUP_ICON
+fn pe_icon(data: &[u8]) -> Option<Vec<u8>> {
+    if data.get(0..2)? != b"MZ" { return None }
+
+    let pe_offset = read_u32(data, 0x3C)? as usize;
+    if data.get(pe_offset..pe_offset + 4)? != b"PE\0\0" { return None }
+
+    let coff = pe_offset + 4;
+    let section_count = read_u16(data, coff + 2)? as usize;
+    let optional_header_size = read_u16(data, coff + 16)? as usize;
+    let optional = coff + 20;
+
+    // Data directories are at a different offset for PE32 and PE32+
+    let data_directories = match read_u16(data, optional)? {
+        0x10b => optional + 96,
+        0x20b => optional + 112,
+        _     => return None
+    };
+    let resource_rva = read_u32(data, data_directories + 2 * 8)?;
+    if resource_rva == 0 { return None }
+
+    let mut sections = Vec::new();
+    let section_table = optional + optional_header_size;
+    for i in 0..section_count {
+        let header = section_table + i * 40;
+        let virtual_size = read_u32(data, header + 8)?;
+        let raw_size = read_u32(data, header + 16)?;
+        sections.push(Section {
+            virtual_address: read_u32(data, header + 12)?,
+            virtual_size: virtual_size.max(raw_size),
+            raw_offset: read_u32(data, header + 20)?,
+        });
+    }
+
+    let rva_to_offset = |rva: u32| -> Option<usize> {
+        sections.iter()
+            .find(|s| rva >= s.virtual_address && rva - s.virtual_address < s.virtual_size)
+            .map(|s| (rva - s.virtual_address) as usize + s.raw_offset as usize)
+    };
+
+    let resources = rva_to_offset(resource_rva)?;
+
+    // Resource tree is type -> name -> language -> data
+    let resource_data = |type_id: u32| -> Vec<(u32, Vec<u8>)> {
+        let mut result = Vec::new();
+
+        let names = match resource_subdirectory(data, resources, resources, type_id) {
+            Some(dir) => dir,
+            None      => return result
+        };
+
+        for (name_id, languages) in resource_entries(data, resources, names) {
+            let languages = match languages { Subdirectory(offset) => offset, Leaf(_) => continue };
+            let leaf = match resource_entries(data, resources, languages).first() {
+                Some((_, Leaf(offset))) => *offset,
+                _ => continue
+            };
+
+            let rva = match read_u32(data, leaf) { Some(x) => x, None => continue };
+            let size = match read_u32(data, leaf + 4) { Some(x) => x as usize, None => continue };
+            let start = match rva_to_offset(rva) { Some(x) => x, None => continue };
+
+            if let Some(bytes) = data.get(start..start + size) {
+                result.push((name_id, bytes.to_vec()));
+            }
+        }
+
+        result
+    };
+
+    let groups = resource_data(RT_GROUP_ICON);
+    let (_, group) = groups.first()?;
+    let icons = resource_data(RT_ICON);
+
+    build_ico(group, &icons)
+}
+
+
+enum ResourceEntry {
+    Subdirectory(usize),
+    Leaf(usize),
+}
+use self::ResourceEntry::{Subdirectory, Leaf};
+
+
+/// (id, entry) for every entry in the resource directory at `directory`. Named entries get id 0.
+fn resource_entries(data: &[u8], resources: usize, directory: usize) -> Vec<(u32, ResourceEntry)> {
+    let mut result = Vec::new();
+
+    let named = read_u16(data, directory + 12).unwrap_or(0) as usize;
+    let ids = read_u16(data, directory + 14).unwrap_or(0) as usize;
+
+    for i in 0..(named + ids) {
+        let entry = directory + 16 + i * 8;
+        let (name, offset) = match (read_u32(data, entry), read_u32(data, entry + 4)) {
+            (Some(n), Some(o)) => (n, o),
+            _ => break
+        };
+
+        let id = if name & 0x8000_0000 != 0 { 0 } else { name };
+        let target = resources + (offset & 0x7FFF_FFFF) as usize;
+
+        if offset & 0x8000_0000 != 0 { result.push((id, Subdirectory(target))) }
+        else                         { result.push((id, Leaf(target))) }
+    }
+
+    result
+}
+
+
+fn resource_subdirectory(data: &[u8], resources: usize, directory: usize, id: u32) -> Option<usize> {
+    for (entry_id, entry) in resource_entries(data, resources, directory) {
+        match entry {
+            Subdirectory(offset) if entry_id == id => return Some(offset),
+            _ => {}
+        }
+    }
+    None
+}
+
+
+/// Turns a GRPICONDIR resource plus the RT_ICON resources it refers to into an .ico file
+fn build_ico(group: &[u8], icons: &[(u32, Vec<u8>)]) -> Option<Vec<u8>> {
+    let count = read_u16(group, 4)? as usize;
+
+    let mut entries = Vec::new();
+    for i in 0..count {
+        let entry = group.get(6 + i * 14..6 + (i + 1) * 14)?;
+        let icon_id = u32::from(read_u16(entry, 12)?);
+
+        if let Some((_, image)) = icons.iter().find(|(id, _)| *id == icon_id) {
+            entries.push((&entry[0..8], image));
+        }
+    }
+    if entries.is_empty() { return None }
+
+    // ICONDIR, then one 16-byte ICONDIRENTRY per image, then the images themselves
+    let mut ico = vec![0, 0, 1, 0, entries.len() as u8, (entries.len() >> 8) as u8];
+    let mut image_offset = 6 + entries.len() * 16;
+
+    for (header, image) in &entries {
+        ico.extend_from_slice(header);
+        ico.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        ico.extend_from_slice(&(image_offset as u32).to_le_bytes());
+        image_offset += image.len();
+    }
+    for (_, image) in &entries {
+        ico.extend_from_slice(image);
+    }
+
+    Some(ico)
+}
+
+
+/////////////////////////////////////////////////////////
+//
+//                     APPIMAGE
+//
+/////////////////////////////////////////////////////////
+
+pub fn is_appimage(path: &Path) -> bool {
+    let by_extension = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("appimage"),
+        None      => false
+    };
+    if by_extension { return true }
+
+    // Type 1 and 2 AppImages are ELF files with "AI" and the type number at offset 8
+    let mut header = [0u8; 11];
+    match fs::File::open(path) {
+        Ok(mut file) => {
+            file.read_exact(&mut header).is_ok() && &header[0..4] == b"\x7fELF" && &header[8..10] == b"AI"
+        }
+        Err(_) => false
+    }
+}
+
+
+/// Pulls .DirIcon (following it if it's a symlink) out of a type 2 AppImage's squashfs with unsquashfs.
+/// The AppImage itself is never run -- it could be anything with the right extension.
+/// Type 1 images (ISO 9660) and systems without unsquashfs give None.
+pub fn extract_appimage_icon(appimage: &Path, cache: &Path) -> io::Result<Option<PathBuf>> {
+    let offset = match squashfs_offset(appimage)? {
+        Some(offset) => offset,
+        None         => return Ok(None)
+    };
+    if desktop::find_program("unsquashfs").is_none() { return Ok(None) }
+
+    let work_dir = cache.join("appimage-extract");
+    fs::create_dir_all(&work_dir)?;
+
+    let extracted_root = work_dir.join("squashfs-root");
+    let _ = fs::remove_dir_all(&extracted_root);
+
+    let extract = |pattern: &str| -> io::Result<()> {
+        Command::new("unsquashfs")
+            .args(["-o", &offset.to_string(), "-f", "-d"])
+            .arg(&extracted_root)
+            .arg(appimage)
+            .arg(pattern)
+            .output()
+            .map(|_| ())
+    };
+
+    extract(".DirIcon")?;
+    let mut icon = extracted_root.join(".DirIcon");
+
+    // .DirIcon is usually a symlink to the real icon inside the image
+    let mut hops = 0;
+    while let Ok(link) = fs::read_link(&icon) {
+        let relative = link.strip_prefix("/").unwrap_or(&link).to_path_buf();
+        extract(&relative.to_string_lossy())?;
+        icon = extracted_root.join(relative);
+
+        hops += 1;
+        if hops > 8 { break }
+    }
+
+    let result = match fs::read(&icon) {
+        Ok(contents) => {
+            let extension = if looks_like_svg(&contents) { "svg" } else { "png" };
+            let dest = cache_path_for(cache, appimage, &contents, extension)?;
+            fs::write(&dest, &contents)?;
+            Some(dest)
+        }
+        Err(_) => None
+    };
+
+    let _ = fs::remove_dir_all(&extracted_root);
+    Ok(result)
+}
+
+
+/// Where the squashfs image starts in a type 2 AppImage: right after the ELF runtime, which ends
+/// with its section header table. None unless the file says it's a type 2 image and a squashfs is really there.
+pub fn squashfs_offset(appimage: &Path) -> io::Result<Option<u64>> {
+    let mut file = fs::File::open(appimage)?;
+    let mut header = [0u8; 64];
+    if file.read_exact(&mut header).is_err() || &header[8..11] != b"AI\x02" { return Ok(None) }
+
+    let offset = match elf_end(&header) {
+        Some(offset) => offset,
+        None         => return Ok(None)
+    };
+    let mut magic = [0u8; 4];
+    file.seek(SeekFrom::Start(offset))?;
+    if file.read_exact(&mut magic).is_err() || &magic != b"hsqs" { return Ok(None) }
+    Ok(Some(offset))
+}
+
+
+/// e_shoff + e_shentsize * e_shnum, for 32 and 64 bit ELF files of either byte order
+fn elf_end(header: &[u8]) -> Option<u64> {
+    if header.get(0..4)? != b"\x7fELF" { return None }
+    let little_endian = match header.get(5)? {
+        1 => true,
+        2 => false,
+        _ => return None
+    };
+    let number = |offset: usize, size: usize| -> Option<u64> {
+        let bytes = header.get(offset..offset + size)?;
+        let value = |acc: u64, byte: &u8| acc << 8 | u64::from(*byte);
+        Some(if little_endian { bytes.iter().rev().fold(0, value) } else { bytes.iter().fold(0, value) })
+    };
+
+    let (table, entry_size, entries) = match header.get(4)? {
+        1 => (number(0x20, 4)?, number(0x2E, 2)?, number(0x30, 2)?),
+        2 => (number(0x28, 8)?, number(0x3A, 2)?, number(0x3C, 2)?),
+        _ => return None
+    };
+    table.checked_add(entry_size * entries)
+}
+
+
+fn looks_like_svg(contents: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(&contents[..contents.len().min(256)]).to_string();
+    start.contains("<svg") || start.trim_start().starts_with("<?xml")
+}
+
+
+/////////////////////////////////////////////////////////
+//
+//                   LOCAL WEB APPS
+//
+/////////////////////////////////////////////////////////
+
+/// Icons referenced by `<link rel="icon">` (or a favicon.ico lying next to it) in a local HTML
+/// file, or in the index.html of a directory.
+pub fn web_app_icons(target: &Path) -> Vec<PathBuf> {
+    let html_file = if target.is_dir() {
+        target.join("index.html")
+    }
+    else {
+        match target.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm") => target.to_path_buf(),
+            _ => return Vec::new()
+        }
+    };
+
+    let html = match fs::read_to_string(&html_file) {
+        Ok(x)  => x,
+        Err(_) => return Vec::new()
+    };
+    let base = html_file.parent().unwrap_or(Path::new("/"));
+
+    let mut result: Vec<PathBuf> = html_icon_hrefs(&html)
+        .iter()
+        .filter(|href| !href.contains("://") && !href.starts_with("data:") && !href.starts_with("//"))
+        .map(|href| base.join(href.split(|c| c == '?' || c == '#').next().unwrap_or_default()))
+        .filter(|path| path.is_file())
+        .collect();
+
+    let favicon = base.join("favicon.ico");
+    if favicon.is_file() && !result.contains(&favicon) {
+        result.push(favicon);
+    }
+
+    result
+}
+
+
+/// The href of every <link> whose rel mentions "icon", in document order
+pub fn html_icon_hrefs(html: &str) -> Vec<String> {
+    lazy_static! {
+        static ref LINK_TAG: Regex = RegexBuilder::new(r"<link\s[^>]*>")
+            .case_insensitive(true)
+            .build().unwrap();
+
+        static ref REL_ATTR: Regex = RegexBuilder::new(r#"\brel\s*=\s*["']?([^"'>]*)"#)
+            .case_insensitive(true)
+            .build().unwrap();
+
+        static ref HREF_ATTR: Regex = RegexBuilder::new(r#"\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#)
+            .case_insensitive(true)
+            .build().unwrap();
+    }
+
+    let mut result = Vec::new();
+
+    for tag in LINK_TAG.find_iter(html) {
+        let tag = tag.as_str();
+
+        let is_icon = match REL_ATTR.captures(tag) {
+            Some(caps) => caps.get(1).unwrap().as_str().to_lowercase().split_whitespace().any(|rel| rel.ends_with("icon")),
+            None       => false
+        };
+        if !is_icon { continue }
+
+        if let Some(caps) = HREF_ATTR.captures(tag) {
+            let href = caps.get(1).or(caps.get(2)).or(caps.get(3)).unwrap().as_str();
+            result.push(href.to_string());
+        }
+    }
+
+    result
+}
+
+
+/////////////////////////////////////////////////////////
+//
+//                NEARBY .DESKTOP FILES
+//
+/////////////////////////////////////////////////////////
+
+/// Icon= values of .desktop files sitting in the same folder as the target (that actually resolve)
+pub fn nearby_desktop_file_icons(target: &Path, icons: &IconSearch) -> Vec<String> {
+    let mut result = Vec::new();
+
+    let folder = if target.is_dir() { target } else {
+        match target.parent() {
+            Some(parent) => parent,
+            None         => return result
+        }
+    };
+
+    let files = match fs::read_dir(folder) {
+        Ok(x)  => x,
+        Err(_) => return result
+    };
+
+    for file in files.filter_map(|f| f.ok()) {
+        let path = file.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("desktop") { continue }
+
+        let entry = match fs::File::open(&path).and_then(|f| DesktopEntry::read(&mut io::BufReader::new(f))) {
+            Ok(entry) => entry,
+            Err(_)    => continue
+        };
+
+        let mut icon = entry.get_icon().to_string();
+        if icon.is_empty() { continue }
+
+        // Relative icon paths are relative to the .desktop file
+        if icon.contains('/') && !icon.starts_with('/') {
+            icon = folder.join(&icon).to_string_lossy().to_string();
+        }
+
+        if icons.find_icon(&icon, 48).is_some() && !result.contains(&icon) {
+            result.push(icon);
+        }
+    }
+
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{build_ico, html_icon_hrefs, squashfs_offset, extract_appimage_icon};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use super::super::context::TestDir;
+
+    #[test]
+    fn html_icon_hrefs_finds_icon_links() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="style.css">
+            <LINK REL="shortcut icon" HREF='img/favicon.png'>
+            <link href=apple.png rel="apple-touch-icon" sizes="180x180">
+            </head></html>"#;
+
+        assert_eq!(html_icon_hrefs(html), vec!["img/favicon.png", "apple.png"]);
+    }
+
+    #[test]
+    fn build_ico_assembles_group_and_images() {
+        // GRPICONDIR with one 32x32 entry pointing at RT_ICON id 7
+        let group = vec![
+            0, 0, 1, 0, 1, 0,
+            32, 32, 0, 0, 1, 0, 32, 0, 4, 0, 0, 0, 7, 0,
+        ];
+        let icons = vec![(7, vec![0xAA, 0xBB, 0xCC, 0xDD])];
+
+        let ico = build_ico(&group, &icons).unwrap();
+
+        assert_eq!(&ico[0..6], &[0, 0, 1, 0, 1, 0]);
+        assert_eq!(&ico[6..14], &[32, 32, 0, 0, 1, 0, 32, 0]);
+        assert_eq!(&ico[14..18], &[4, 0, 0, 0]);
+        assert_eq!(&ico[18..22], &[22, 0, 0, 0]);
+        assert_eq!(&ico[22..], &[0xAA, 0xBB, 0xCC, 0xDD]);
+    }
+
+    #[test]
+    fn appimages_are_found_by_their_elf_header_and_never_run() {
+        let dir = TestDir::new("appimage");
+
+        // A 64 bit little endian header whose section table (2 entries of 64 bytes at 0x100) ends at 0x180
+        let mut image = vec![0u8; 0x180];
+        image[0..4].copy_from_slice(b"\x7fELF");
+        image[4] = 2;
+        image[5] = 1;
+        image[8..11].copy_from_slice(b"AI\x02");
+        image[0x28] = 0x00;
+        image[0x29] = 0x01;
+        image[0x3A] = 64;
+        image[0x3C] = 2;
+        image.extend_from_slice(b"hsqs");
+        fs::write(dir.join("Tool.AppImage"), &image).unwrap();
+        assert_eq!(squashfs_offset(&dir.join("Tool.AppImage")).unwrap(), Some(0x180));
+
+        // A script with the right name would have run with --appimage-extract and left the marker behind
+        let marker = dir.join("ran");
+        fs::write(dir.join("Fake.AppImage"), format!("#!/bin/sh\ntouch {}\n", marker.display())).unwrap();
+        fs::set_permissions(dir.join("Fake.AppImage"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(squashfs_offset(&dir.join("Fake.AppImage")).unwrap(), None);
+        assert_eq!(extract_appimage_icon(&dir.join("Fake.AppImage"), &dir.join("cache")).unwrap(), None);
+        assert!(!marker.exists());
+    }
+}
@@ -0,0 +1,534 @@
+extern crate regex;
+extern crate lazy_static;
+
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use gio::prelude::*;
+use gio::Settings;
+use regex::{Regex, RegexBuilder};
+
+use super::context::Context;
+
+
+/// Every theme falls back to this one eventually
+pub const FALLBACK_THEME: &str = "hicolor";
+
+/// Where GNOME keeps the icon theme setting
+const INTERFACE_SCHEMA: &str = "org.gnome.desktop.interface";
+
+/// Extensions we look for, in order of preference
+pub const ICON_EXTENSIONS: [&str; 3] = ["png", "svg", "xpm"];
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DirectoryType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+
+/// One of the subdirectories listed in an index.theme (e.g. "48x48/apps")
+#[derive(Clone, Debug)]
+pub struct ThemeDirectory {
+    pub subdir: String,
+    pub size: i32,
+    pub scale: i32,
+    pub min_size: i32,
+    pub max_size: i32,
+    pub threshold: i32,
+    pub dir_type: DirectoryType,
+    pub context: String,
+}
+
+impl ThemeDirectory {
+    fn with_defaults(subdir: &str) -> ThemeDirectory {
+        ThemeDirectory {
+            subdir: subdir.to_string(),
+            size: 0,
+            scale: 1,
+            min_size: -1,
+            max_size: -1,
+            threshold: 2,
+            dir_type: DirectoryType::Threshold,
+            context: String::new(),
+        }
+    }
+
+
+    /// DirectoryMatchesSize from the Icon Theme Specification
+    pub fn matches_size(&self, size: i32, scale: i32) -> bool {
+        if self.scale != scale { return false }
+
+        match self.dir_type {
+            DirectoryType::Fixed     => self.size == size,
+            DirectoryType::Scalable  => self.min_size <= size && size <= self.max_size,
+            DirectoryType::Threshold => self.size - self.threshold <= size && size <= self.size + self.threshold,
+        }
+    }
+
+
+    /// DirectorySizeDistance from the Icon Theme Specification
+    pub fn size_distance(&self, size: i32, scale: i32) -> i32 {
+        let wanted = size * scale;
+
+        match self.dir_type {
+            DirectoryType::Fixed => (self.size * self.scale - wanted).abs(),
+
+            DirectoryType::Scalable => {
+                if wanted < self.min_size * self.scale { self.min_size * self.scale - wanted }
+                else if wanted > self.max_size * self.scale { wanted - self.max_size * self.scale }
+                else { 0 }
+            }
+
+            DirectoryType::Threshold => {
+                if wanted < (self.size - self.threshold) * self.scale { self.min_size * self.scale - wanted }
+                else if wanted > (self.size + self.threshold) * self.scale { wanted - self.max_size * self.scale }
+                else { 0 }
+            }
+        }
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct IconTheme {
+    pub name: String,
+    pub inherits: Vec<String>,
+    pub directories: Vec<ThemeDirectory>,
+    /// Every <base dir>/<theme name> that exists on disk
+    pub roots: Vec<PathBuf>,
+}
+
+impl IconTheme {
+    /// Finds the theme's index.theme in the base directories and parses it. Themes that were found are
+    /// kept for the rest of the run, since every lookup walks the whole chain again.
+    pub fn load(bases: &[PathBuf], name: &str) -> Option<IconTheme> {
+        lazy_static! {
+            static ref LOADED: Mutex<HashMap<(Vec<PathBuf>, String), IconTheme>> = Mutex::new(HashMap::new());
+        }
+
+        let key = (bases.to_vec(), name.to_string());
+        if let Some(theme) = LOADED.lock().unwrap().get(&key) {
+            return Some(theme.clone());
+        }
+
+        let theme = IconTheme::load_uncached(bases, name)?;
+        LOADED.lock().unwrap().insert(key, theme.clone());
+        Some(theme)
+    }
+
+
+    fn load_uncached(bases: &[PathBuf], name: &str) -> Option<IconTheme> {
+        let roots: Vec<PathBuf> = bases.iter()
+            .map(|base| base.join(name))
+            .filter(|root| root.is_dir())
+            .collect();
+
+        for root in &roots {
+            let file = match fs::File::open(root.join("index.theme")) {
+                Ok(f)  => f,
+                Err(_) => continue
+            };
+
+            let mut theme = IconTheme::read(name, &mut io::BufReader::new(file));
+            theme.roots = roots.clone();
+            return Some(theme);
+        }
+
+        None
+    }
+
+
+    /// Parses an index.theme (roots are left empty)
+    pub fn read(name: &str, input: &mut io::BufRead) -> IconTheme {
+        lazy_static! {
+            static ref SECTION_REGEX: Regex = RegexBuilder::new(r"^\[([^\]]+)\]")
+                .build().unwrap();
+
+            static ref ATTR_REGEX: Regex = RegexBuilder::new(r"^([^\[#=]+)=(.*)$")
+                .build().unwrap();
+        }
+
+        let mut theme = IconTheme {
+            name: name.to_string(),
+            inherits: Vec::new(),
+            directories: Vec::new(),
+            roots: Vec::new(),
+        };
+
+        let mut directory_names = Vec::<String>::new();
+        let mut sections = Vec::<ThemeDirectory>::new();
+        let mut section = String::new();
+        let mut line = String::new();
+
+        while let Ok(bytes_read) = input.read_line(&mut line) {
+            if bytes_read == 0 { break }
+
+            if let Some(caps) = SECTION_REGEX.captures(&line) {
+                section = caps.get(1).unwrap().as_str().to_string();
+                if section != "Icon Theme" {
+                    sections.push(ThemeDirectory::with_defaults(&section));
+                }
+                line.clear();
+                continue;
+            }
+
+            let caps = match ATTR_REGEX.captures(line.trim_end()) {
+                Some(c) => c,
+                None    => { line.clear(); continue }
+            };
+
+            let key = caps.get(1).unwrap().as_str().trim();
+            let value = caps.get(2).unwrap().as_str().trim();
+
+            if section == "Icon Theme" {
+                match key {
+                    "Inherits"           => theme.inherits = split_list(value),
+                    "Directories"        => directory_names.extend(split_list(value)),
+                    "ScaledDirectories"  => directory_names.extend(split_list(value)),
+                    _ => {}
+                }
+            }
+            else if let Some(dir) = sections.last_mut() {
+                let number = value.parse::<i32>().ok();
+                match (key, number) {
+                    ("Size", Some(n))      => dir.size = n,
+                    ("Scale", Some(n))     => dir.scale = n,
+                    ("MinSize", Some(n))   => dir.min_size = n,
+                    ("MaxSize", Some(n))   => dir.max_size = n,
+                    ("Threshold", Some(n)) => dir.threshold = n,
+                    ("Context", _)         => dir.context = value.to_string(),
+                    ("Type", _) => dir.dir_type = match value {
+                        "Fixed"    => DirectoryType::Fixed,
+                        "Scalable" => DirectoryType::Scalable,
+                        _          => DirectoryType::Threshold,
+                    },
+                    _ => {}
+                }
+            }
+
+            line.clear();
+        }
+
+        // Only directories that are actually listed count, in the order they're listed
+        for name in directory_names {
+            if let Some(dir) = sections.iter().find(|d| d.subdir == name) {
+                let mut dir = dir.clone();
+                if dir.min_size < 0 { dir.min_size = dir.size }
+                if dir.max_size < 0 { dir.max_size = dir.size }
+                theme.directories.push(dir);
+            }
+        }
+
+        theme
+    }
+
+
+    /// LookupIcon from the Icon Theme Specification (this theme only, no inheritance)
+    pub fn lookup_icon(&self, icon_name: &str, size: i32, scale: i32) -> Option<PathBuf> {
+        for dir in &self.directories {
+            if !dir.matches_size(size, scale) { continue }
+
+            for root in &self.roots {
+                if let Some(path) = icon_file_in(&root.join(&dir.subdir), icon_name) {
+                    return Some(path);
+                }
+            }
+        }
+
+        let mut closest = None;
+        let mut minimal_distance = i32::max_value();
+
+        for dir in &self.directories {
+            let distance = dir.size_distance(size, scale);
+            if distance >= minimal_distance { continue }
+
+            for root in &self.roots {
+                if let Some(path) = icon_file_in(&root.join(&dir.subdir), icon_name) {
+                    closest = Some(path);
+                    minimal_distance = distance;
+                    break;
+                }
+            }
+        }
+
+        closest
+    }
+
+
+    /// Names of every icon this theme (not its parents) provides, optionally limited to one Context
+    pub fn icon_names(&self, context: Option<&str>) -> BTreeSet<String> {
+        let mut result = BTreeSet::new();
+
+        for dir in &self.directories {
+            if let Some(c) = context {
+                if dir.context != c { continue }
+            }
+
+            for root in &self.roots {
+                let files = match fs::read_dir(root.join(&dir.subdir)) {
+                    Ok(x)  => x,
+                    Err(_) => continue
+                };
+
+                for file in files.filter_map(|f| f.ok()) {
+                    let path = file.path();
+                    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+                    if !ICON_EXTENSIONS.contains(&extension) { continue }
+
+                    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                        result.insert(stem.to_string());
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+
+/// Where icon themes live: ~/.icons, $XDG_DATA_HOME/icons, $XDG_DATA_DIRS/icons and /usr/share/pixmaps,
+/// going by the context's folders
+pub fn base_directories(context: &Context) -> Vec<PathBuf> {
+    let mut result = vec![context.home().join(".icons"), context.data_home().join("icons")];
+    for dir in context.system_data_dirs() {
+        result.push(dir.join("icons"));
+    }
+    result.push(PathBuf::from("/usr/share/pixmaps"));
+    result
+}
+
+
+/// The user's configured icon theme: GNOME's setting, then GTK's settings.ini, then hicolor
+pub fn current_theme_name(context: &Context) -> String {
+    if let Some(name) = gnome_theme_name(context) { return name }
+
+    lazy_static! {
+        static ref THEME_SETTING: Regex = RegexBuilder::new(r"^\s*gtk-icon-theme-name\s*=\s*(.+?)\s*$")
+            .multi_line(true)
+            .build().unwrap();
+    }
+
+    let settings = context.config_home().join("gtk-3.0").join("settings.ini");
+
+    match fs::read_to_string(settings) {
+        Ok(contents) => match THEME_SETTING.captures(&contents) {
+            Some(caps) => caps.get(1).unwrap().as_str().trim_matches('"').to_string(),
+            None       => FALLBACK_THEME.to_string()
+        },
+        Err(_) => FALLBACK_THEME.to_string()
+    }
+}
+
+
+/// icon-theme from org.gnome.desktop.interface, where that schema is installed
+/// (GSettings aborts on schemas it doesn't know, so it's looked up first)
+fn gnome_theme_name(context: &Context) -> Option<String> {
+    let gsettings = context.gsettings()?;
+    let schema = gsettings.schemas.lookup(INTERFACE_SCHEMA, true)?;
+    let name = Settings::new_full(&schema, gsettings.backend.as_ref(), None).get_string("icon-theme")?.to_string();
+    if name.is_empty() { None } else { Some(name) }
+}
+
+
+/// Where icons are looked up, taken from a Context. It's plain data, so it can go along to a background thread.
+#[derive(Clone, Debug)]
+pub struct IconSearch {
+    pub bases: Vec<PathBuf>,
+    /// The user's theme, searched before the ones it inherits from
+    pub theme: String,
+}
+
+impl IconSearch {
+    pub fn new(context: &Context) -> IconSearch {
+        IconSearch { bases: base_directories(context), theme: current_theme_name(context) }
+    }
+
+
+    /// The current theme followed by everything it inherits from, ending with hicolor. Like FindIconHelper
+    /// in the Icon Theme Specification, each parent's own parents come before the next parent.
+    pub fn theme_chain(&self) -> Vec<IconTheme> {
+        let mut result = Vec::new();
+        let mut visited = HashSet::new();
+        self.add_with_parents(&self.theme, &mut visited, &mut result);
+
+        if let Some(hicolor) = IconTheme::load(&self.bases, FALLBACK_THEME) {
+            result.push(hicolor);
+        }
+
+        result
+    }
+
+    fn add_with_parents(&self, name: &str, visited: &mut HashSet<String>, result: &mut Vec<IconTheme>) {
+        if name == FALLBACK_THEME || !visited.insert(name.to_string()) { return }
+
+        if let Some(theme) = IconTheme::load(&self.bases, name) {
+            let parents = theme.inherits.clone();
+            result.push(theme);
+            for parent in &parents {
+                self.add_with_parents(parent, visited, result);
+            }
+        }
+    }
+
+
+    /// Resolves whatever is in an entry's `Icon=` to a file: absolute paths are used as-is,
+    /// anything else is looked up as a theme icon name.
+    pub fn find_icon(&self, icon: &str, size: i32) -> Option<PathBuf> {
+        if icon.is_empty() { return None }
+
+        if icon.contains('/') {
+            let path = PathBuf::from(icon);
+            return if path.is_file() { Some(path) } else { None };
+        }
+
+        for theme in self.theme_chain() {
+            if let Some(path) = theme.lookup_icon(icon, size, 1) {
+                return Some(path);
+            }
+        }
+
+        // LookupFallbackIcon: loose files directly in a base directory
+        for base in &self.bases {
+            if let Some(path) = icon_file_in(base, icon) {
+                return Some(path);
+            }
+        }
+
+        None
+    }
+
+
+    /// All themed icon names available to the user, optionally limited to one Context (e.g. "Applications")
+    pub fn list_icon_names(&self, icon_context: Option<&str>) -> Vec<String> {
+        let mut result = BTreeSet::new();
+
+        for theme in self.theme_chain() {
+            result.extend(theme.icon_names(icon_context));
+        }
+
+        result.into_iter().collect()
+    }
+}
+
+
+/// IconSearch::find_icon with the context's folders and theme
+pub fn find_icon(context: &Context, icon: &str, size: i32) -> Option<PathBuf> {
+    IconSearch::new(context).find_icon(icon, size)
+}
+
+
+fn icon_file_in(dir: &Path, icon_name: &str) -> Option<PathBuf> {
+    for extension in ICON_EXTENSIONS.iter() {
+        let path = dir.join(format!("{}.{}", icon_name, extension));
+        if path.is_file() { return Some(path) }
+    }
+    None
+}
+
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{IconTheme, IconSearch, DirectoryType, current_theme_name, INTERFACE_SCHEMA};
+    use std::io;
+    use std::fs;
+    use std::process::Command;
+    use gio::prelude::*;
+    use gio::{Settings, SettingsSchemaSource};
+    use super::super::context::{TestDir, GSettingsSource};
+
+    #[test]
+    fn icon_theme_can_parse_index_theme() {
+        let index = "[Icon Theme]
+Name=Test
+Inherits=Adwaita,hicolor
+Directories=48x48/apps,scalable/apps
+
+[48x48/apps]
+Size=48
+Context=Applications
+Type=Fixed
+
+[scalable/apps]
+Size=128
+MinSize=8
+MaxSize=512
+Context=Applications
+Type=Scalable
+
+[unlisted]
+Size=16";
+        let mut stream = io::Cursor::new(index);
+        let theme = IconTheme::read("test", &mut stream);
+
+        assert_eq!(theme.inherits, vec!["Adwaita", "hicolor"]);
+        assert_eq!(theme.directories.len(), 2);
+
+        let fixed = &theme.directories[0];
+        assert_eq!(fixed.dir_type, DirectoryType::Fixed);
+        assert!(fixed.matches_size(48, 1));
+        assert!(!fixed.matches_size(32, 1));
+        assert!(!fixed.matches_size(48, 2));
+        assert_eq!(fixed.size_distance(32, 1), 16);
+
+        let scalable = &theme.directories[1];
+        assert!(scalable.matches_size(300, 1));
+        assert_eq!(scalable.size_distance(4, 1), 4);
+        assert_eq!(scalable.size_distance(600, 1), 88);
+    }
+
+
+    #[test]
+    fn inherited_themes_are_searched_depth_first() {
+        let base = TestDir::new("icon-themes");
+        for (name, inherits) in &[("Top", "Left,Right"), ("Left", "Deep"), ("Right", "hicolor"), ("Deep", ""), ("hicolor", "")] {
+            fs::create_dir_all(base.join(name)).unwrap();
+            fs::write(base.join(name).join("index.theme"), format!("[Icon Theme]\nInherits={}\n", inherits)).unwrap();
+        }
+
+        let search = IconSearch { bases: vec![base.to_path_buf()], theme: String::from("Top") };
+        let chain: Vec<String> = search.theme_chain().into_iter().map(|t| t.name).collect();
+        assert_eq!(chain, vec!["Top", "Left", "Deep", "Right", "hicolor"]);
+    }
+
+
+    #[test]
+    fn the_theme_comes_from_gsettings_before_settings_ini() {
+        let root = TestDir::new("icon-theme-name");
+        let mut context = root.context();
+        assert_eq!(current_theme_name(&context), "hicolor");
+
+        fs::create_dir_all(root.join("config").join("gtk-3.0")).unwrap();
+        fs::write(root.join("config").join("gtk-3.0").join("settings.ini"), "[Settings]\ngtk-icon-theme-name = \"Breeze\"\n").unwrap();
+        assert_eq!(current_theme_name(&context), "Breeze");
+
+        // A schema source of our own, so this runs without GNOME installed
+        fs::write(root.join("interface.gschema.xml"), format!("<schemalist><schema id=\"{}\" path=\"/org/gnome/desktop/interface/\">\
+            <key name=\"icon-theme\" type=\"s\"><default>''</default></key></schema></schemalist>", INTERFACE_SCHEMA)).unwrap();
+        let status = Command::new("glib-compile-schemas").arg(&*root).status().expect("glib-compile-schemas is needed for this test");
+        assert!(status.success());
+        let schemas = SettingsSchemaSource::new_from_directory(&root, None, true).unwrap();
+        let gsettings = GSettingsSource { schemas: schemas, backend: gio::memory_settings_backend_new() };
+
+        let settings = Settings::new_full(&gsettings.schemas.lookup(INTERFACE_SCHEMA, true).unwrap(), gsettings.backend.as_ref(), None);
+        context.set_gsettings(Some(gsettings.clone()));
+        assert_eq!(current_theme_name(&context), "Breeze");
+        settings.set_string("icon-theme", "Papirus").unwrap();
+        assert_eq!(current_theme_name(&context), "Papirus");
+    }
+}
@@ -0,0 +1,300 @@
+extern crate gdk_pixbuf;
+extern crate glib;
+
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::collections::HashSet;
+
+use gdk_pixbuf::Pixbuf;
+
+use super::desktop::name_to_filename;
+use super::context::Context;
+use super::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+
+
+/// Every icon installed by mkdesktop starts with this, so we know which ones we're allowed to clean up
+pub const MANAGED_ICON_PREFIX: &str = "mkdesktop-";
+
+/// Sizes that raster icons get installed at (only the ones that don't require upscaling)
+pub const ICON_SIZES: [i32; 7] = [16, 24, 32, 48, 64, 128, 256];
+
+
+/// $XDG_DATA_HOME/icons/hicolor, going by the context's data_home
+pub fn icons_dir(context: &Context) -> PathBuf {
+    context.data_home().join("icons").join("hicolor")
+}
+
+
+pub fn is_managed(icon: &str) -> bool {
+    icon.starts_with(MANAGED_ICON_PREFIX) && !icon.contains('/')
+}
+
+
+/// Copies the icon at `source` into the hicolor theme under a name generated from the entry name
+/// and the icon's contents. Returns the theme name to use for `Icon=`.
+pub fn install_icon(context: &Context, source: &Path, entry_name: &str) -> io::Result<String> {
+    // Already one of ours (e.g. the editor handed us back an installed file)
+    if source.starts_with(icons_dir(context)) {
+        if let Some(stem) = source.file_stem().and_then(|s| s.to_str()) {
+            if is_managed(stem) { return Ok(stem.to_string()) }
+        }
+    }
+
+    let contents = fs::read(source)?;
+    let icon_name = generate_icon_name(entry_name, &contents);
+
+    let extension = source.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+
+    if extension == "svg" || extension == "svgz" {
+        let mut dest = icons_dir(context);
+        dest.push("scalable");
+        dest.push("apps");
+        fs::create_dir_all(&dest)?;
+        dest.push(format!("{}.svg", icon_name));
+
+        // Icon themes only know .svg, so compressed ones go in uncompressed
+        if contents.starts_with(&GZIP_MAGIC) { fs::write(dest, gunzip(source)?)?; }
+        else                                 { fs::write(dest, &contents)?; }
+    }
+    else {
+        rasterize_icon(context, source, &icon_name)?;
+    }
+
+    refresh_icon_cache();
+    Ok(icon_name)
+}
+
+
+/// Deletes managed icons that nothing refers to anymore
+pub fn remove_orphaned_icons(context: &Context) -> io::Result<()> {
+    let in_use = icons_in_use(context);
+
+    let mut removed_any = false;
+    for path in installed_icon_files(context) {
+        let stem = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(s) => s.to_string(),
+            None    => continue
+        };
+
+        if is_managed(&stem) && !in_use.contains(&stem) {
+            fs::remove_file(&path)?;
+            removed_any = true;
+        }
+    }
+
+    if removed_any { refresh_icon_cache() }
+    Ok(())
+}
+
+
+/// Finds the best file for a managed icon name (scalable first, then the largest raster)
+pub fn managed_icon_path(context: &Context, icon_name: &str) -> Option<PathBuf> {
+    if !is_managed(icon_name) { return None }
+
+    let mut scalable = icons_dir(context);
+    scalable.push("scalable");
+    scalable.push("apps");
+    scalable.push(format!("{}.svg", icon_name));
+    if scalable.is_file() { return Some(scalable) }
+
+    for size in ICON_SIZES.iter().rev() {
+        let mut raster = size_dir(context, *size);
+        raster.push(format!("{}.png", icon_name));
+        if raster.is_file() { return Some(raster) }
+    }
+
+    None
+}
+
+
+/// Icon= of every file that could point at one of our icons: entries (even ones that don't parse strictly),
+/// their registered, autostart and desktop copies, menu folders and overrides
+fn icons_in_use(context: &Context) -> HashSet<String> {
+    let folders = [
+        context.data_dir(),
+        context.applications_dir(),
+        context.desktop_directories_dir(),
+        context.autostart_dir(),
+        context.desktop_folder(),
+    ];
+
+    let mut result = HashSet::new();
+    for folder in folders.iter() {
+        let files = match fs::read_dir(folder) {
+            Ok(x)  => x,
+            Err(_) => continue
+        };
+
+        for file in files.filter_map(|f| f.ok()) {
+            let path = file.path();
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+            if extension != "desktop" && extension != "directory" { continue }
+
+            if let Some(icon) = KeyFile::open_lenient(&path).ok().and_then(|k| k.get_string(DESKTOP_ENTRY_GROUP, "Icon")) {
+                result.insert(icon);
+            }
+        }
+    }
+    result
+}
+
+
+/// 64-bit FNV-1a. Unlike std's hasher it comes out the same in every build,
+/// so names and hashes made from it stay the same across updates.
+pub fn content_hash(contents: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in contents {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+
+fn generate_icon_name(entry_name: &str, contents: &[u8]) -> String {
+    // name_to_filename already gives us "mkdesktop-<sanitized name>.desktop"
+    let filename = name_to_filename(entry_name);
+    let stem = filename.trim_end_matches(".desktop");
+
+    format!("{}-{:08x}", stem, content_hash(contents) as u32)
+}
+
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// .svgz files are gzipped, which gzip itself undoes
+fn gunzip(source: &Path) -> io::Result<Vec<u8>> {
+    let output = Command::new("gzip").arg("-dc").arg(source).stderr(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} couldn't be decompressed", source.display())));
+    }
+    Ok(output.stdout)
+}
+
+
+fn rasterize_icon(context: &Context, source: &Path, icon_name: &str) -> io::Result<()> {
+    let original = Pixbuf::new_from_file(source).map_err(glib_to_io_error)?;
+    let largest_side = original.get_width().max(original.get_height());
+
+    // Don't upscale, but always install at least the smallest size
+    let mut sizes: Vec<i32> = ICON_SIZES.iter().cloned().filter(|s| *s <= largest_side).collect();
+    if sizes.is_empty() { sizes.push(ICON_SIZES[0]) }
+
+    for size in sizes {
+        let pixbuf = Pixbuf::new_from_file_at_scale(source, size, size, true).map_err(glib_to_io_error)?;
+
+        let mut dest = size_dir(context, size);
+        fs::create_dir_all(&dest)?;
+        dest.push(format!("{}.png", icon_name));
+
+        pixbuf.savev(&dest, "png", &[]).map_err(glib_to_io_error)?;
+    }
+
+    Ok(())
+}
+
+
+fn size_dir(context: &Context, size: i32) -> PathBuf {
+    let mut result = icons_dir(context);
+    result.push(format!("{}x{}", size, size));
+    result.push("apps");
+    result
+}
+
+
+/// Every file in <icons_dir>/*/apps/ that looks like one of ours
+fn installed_icon_files(context: &Context) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+
+    let size_dirs = match fs::read_dir(icons_dir(context)) {
+        Ok(x)  => x,
+        Err(_) => return result
+    };
+
+    for size_dir in size_dirs.filter_map(|d| d.ok()) {
+        let apps_dir = size_dir.path().join("apps");
+        let icons = match fs::read_dir(apps_dir) {
+            Ok(x)  => x,
+            Err(_) => continue
+        };
+
+        for icon in icons.filter_map(|i| i.ok()) {
+            let path = icon.path();
+            let managed = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.starts_with(MANAGED_ICON_PREFIX),
+                None       => false
+            };
+            if managed { result.push(path) }
+        }
+    }
+
+    result
+}
+
+
+/// Asks the desktop to notice the new icons. Failure here just means a stale cache, so it's ignored.
+fn refresh_icon_cache() {
+    let _ = Command::new("xdg-icon-resource").arg("forceupdate").status();
+}
+
+
+fn glib_to_io_error(error: glib::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{}", error))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+    use super::super::desktop::DesktopEntry;
+    use super::super::exec::Exec;
+
+    const SVG: &str = "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"16\" height=\"16\"/>\n";
+
+    #[test]
+    fn adopted_icons_are_installed_under_stable_names() {
+        let root = TestDir::new("icons-adopt");
+        let context = root.context();
+
+        fs::write(root.join("tool.svg"), SVG).unwrap();
+        let mut entry = DesktopEntry::new("Tool", "", "", Exec::parse("tool"), &root.join("tool.svg").to_string_lossy(), "");
+        entry.adopt_icon(&context).unwrap();
+        assert_eq!(entry.get_icon(), "mkdesktop-Tool-8dca2795");
+        let installed = icons_dir(&context).join("scalable").join("apps").join("mkdesktop-Tool-8dca2795.svg");
+        assert_eq!(fs::read_to_string(&installed).unwrap(), SVG);
+
+        // A compressed one goes in as plain SVG, since themes don't look for .svgz
+        let status = Command::new("gzip").arg("-k").arg(root.join("tool.svg")).status().expect("gzip is needed for this test");
+        assert!(status.success());
+        fs::rename(root.join("tool.svg.gz"), root.join("tool.svgz")).unwrap();
+        let name = install_icon(&context, &root.join("tool.svgz"), "Tool").unwrap();
+        let installed = icons_dir(&context).join("scalable").join("apps").join(format!("{}.svg", name));
+        assert_eq!(fs::read_to_string(&installed).unwrap(), SVG);
+    }
+
+    #[test]
+    fn only_icons_nothing_refers_to_are_orphans() {
+        let root = TestDir::new("icons-orphans");
+        fs::create_dir_all(root.join("config").join("autostart")).unwrap();
+        let context = root.context();
+
+        let apps = icons_dir(&context).join("scalable").join("apps");
+        fs::create_dir_all(&apps).unwrap();
+        for name in &["mkdesktop-Entry", "mkdesktop-Autostart", "mkdesktop-Broken", "mkdesktop-Gone", "someone-elses"] {
+            fs::write(apps.join(format!("{}.svg", name)), SVG).unwrap();
+        }
+
+        fs::write(context.data_dir().join("mkdesktop-Entry.desktop"), "[Desktop Entry]\nName=Entry\nIcon=mkdesktop-Entry\n").unwrap();
+        // Strict parsing would give up on the repeated key, but the icon is still in use
+        fs::write(context.data_dir().join("mkdesktop-Broken.desktop"), "[Desktop Entry]\nName=Broken\nName=Broken\nIcon=mkdesktop-Broken\n").unwrap();
+        fs::write(context.autostart_dir().join("mkdesktop-Old.desktop"), "[Desktop Entry]\nName=Old\nIcon=mkdesktop-Autostart\n").unwrap();
+
+        remove_orphaned_icons(&context).unwrap();
+        let mut left: Vec<String> = fs::read_dir(&apps).unwrap().map(|f| f.unwrap().file_name().to_string_lossy().to_string()).collect();
+        left.sort();
+        assert_eq!(left, vec!["mkdesktop-Autostart.svg", "mkdesktop-Broken.svg", "mkdesktop-Entry.svg", "someone-elses.svg"]);
+    }
+}
@@ -0,0 +1,461 @@
+extern crate regex;
+
+use std::io;
+use std::fs;
+use std::fmt;
+use std::path::Path;
+
+use regex::{Regex, RegexBuilder};
+
+
+pub const DESKTOP_ENTRY_GROUP: &str = "Desktop Entry";
+
+
+/// Where and why a desktop file couldn't be parsed. Lines and columns count from 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, column: usize, message: &str) -> ParseError {
+        ParseError { line: line, column: column, message: message.to_string() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl From<ParseError> for io::Error {
+    fn from(error: ParseError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+    }
+}
+
+
+#[derive(Clone)]
+enum Line {
+    Group(String),
+    Entry(String, String),
+    /// Comments and blank lines -- written back untouched
+    Other(String),
+}
+
+
+/// A desktop file as a plain list of groups and key/value lines.
+/// Unlike DesktopEntry this keeps everything (comments, localized keys, X- keys, actions),
+/// so it's what to use when changing single keys without disturbing the rest of the file.
+#[derive(Clone)]
+pub struct KeyFile {
+    lines: Vec<Line>,
+}
+
+impl KeyFile {
+    /// Reads a whole file, failing on the first line that isn't valid (see KeyFile::parse)
+    pub fn read(input: &mut io::BufRead) -> io::Result<KeyFile> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(error) => {
+                // Everything before the bad byte is fine, so that part can be counted as text
+                let valid = String::from_utf8_lossy(&error.as_bytes()[..error.utf8_error().valid_up_to()]).to_string();
+                let line = valid.matches('\n').count() + 1;
+                let column = valid.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+                return Err(ParseError::new(line, column, "Isn't valid UTF-8").into());
+            }
+        };
+
+        KeyFile::parse(&text).map_err(io::Error::from)
+    }
+
+
+    /// Parses a desktop file line by line. Every line has to be blank, a #comment, a [Group] header or a Key=Value
+    /// inside a group; keys can't repeat within a group and groups can't repeat. Values are kept as written
+    /// (escapes and all) and only the whitespace around `=` and at the ends of the line is dropped.
+    pub fn parse(text: &str) -> Result<KeyFile, ParseError> {
+        let mut lines = Vec::new();
+        let mut groups: Vec<String> = Vec::new();
+        let mut keys: Vec<String> = Vec::new();
+
+        let mut raw_lines: Vec<&str> = text.split('\n').collect();
+        if raw_lines.last() == Some(&"") { raw_lines.pop(); }
+
+        for (index, raw) in raw_lines.into_iter().enumerate() {
+            let number = index + 1;
+            let text = raw.trim_end_matches('\r');
+            let trimmed = text.trim();
+            let indent = text.chars().count() - text.trim_start().chars().count();
+
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                lines.push(Line::Other(text.to_string()));
+            }
+            else if trimmed.starts_with('[') {
+                if !trimmed.ends_with(']') {
+                    return Err(ParseError::new(number, indent + 1, "Group header is missing its closing ]"));
+                }
+                let name = &trimmed[1..trimmed.len() - 1];
+                if let Some(offset) = name.chars().position(|c| c == '[' || c == ']' || c.is_control()) {
+                    return Err(ParseError::new(number, indent + 2 + offset, "Group names can't contain [, ] or control characters"));
+                }
+                if groups.iter().any(|g| g == name) {
+                    return Err(ParseError::new(number, indent + 1, &format!("Group [{}] appears more than once", name)));
+                }
+
+                groups.push(name.to_string());
+                keys.clear();
+                lines.push(Line::Group(name.to_string()));
+            }
+            else {
+                let equals = match trimmed.find('=') {
+                    Some(i) => i,
+                    None    => return Err(ParseError::new(number, indent + 1, "Expected a [Group], a Key=Value or a #comment"))
+                };
+                let key = trimmed[..equals].trim();
+                let value = trimmed[equals + 1..].trim();
+
+                if groups.is_empty() {
+                    return Err(ParseError::new(number, indent + 1, "Key is outside of any group"));
+                }
+                if !is_valid_key(key) {
+                    return Err(ParseError::new(number, indent + 1, &format!("\"{}\" isn't a valid key", key)));
+                }
+                if keys.iter().any(|k| k == key) {
+                    return Err(ParseError::new(number, indent + 1, &format!("{} appears more than once in [{}]", key, groups.last().unwrap())));
+                }
+
+                keys.push(key.to_string());
+                lines.push(Line::Entry(key.to_string(), value.to_string()));
+            }
+        }
+
+        Ok(KeyFile { lines: lines })
+    }
+
+
+    /// Reads files in the same format that other programs write, the way GLib and KConfig read them rather than
+    /// the way the spec says: a group that appears again is merged into the first one, a repeated key keeps its
+    /// last value, and lines that are neither are kept as they are. Keys can be anything (mimeapps.list is keyed
+    /// by MIME type, KDE's have spaces), and group names can nest like KConfig's [services][org.kde.konsole.desktop],
+    /// which comes out as the group "services][org.kde.konsole.desktop".
+    pub fn parse_lenient(text: &str) -> KeyFile {
+        let mut before_groups: Vec<Line> = Vec::new();
+        let mut groups: Vec<(String, Vec<Line>)> = Vec::new();
+        let mut current: Option<usize> = None;
+
+        for raw in text.lines() {
+            let trimmed = raw.trim();
+
+            if trimmed.len() >= 2 && trimmed.starts_with('[') && trimmed.ends_with(']') {
+                let name = &trimmed[1..trimmed.len() - 1];
+                current = match groups.iter().position(|(group, _)| group == name) {
+                    Some(index) => Some(index),
+                    None => {
+                        groups.push((name.to_string(), Vec::new()));
+                        Some(groups.len() - 1)
+                    }
+                };
+                continue;
+            }
+
+            let entry = match (current, trimmed.find('=')) {
+                (Some(_), Some(equals)) if !trimmed.starts_with('#') && equals > 0 => {
+                    Some((trimmed[..equals].trim().to_string(), trimmed[equals + 1..].trim().to_string()))
+                }
+                _ => None
+            };
+            match (current, entry) {
+                (Some(index), Some((key, value))) => {
+                    let group_lines = &mut groups[index].1;
+                    group_lines.retain(|line| match line {
+                        Line::Entry(k, _) => *k != key,
+                        _ => true
+                    });
+                    group_lines.push(Line::Entry(key, value));
+                }
+                (Some(index), None) => groups[index].1.push(Line::Other(raw.to_string())),
+                (None, _) => before_groups.push(Line::Other(raw.to_string())),
+            }
+        }
+
+        let mut lines = before_groups;
+        for (name, group_lines) in groups {
+            lines.push(Line::Group(name));
+            lines.extend(group_lines);
+        }
+        KeyFile { lines: lines }
+    }
+
+    /// A file read with parse_lenient, with bytes that aren't UTF-8 replaced. A missing file is an empty one.
+    pub fn open_lenient(path: &Path) -> io::Result<KeyFile> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(KeyFile::parse_lenient(&String::from_utf8_lossy(&bytes))),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(KeyFile::parse_lenient("")),
+            Err(e) => Err(e)
+        }
+    }
+
+
+    pub fn open(path: &Path) -> io::Result<KeyFile> {
+        let file = fs::File::open(path)?;
+        KeyFile::read(&mut io::BufReader::new(file))
+    }
+
+
+    pub fn write(&self, output: &mut io::Write) -> io::Result<()> {
+        for line in &self.lines {
+            match line {
+                Line::Group(name)        => output.write_fmt(format_args!("[{}]\n", name))?,
+                Line::Entry(key, value)  => output.write_fmt(format_args!("{}={}\n", key, value))?,
+                Line::Other(text)        => output.write_fmt(format_args!("{}\n", text))?,
+            }
+        }
+        output.flush()
+    }
+
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        self.write(&mut result).expect("Writing to a Vec can't fail");
+        result
+    }
+
+
+    /// Group names in file order
+    pub fn groups(&self) -> Vec<&str> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Group(name) => Some(name.as_str()),
+            _ => None
+        }).collect()
+    }
+
+
+    pub fn has_group(&self, group: &str) -> bool {
+        self.lines.iter().any(|line| match line {
+            Line::Group(name) => name == group,
+            _ => false
+        })
+    }
+
+
+    /// The value as it's written in the file, escapes included
+    pub fn get(&self, group: &str, key: &str) -> Option<&str> {
+        let (start, end) = self.group_range(group)?;
+        self.lines[start..end].iter().filter_map(|line| match line {
+            Line::Entry(k, v) if k == key => Some(v.as_str()),
+            _ => None
+        }).last()
+    }
+
+
+    /// The value with escapes like \s and \n turned back into the characters they stand for
+    pub fn get_string(&self, group: &str, key: &str) -> Option<String> {
+        self.get(group, key).map(unescape_value)
+    }
+
+
+    /// Keys of a group in file order
+    pub fn keys(&self, group: &str) -> Vec<&str> {
+        match self.group_range(group) {
+            Some((start, end)) => self.lines[start..end].iter().filter_map(|line| match line {
+                Line::Entry(k, _) => Some(k.as_str()),
+                _ => None
+            }).collect(),
+            None => Vec::new()
+        }
+    }
+
+
+    /// Replaces the value in place if the key exists, otherwise adds it after the group's last key.
+    /// The group is created at the end of the file if it doesn't exist yet.
+    pub fn set(&mut self, group: &str, key: &str, value: &str) {
+        let entry = Line::Entry(key.to_string(), escape_value(value));
+
+        let (start, end) = match self.group_range(group) {
+            Some(range) => range,
+            None => {
+                if !self.lines.is_empty() { self.lines.push(Line::Other(String::new())) }
+                self.lines.push(Line::Group(group.to_string()));
+                self.lines.push(entry);
+                return;
+            }
+        };
+
+        let mut insert_at = start;
+        for i in start..end {
+            if let Line::Entry(k, _) = &self.lines[i] {
+                if k == key {
+                    self.lines[i] = entry;
+                    return;
+                }
+                insert_at = i + 1;
+            }
+        }
+        self.lines.insert(insert_at, entry);
+    }
+
+
+    /// Removes every occurrence of the key from the group. Returns whether anything was removed.
+    pub fn unset(&mut self, group: &str, key: &str) -> bool {
+        let (start, end) = match self.group_range(group) {
+            Some(range) => range,
+            None => return false
+        };
+
+        let before = self.lines.len();
+        let mut i = start;
+        let mut end = end;
+        while i < end {
+            let matches = match &self.lines[i] {
+                Line::Entry(k, _) => k == key,
+                _ => false
+            };
+            if matches { self.lines.remove(i); end -= 1; }
+            else       { i += 1; }
+        }
+        self.lines.len() != before
+    }
+
+
+    /// Index of the first line after the group header, and the index of the next group header (or the end)
+    fn group_range(&self, group: &str) -> Option<(usize, usize)> {
+        let header = self.lines.iter().position(|line| match line {
+            Line::Group(name) => name == group,
+            _ => false
+        })?;
+
+        let end = self.lines[header + 1..].iter()
+            .position(|line| match line { Line::Group(_) => true, _ => false })
+            .map(|offset| header + 1 + offset)
+            .unwrap_or(self.lines.len());
+
+        Some((header + 1, end))
+    }
+}
+
+
+/// Keys are alphanumerics and dashes, optionally followed by a [locale] (e.g. Name[de_DE@euro])
+pub fn is_valid_key(key: &str) -> bool {
+    lazy_static! {
+        static ref KEY_REGEX: Regex = RegexBuilder::new(r"^[A-Za-z0-9\-]+(\[[A-Za-z]+(_[A-Za-z]+)?(\.[A-Za-z0-9\-]+)?(@[A-Za-z]+)?\])?$")
+            .build().unwrap();
+    }
+    KEY_REGEX.is_match(key)
+}
+
+
+/// Parses "Key=Value" (or "Key[locale]=Value") as given on the command line
+pub fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    let mut parts = assignment.splitn(2, '=');
+    let key = parts.next().unwrap().trim();
+    let value = match parts.next() {
+        Some(v) => v,
+        None    => return Err(format!("\"{}\" isn't of the form Key=Value", assignment))
+    };
+
+    if !is_valid_key(key) {
+        return Err(format!("\"{}\" isn't a valid key (letters, digits and dashes, optionally followed by [locale])", key));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+
+/// Values have to stay on one line, and spaces at either end would be taken for the whitespace around `=`
+pub fn escape_value(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r");
+
+    let body = escaped.trim_matches(' ');
+    if body.len() == escaped.len() { return escaped }
+
+    let leading = escaped.len() - escaped.trim_start_matches(' ').len();
+    let trailing = if body.is_empty() { 0 } else { escaped.len() - escaped.trim_end_matches(' ').len() };
+    format!("{}{}{}", "\\s".repeat(leading), body, "\\s".repeat(trailing))
+}
+
+
+/// Undoes escape_value. Unknown escapes are left as they are.
+pub fn unescape_value(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' { result.push(c); continue }
+
+        match chars.next() {
+            Some('s')  => result.push(' '),
+            Some('n')  => result.push('\n'),
+            Some('t')  => result.push('\t'),
+            Some('r')  => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(other) => { result.push('\\'); result.push(other); }
+            None        => result.push('\\')
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn keyfile_sets_and_unsets_keys_without_touching_the_rest() {
+        let desktop_string = "[Desktop Entry]
+# A comment that should survive
+Type=Application
+Name=Thing
+Name[de]=Ding
+Exec=thing
+
+[Desktop Action delete-shortcut]
+Name=Delete Shortcut
+";
+        let mut keyfile = KeyFile::read(&mut io::Cursor::new(desktop_string)).unwrap();
+
+        keyfile.set(DESKTOP_ENTRY_GROUP, "Name[de]", "Sache");
+        keyfile.set(DESKTOP_ENTRY_GROUP, "X-Thing-Mode", "fast");
+        assert!(keyfile.unset(DESKTOP_ENTRY_GROUP, "Type"));
+        assert!(!keyfile.unset(DESKTOP_ENTRY_GROUP, "Icon"));
+
+        assert_eq!(keyfile.get(DESKTOP_ENTRY_GROUP, "Name[de]"), Some("Sache"));
+        assert_eq!(keyfile.get("Desktop Action delete-shortcut", "Name"), Some("Delete Shortcut"));
+        assert_eq!(String::from_utf8(keyfile.to_bytes()).unwrap(), "[Desktop Entry]
+# A comment that should survive
+Name=Thing
+Name[de]=Sache
+Exec=thing
+X-Thing-Mode=fast
+
+[Desktop Action delete-shortcut]
+Name=Delete Shortcut
+");
+
+        assert!(parse_assignment("Comment[pt_BR]=Olá=oi").is_ok());
+        assert!(parse_assignment("Comment").is_err());
+        assert!(parse_assignment("Bad Key=1").is_err());
+    }
+
+
+    #[test]
+    fn keyfile_parse_errors_point_at_the_line() {
+        let error = KeyFile::parse("[Desktop Entry]\nName=Thing\n  Exec thing\n").err().unwrap();
+        assert_eq!((error.line, error.column), (3, 3));
+
+        let keyfile = KeyFile::parse("[Desktop Entry]\nExec=app --color=#fff\nName=\\sTwo\\nLines\n").unwrap();
+        assert_eq!(keyfile.get(DESKTOP_ENTRY_GROUP, "Exec"), Some("app --color=#fff"));
+        assert_eq!(keyfile.get_string(DESKTOP_ENTRY_GROUP, "Name").unwrap(), " Two\nLines");
+        assert_eq!(unescape_value(&escape_value("  tabs\tand \\ ")), "  tabs\tand \\ ");
+    }
+}
@@ -3,6 +3,26 @@ extern crate lazy_static;
 extern crate path_abs;
 extern crate dirs;
  
+pub mod context;
 pub mod desktop;
+pub mod keyfile;
+pub mod exec;
+pub mod scan;
+pub mod doctor;
+pub mod reconcile;
+pub mod menu;
+pub mod webapp;
+pub mod autostart;
+pub mod mime;
+pub mod desktop_icon;
+pub mod shortcut;
+pub mod wine;
+pub mod packages;
+pub mod overrides;
+pub mod icons;
+pub mod icon_theme;
+pub mod icon_sources;
+pub mod categories;
+pub mod completions;
 pub mod cli;
 pub mod gui;
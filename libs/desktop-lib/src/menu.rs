@@ -0,0 +1,242 @@
+use std::io;
+use std::fs;
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use super::context::Context;
+use super::desktop::{self, DesktopEntry};
+
+
+const MENU_DOCTYPE: &str = "<!DOCTYPE Menu PUBLIC \"-//freedesktop//DTD Menu 1.0//EN\"\n \
+                            \"http://www.freedesktop.org/standards/menu-spec/1.0/menu.dtd\">";
+
+
+/// A submenu of the applications menu, kept as a fragment in the context's menus_dir.
+/// Its title and icon come from a Type=Directory entry, and it lists entries by desktop file ID.
+/// Entries still show up under their Categories too; this only adds them to the submenu.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Menu {
+    name: String,
+    directory: String,
+    entries: Vec<String>,
+}
+
+impl Menu {
+    pub fn new(name: &str) -> Menu {
+        Menu {
+            name: name.to_string(),
+            directory: DesktopEntry::directory(name, "", "").filename(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &str { &self.name }
+    /// The .directory file the submenu takes its title and icon from
+    pub fn get_directory(&self) -> &str { &self.directory }
+    /// Desktop file IDs of the entries in the submenu
+    pub fn get_entries(&self) -> &[String] { &self.entries }
+
+
+    pub fn filename(&self) -> String {
+        format!("{}.menu", desktop::name_to_filename(&self.name).trim_end_matches(".desktop"))
+    }
+
+    pub fn filepath(&self, context: &Context) -> PathBuf {
+        context.menus_dir().join(self.filename())
+    }
+
+
+    pub fn contains(&self, desktop_id: &str) -> bool {
+        self.entries.iter().any(|e| e == desktop_id)
+    }
+
+    /// Returns false if the entry was already in the menu
+    pub fn add(&mut self, desktop_id: &str) -> bool {
+        if self.contains(desktop_id) { return false }
+        self.entries.push(desktop_id.to_string());
+        true
+    }
+
+    /// Returns false if the entry wasn't in the menu
+    pub fn remove(&mut self, desktop_id: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e != desktop_id);
+        self.entries.len() != before
+    }
+
+
+    /// The fragment as it gets merged into the applications menu
+    pub fn to_xml(&self) -> String {
+        let mut result = String::new();
+        result += MENU_DOCTYPE;
+        result += "\n<Menu>\n";
+        result += "  <Name>Applications</Name>\n";
+        result += "  <Menu>\n";
+        result += &format!("    <Name>{}</Name>\n", escape_xml(&self.name));
+        result += &format!("    <Directory>{}</Directory>\n", escape_xml(&self.directory));
+        result += "    <Include>\n";
+        for entry in &self.entries {
+            result += &format!("      <Filename>{}</Filename>\n", escape_xml(entry));
+        }
+        result += "    </Include>\n";
+        result += "  </Menu>\n";
+        result += "</Menu>\n";
+        result
+    }
+
+    /// Reads back a fragment written by to_xml. This is not a general menu file parser:
+    /// anything that isn't one submenu of Applications is an error.
+    pub fn parse(xml: &str) -> io::Result<Menu> {
+        lazy_static! {
+            static ref NAME: Regex = Regex::new(r"<Name>([^<]*)</Name>").unwrap();
+            static ref DIRECTORY: Regex = Regex::new(r"<Directory>([^<]*)</Directory>").unwrap();
+            static ref FILENAME: Regex = Regex::new(r"<Filename>([^<]*)</Filename>").unwrap();
+        }
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        let names: Vec<String> = NAME.captures_iter(xml).map(|c| unescape_xml(&c[1])).collect();
+        let name = match names.as_slice() {
+            [applications, name] if applications == "Applications" => name.clone(),
+            _ => return Err(invalid("Not a menu fragment written by mkdesktop"))
+        };
+
+        let directory = match DIRECTORY.captures(xml) {
+            Some(c) => unescape_xml(&c[1]),
+            None    => return Err(invalid("Menu has no Directory"))
+        };
+
+        Ok(Menu {
+            name: name,
+            directory: directory,
+            entries: FILENAME.captures_iter(xml).map(|c| unescape_xml(&c[1])).collect(),
+        })
+    }
+
+
+    /// Writes the fragment. Menus pick up the change on their own.
+    pub fn save(&self, context: &Context) -> io::Result<()> {
+        fs::create_dir_all(context.menus_dir())?;
+        fs::write(self.filepath(context), self.to_xml())
+    }
+
+    /// Removes the fragment and the menu's folder entry
+    pub fn delete(&self, context: &Context) -> io::Result<()> {
+        fs::remove_file(self.filepath(context))?;
+
+        let folder = context.data_dir().join(&self.directory);
+        if folder.exists() {
+            context.unregister(&self.directory)?;
+            fs::remove_file(folder)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Makes a new submenu: its folder entry (registered so the menu can find it) and an empty fragment
+pub fn create(context: &Context, name: &str, comment: &str, icon: &str) -> io::Result<Menu> {
+    let menu = Menu::new(name);
+    if menu.filepath(context).exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("There's already a menu called \"{}\"", name)));
+    }
+
+    let mut folder = DesktopEntry::directory(name, comment, icon);
+    folder.adopt_icon(context)?;
+    folder.write_to_apps_dir(context)?;
+    menu.save(context)?;
+    Ok(menu)
+}
+
+
+/// Every menu mkdesktop manages, sorted by name. Fragments that don't parse are skipped.
+pub fn read_menus(context: &Context) -> io::Result<Vec<Menu>> {
+    let dir = context.menus_dir();
+    if !dir.exists() { return Ok(Vec::new()) }
+
+    let mut result = Vec::new();
+    for direntry in fs::read_dir(dir)? {
+        let path = direntry?.path();
+        let is_ours = path.file_name().and_then(|n| n.to_str())
+            .map(|n| n.starts_with("mkdesktop-") && n.ends_with(".menu"))
+            .unwrap_or(false);
+        if !is_ours { continue }
+
+        match fs::read_to_string(&path).and_then(|xml| Menu::parse(&xml)) {
+            Ok(menu) => result.push(menu),
+            Err(e)   => eprintln!("Couldn't parse {:?} - {}", path, e)
+        }
+    }
+
+    result.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(result)
+}
+
+
+/// Finds a menu by name (ignoring case) or by fragment filename
+pub fn select(context: &Context, selector: &str) -> io::Result<Menu> {
+    let selector = selector.trim();
+    for menu in read_menus(context)? {
+        if menu.name.eq_ignore_ascii_case(selector) || menu.filename() == selector {
+            return Ok(menu);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("No menu called \"{}\"", selector)))
+}
+
+
+/// Points every menu that has `old_id` at `new_id` instead, for when an entry's file gets renamed
+pub fn rename_entry(context: &Context, old_id: &str, new_id: &str) -> io::Result<()> {
+    for mut menu in read_menus(context)? {
+        if menu.remove(old_id) {
+            menu.add(new_id);
+            menu.save(context)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Takes a deleted entry out of every menu it was in
+pub fn forget_entry(context: &Context, desktop_id: &str) -> io::Result<()> {
+    for mut menu in read_menus(context)? {
+        if menu.remove(desktop_id) {
+            menu.save(context)?;
+        }
+    }
+    Ok(())
+}
+
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn menu_fragments_read_back_the_same() {
+        let mut menu = Menu::new("R&D <Tools>");
+        assert!(menu.add("mkdesktop-Scope.desktop"));
+        assert!(menu.add("mkdesktop-Logic-Analyzer.desktop"));
+        assert!(!menu.add("mkdesktop-Scope.desktop"));
+
+        let xml = menu.to_xml();
+        assert!(xml.contains("<Name>R&amp;D &lt;Tools&gt;</Name>"), "{}", xml);
+        assert!(xml.contains("<Directory>mkdesktop-R-D-Tools-.directory</Directory>"), "{}", xml);
+        assert_eq!(Menu::parse(&xml).unwrap(), menu);
+
+        assert!(menu.remove("mkdesktop-Scope.desktop"));
+        assert_eq!(menu.get_entries(), &["mkdesktop-Logic-Analyzer.desktop".to_string()]);
+        assert_eq!(menu.filename(), "mkdesktop-R-D-Tools-.menu");
+
+        assert!(Menu::parse("<Menu><Name>Applications</Name></Menu>").is_err());
+    }
+}
@@ -0,0 +1,435 @@
+use std::io;
+use std::fs;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::cmp::Reverse;
+
+use regex::Regex;
+
+use super::context::Context;
+use super::desktop::glob_matches;
+use super::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+
+
+pub const DEFAULT_APPLICATIONS: &str = "Default Applications";
+pub const ADDED_ASSOCIATIONS: &str = "Added Associations";
+pub const REMOVED_ASSOCIATIONS: &str = "Removed Associations";
+
+
+/// type/subtype as in RFC 6838, e.g. text/markdown or application/vnd.oasis.opendocument.text
+pub fn is_valid_mime_type(mime_type: &str) -> bool {
+    lazy_static! {
+        static ref MIME_REGEX: Regex = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9!#$&^_.+\-]*/[A-Za-z0-9][A-Za-z0-9!#$&^_.+\-]*$").unwrap();
+    }
+    MIME_REGEX.is_match(mime_type)
+}
+
+
+/// Splits a MimeType= value (or one typed by the user, which may use commas) into its types
+pub fn split(list: &str) -> Vec<String> {
+    list.split(&[';', ','][..])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// The other way around, with the trailing semicolon the spec asks for
+pub fn join(mime_types: &[String]) -> String {
+    mime_types.iter().map(|t| format!("{};", t)).collect()
+}
+
+
+/// The user's mimeapps.list, which says which application opens what.
+/// Only touches the groups from the spec, anything else in the file is kept as it is.
+pub struct MimeApps {
+    keyfile: KeyFile,
+}
+
+impl MimeApps {
+    /// A missing file is just an empty list
+    pub fn open(context: &Context) -> io::Result<MimeApps> {
+        MimeApps::open_path(&context.mimeapps_list())
+    }
+
+    /// Several tools write repeated groups and keys into these, so they're read the forgiving way
+    fn open_path(path: &Path) -> io::Result<MimeApps> {
+        Ok(MimeApps { keyfile: KeyFile::open_lenient(path)? })
+    }
+
+    pub fn save(&self, context: &Context) -> io::Result<()> {
+        fs::create_dir_all(context.config_home())?;
+        fs::write(context.mimeapps_list(), self.keyfile.to_bytes())
+    }
+
+
+    /// Every type the group has a line for
+    pub fn mime_types(&self, group: &str) -> Vec<String> {
+        self.keyfile.keys(group).into_iter().map(String::from).collect()
+    }
+
+    /// The desktop file IDs listed for the type in one of the groups, in order
+    pub fn get(&self, group: &str, mime_type: &str) -> Vec<String> {
+        self.keyfile.get_string(group, mime_type).map(|value| split(&value)).unwrap_or_default()
+    }
+
+    fn put(&mut self, group: &str, mime_type: &str, ids: &[String]) {
+        if ids.is_empty() { self.keyfile.unset(group, mime_type); }
+        else              { self.keyfile.set(group, mime_type, &join(ids)) }
+    }
+
+    fn add_to(&mut self, group: &str, mime_type: &str, desktop_id: &str, first: bool) {
+        let mut ids = self.get(group, mime_type);
+        ids.retain(|id| id != desktop_id);
+        if first { ids.insert(0, desktop_id.to_string()) }
+        else     { ids.push(desktop_id.to_string()) }
+        self.put(group, mime_type, &ids);
+    }
+
+    fn remove_from(&mut self, group: &str, mime_type: &str, desktop_id: &str) {
+        let mut ids = self.get(group, mime_type);
+        ids.retain(|id| id != desktop_id);
+        self.put(group, mime_type, &ids);
+    }
+
+
+    /// The first default for the type, if there is one
+    pub fn default_for(&self, mime_type: &str) -> Option<String> {
+        self.get(DEFAULT_APPLICATIONS, mime_type).into_iter().next()
+    }
+
+    /// Makes the entry the first choice for the type. It's also added as an association
+    /// (and no longer removed), since a default the type isn't associated with gets ignored.
+    pub fn set_default(&mut self, mime_type: &str, desktop_id: &str) {
+        self.add_to(DEFAULT_APPLICATIONS, mime_type, desktop_id, true);
+        self.add_association(mime_type, desktop_id);
+    }
+
+    /// Stops the entry being the default, leaving it associated with the type
+    pub fn unset_default(&mut self, mime_type: &str, desktop_id: &str) {
+        self.remove_from(DEFAULT_APPLICATIONS, mime_type, desktop_id);
+    }
+
+    pub fn add_association(&mut self, mime_type: &str, desktop_id: &str) {
+        self.add_to(ADDED_ASSOCIATIONS, mime_type, desktop_id, false);
+        self.remove_from(REMOVED_ASSOCIATIONS, mime_type, desktop_id);
+    }
+
+    /// Stops the entry from being offered for the type, even though its MimeType= lists it
+    pub fn remove_association(&mut self, mime_type: &str, desktop_id: &str) {
+        self.remove_from(DEFAULT_APPLICATIONS, mime_type, desktop_id);
+        self.remove_from(ADDED_ASSOCIATIONS, mime_type, desktop_id);
+        self.add_to(REMOVED_ASSOCIATIONS, mime_type, desktop_id, false);
+    }
+
+
+    /// Every type the entry is mentioned for in the group
+    pub fn mime_types_for(&self, group: &str, desktop_id: &str) -> Vec<String> {
+        self.keyfile.keys(group).into_iter()
+            .filter(|mime_type| self.get(group, mime_type).iter().any(|id| id == desktop_id))
+            .map(String::from)
+            .collect()
+    }
+
+    /// Points every mention of `old_id` at `new_id` (or drops it if there's no new one)
+    pub fn rename_entry(&mut self, old_id: &str, new_id: Option<&str>) {
+        for group in &[DEFAULT_APPLICATIONS, ADDED_ASSOCIATIONS, REMOVED_ASSOCIATIONS] {
+            for mime_type in self.mime_types_for(group, old_id) {
+                let ids: Vec<String> = self.get(group, &mime_type).into_iter()
+                    .filter_map(|id| if id == old_id { new_id.map(String::from) } else { Some(id) })
+                    .collect();
+                self.put(group, &mime_type, &ids);
+            }
+        }
+    }
+}
+
+
+/// For when an entry's file is renamed
+pub fn rename_entry(context: &Context, old_id: &str, new_id: &str) -> io::Result<()> {
+    update_references(context, old_id, Some(new_id))
+}
+
+/// Takes a deleted entry out of mimeapps.list
+pub fn forget_entry(context: &Context, desktop_id: &str) -> io::Result<()> {
+    update_references(context, desktop_id, None)
+}
+
+fn update_references(context: &Context, old_id: &str, new_id: Option<&str>) -> io::Result<()> {
+    if !context.mimeapps_list().exists() { return Ok(()) }
+
+    let mut list = MimeApps::open(context)?;
+    let before = list.keyfile.to_bytes();
+    list.rename_entry(old_id, new_id);
+    if list.keyfile.to_bytes() != before { list.save(context)?; }
+    Ok(())
+}
+
+
+/// Links are handed to whatever handles x-scheme-handler/<scheme>
+pub fn scheme_mime_type(scheme: &str) -> String {
+    format!("x-scheme-handler/{}", scheme.to_lowercase())
+}
+
+/// Letters, then letters, digits, +, - and . (RFC 3986)
+pub fn is_valid_scheme(scheme: &str) -> bool {
+    lazy_static! {
+        static ref SCHEME_REGEX: Regex = Regex::new(r"^[A-Za-z][A-Za-z0-9+.\-]*$").unwrap();
+    }
+    SCHEME_REGEX.is_match(scheme)
+}
+
+/// The part of the URL before the colon, lowercased
+pub fn url_scheme(url: &str) -> Option<String> {
+    let scheme = url.splitn(2, ':').next()?;
+    if url.contains(':') && is_valid_scheme(scheme) { Some(scheme.to_lowercase()) } else { None }
+}
+
+
+/// $XDG_CURRENT_DESKTOP as the lowercase names used for desktop-specific lists (gnome-mimeapps.list)
+pub fn current_desktops() -> Vec<String> {
+    env::var("XDG_CURRENT_DESKTOP").unwrap_or_default()
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+
+/// Every mimeapps.list that has a say, most important first, as the spec orders them:
+/// the user's config, the system's config, then the (deprecated) ones next to the desktop files.
+/// Desktop-specific lists come before the general one in each folder.
+fn list_files(context: &Context, desktops: &[String]) -> Vec<PathBuf> {
+    let mut folders = vec![context.config_home().to_path_buf()];
+    folders.extend(context.system_config_dirs().iter().cloned());
+    folders.extend(applications_dirs(context));
+
+    let mut result = Vec::new();
+    for folder in folders {
+        for desktop in desktops {
+            result.push(folder.join(format!("{}-mimeapps.list", desktop)));
+        }
+        result.push(folder.join("mimeapps.list"));
+    }
+    result
+}
+
+fn applications_dirs(context: &Context) -> Vec<PathBuf> {
+    let mut result = vec![context.data_home().join("applications")];
+    result.extend(context.system_data_dirs().iter().map(|dir| dir.join("applications")));
+    result
+}
+
+/// Where the installed desktop file with this ID is, if there is one
+pub fn installed_path(context: &Context, desktop_id: &str) -> Option<PathBuf> {
+    applications_dirs(context).into_iter()
+        .map(|dir| dir.join(desktop_id))
+        .find(|path| path.is_file())
+}
+
+
+/// The launcher that opens `mime_type`, the way the spec says to pick it: the first installed default,
+/// then the first added association that isn't removed, then any installed application that lists the type itself.
+pub fn find_handler(context: &Context, mime_type: &str, desktops: &[String]) -> Option<(String, PathBuf)> {
+    let lists: Vec<MimeApps> = list_files(context, desktops).iter()
+        .filter(|path| path.is_file())
+        .filter_map(|path| MimeApps::open_path(path).ok())
+        .collect();
+
+    for list in &lists {
+        for id in list.get(DEFAULT_APPLICATIONS, mime_type) {
+            if let Some(path) = installed_path(context, &id) { return Some((id, path)) }
+        }
+    }
+
+    // A removal counts for its own list and everything less important than it
+    let mut removed = Vec::new();
+    for list in &lists {
+        removed.extend(list.get(REMOVED_ASSOCIATIONS, mime_type));
+        for id in list.get(ADDED_ASSOCIATIONS, mime_type) {
+            if removed.contains(&id) { continue }
+            if let Some(path) = installed_path(context, &id) { return Some((id, path)) }
+        }
+    }
+
+    for dir in applications_dirs(context) {
+        let mut ids: Vec<String> = match fs::read_dir(&dir) {
+            Ok(files) => files.filter_map(|f| f.ok()).filter_map(|f| f.file_name().to_str().map(String::from)).collect(),
+            Err(_) => continue
+        };
+        ids.sort();
+
+        for id in ids.into_iter().filter(|id| id.ends_with(".desktop") && !removed.contains(id)) {
+            let path = dir.join(&id);
+            let declared = KeyFile::open(&path).ok()
+                .and_then(|k| k.get_string(DESKTOP_ENTRY_GROUP, "MimeType"))
+                .map(|types| split(&types).iter().any(|t| t == mime_type))
+                .unwrap_or(false);
+            if declared { return Some((id, path)) }
+        }
+    }
+
+    None
+}
+
+
+/// One line of shared-mime-info's globs2 file: weight:type:glob[:flags]
+struct Glob {
+    weight: u32,
+    mime_type: String,
+    pattern: String,
+    case_sensitive: bool,
+}
+
+fn read_globs(path: &Path) -> Vec<Glob> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return Vec::new()
+    };
+
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, ':');
+            let weight = fields.next()?.parse().ok()?;
+            let mime_type = fields.next()?.to_string();
+            let pattern = fields.next()?.to_string();
+            let case_sensitive = fields.next().map(|flags| flags.split(',').any(|f| f == "cs")).unwrap_or(false);
+            Some(Glob { weight: weight, mime_type: mime_type, pattern: pattern, case_sensitive: case_sensitive })
+        })
+        .collect()
+}
+
+
+/// The shared-mime-info databases, the user's first
+fn databases(context: &Context) -> Vec<PathBuf> {
+    let mut result = vec![context.data_home().join("mime")];
+    result.extend(context.system_data_dirs().iter().map(|dir| dir.join("mime")));
+    result
+}
+
+
+/// Every MIME type shared-mime-info knows about, sorted
+pub fn known_mime_types(context: &Context) -> Vec<String> {
+    let mut result: Vec<String> = databases(context).iter()
+        .filter_map(|database| fs::read_to_string(database.join("types")).ok())
+        .flat_map(|text| text.lines().map(String::from).collect::<Vec<_>>())
+        .filter(|line| !line.is_empty())
+        .collect();
+    result.sort();
+    result.dedup();
+    result
+}
+
+
+/// Guesses the MIME types of a file from its name using the shared-mime-info database, best match first.
+/// A bare extension works too, so ".md" gives text/markdown.
+pub fn mime_types_for_filename(context: &Context, filename: &str) -> Vec<String> {
+    let lowercase = filename.to_lowercase();
+    let mut matches: Vec<(u32, usize, String)> = Vec::new();
+
+    for database in databases(context) {
+        for glob in read_globs(&database.join("globs2")) {
+            let matched = if glob.case_sensitive { glob_matches(&glob.pattern, filename) }
+                          else                   { glob_matches(&glob.pattern.to_lowercase(), &lowercase) };
+            if matched && !matches.iter().any(|(_, _, t)| *t == glob.mime_type) {
+                // The spec prefers heavier globs, then longer (more specific) ones
+                matches.push((glob.weight, glob.pattern.len(), glob.mime_type));
+            }
+        }
+    }
+
+    matches.sort_by_key(|&(weight, length, _)| Reverse((weight, length)));
+    matches.into_iter().map(|(_, _, mime_type)| mime_type).collect()
+}
+
+
+/// What the user typed for a type: either a MIME type, or a filename/extension to look one up for
+pub fn resolve(context: &Context, input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if input.contains('/') {
+        if is_valid_mime_type(input) { Ok(input.to_string()) }
+        else                         { Err(format!("\"{}\" isn't a valid MIME type", input)) }
+    }
+    else {
+        mime_types_for_filename(context, input).into_iter().next()
+            .ok_or_else(|| format!("Couldn't find a MIME type for \"{}\" in the shared-mime-info database", input))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+
+    #[test]
+    fn mimeapps_list_groups_are_kept_consistent() {
+        let root = TestDir::new("mime");
+        let system = root.join("share");
+        fs::create_dir_all(system.join("mime")).unwrap();
+        fs::write(system.join("mime").join("globs2"), "# comment\n50:text/markdown:*.md\n50:text/plain:*.txt\n60:text/x-readme:README*\n").unwrap();
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::write(root.join("config").join("mimeapps.list"), "[Default Applications]\ntext/plain=vim.desktop;\n\n[Added Associations]\ntext/html=firefox.desktop;\n\n\
+                                                            [Default Applications]\ntext/plain=gedit.desktop;\n").unwrap();
+
+        let mut context = root.context();
+        context.set_system_data_dirs(vec![system]);
+
+        assert_eq!(mime_types_for_filename(&context, ".MD"), vec!["text/markdown"]);
+        assert_eq!(mime_types_for_filename(&context, "README.md"), vec!["text/x-readme", "text/markdown"]);
+        assert_eq!(resolve(&context, "text/plain"), Ok(String::from("text/plain")));
+        assert!(resolve(&context, "text/plain maybe").is_err());
+
+        // Repeated groups are merged and the last of a repeated key wins, as GLib reads them
+        let mut list = MimeApps::open(&context).unwrap();
+        assert_eq!(list.default_for("text/plain"), Some(String::from("gedit.desktop")));
+        assert_eq!(list.get(ADDED_ASSOCIATIONS, "text/html"), vec!["firefox.desktop"]);
+        list.set_default("text/plain", "mkdesktop-Notes.desktop");
+        list.remove_association("text/markdown", "mkdesktop-Notes.desktop");
+        assert_eq!(list.get(DEFAULT_APPLICATIONS, "text/plain"), vec!["mkdesktop-Notes.desktop", "gedit.desktop"]);
+        assert_eq!(list.get(ADDED_ASSOCIATIONS, "text/plain"), vec!["mkdesktop-Notes.desktop"]);
+        assert_eq!(list.get(REMOVED_ASSOCIATIONS, "text/markdown"), vec!["mkdesktop-Notes.desktop"]);
+
+        list.set_default("text/markdown", "mkdesktop-Notes.desktop");
+        assert!(list.get(REMOVED_ASSOCIATIONS, "text/markdown").is_empty());
+        list.save(&context).unwrap();
+
+        rename_entry(&context, "mkdesktop-Notes.desktop", "mkdesktop-Jotter.desktop").unwrap();
+        assert_eq!(MimeApps::open(&context).unwrap().default_for("text/markdown"), Some(String::from("mkdesktop-Jotter.desktop")));
+        forget_entry(&context, "mkdesktop-Jotter.desktop").unwrap();
+        let list = MimeApps::open(&context).unwrap();
+        assert_eq!(list.default_for("text/plain"), Some(String::from("gedit.desktop")));
+        assert!(list.default_for("text/markdown").is_none());
+    }
+
+    #[test]
+    fn handlers_are_found_in_spec_order() {
+        let root = TestDir::new("mime-handlers");
+        let (user, system) = (root.join("data").join("applications"), root.join("share").join("applications"));
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        fs::create_dir_all(root.join("config")).unwrap();
+        for (dir, id) in &[(&system, "browser.desktop"), (&system, "other.desktop"), (&user, "ours.desktop")] {
+            fs::write(dir.join(id), "[Desktop Entry]\nName=App\nExec=app %u\nMimeType=x-scheme-handler/ourapp;\n").unwrap();
+        }
+
+        let mut context = root.context();
+        context.set_system_data_dirs(vec![root.join("share")]);
+        let handler = |desktops: &[String]| find_handler(&context, &scheme_mime_type("OurApp"), desktops).map(|(id, _)| id);
+
+        // Nothing in any list: the first one that declares the type
+        assert_eq!(handler(&[]), Some(String::from("ours.desktop")));
+
+        // Removing it falls through to the next, a default wins over everything, and desktop-specific lists come first
+        fs::write(system.join("mimeapps.list"), "[Removed Associations]\nx-scheme-handler/ourapp=ours.desktop;\n").unwrap();
+        assert_eq!(handler(&[]), Some(String::from("browser.desktop")));
+        fs::write(root.join("config").join("mimeapps.list"), "[Default Applications]\nx-scheme-handler/ourapp=missing.desktop;other.desktop;\n").unwrap();
+        assert_eq!(handler(&[]), Some(String::from("other.desktop")));
+        fs::write(root.join("config").join("kde-mimeapps.list"), "[Default Applications]\nx-scheme-handler/ourapp=ours.desktop\n").unwrap();
+        assert_eq!(handler(&[String::from("kde")]), Some(String::from("ours.desktop")));
+
+        assert_eq!(url_scheme("OurApp://open?id=1"), Some(String::from("ourapp")));
+        assert_eq!(url_scheme("/tmp/file"), None);
+    }
+}
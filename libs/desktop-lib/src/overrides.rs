@@ -0,0 +1,228 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::context::Context;
+use super::exec::{self, Exec};
+use super::icons;
+use super::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+use super::packages::{self, PackageKind};
+
+
+/// Where an override says which file it was copied from
+pub const SOURCE_KEY: &str = "X-Mkdesktop-Override-Source";
+/// ...and a hash of what that file held at the time, to notice when an update changes it
+pub const HASH_KEY: &str = "X-Mkdesktop-Override-Hash";
+
+
+/// What became of the file an override was copied from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Upstream {
+    Unchanged,
+    /// Usually a package update -- the override still has the old contents
+    Changed,
+    /// The package is gone, but the override still shows up in menus
+    Removed,
+}
+
+
+/// Menus look in the user's applications folder before the system's, so a file there
+/// with the same desktop file ID hides the original
+pub fn override_path(context: &Context, desktop_id: &str) -> PathBuf {
+    context.applications_dir().join(desktop_id)
+}
+
+
+/// The installed desktop file with this ID in one of the system's applications folders ($XDG_DATA_DIRS),
+/// which is what an override shadows
+pub fn system_file(context: &Context, desktop_id: &str) -> Option<PathBuf> {
+    context.system_data_dirs().iter()
+        .map(|dir| dir.join("applications").join(desktop_id))
+        .find(|path| path.is_file())
+}
+
+
+/// Copies `source` into the user's applications folder under `desktop_id` and lets `change` adjust it.
+/// An override made before is replaced, so changes always start from the original file.
+/// The file goes there directly: xdg-desktop-menu wants a vendor prefix that IDs like org.gnome.Maps don't have.
+pub fn write<F: FnOnce(&mut KeyFile)>(context: &Context, desktop_id: &str, source: &Path, change: F) -> io::Result<PathBuf> {
+    let path = override_path(context, desktop_id);
+    if path.exists() && self::source(context, desktop_id).is_none() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} is already there and isn't an override", path)));
+    }
+
+    let contents = fs::read(source)?;
+    let mut keyfile = KeyFile::open(source)?;
+    change(&mut keyfile);
+    keyfile.set(DESKTOP_ENTRY_GROUP, SOURCE_KEY, &source.to_string_lossy());
+    keyfile.set(DESKTOP_ENTRY_GROUP, HASH_KEY, &content_hash(&contents));
+
+    fs::create_dir_all(context.applications_dir())?;
+    fs::write(&path, keyfile.to_bytes())?;
+    Ok(path)
+}
+
+
+/// The file an override was copied from, or None if there's no override with that ID
+pub fn source(context: &Context, desktop_id: &str) -> Option<PathBuf> {
+    KeyFile::open(&override_path(context, desktop_id)).ok()?
+        .get_string(DESKTOP_ENTRY_GROUP, SOURCE_KEY)
+        .map(PathBuf::from)
+}
+
+
+/// Whether the file an override was copied from is still what it was, or None if there's no override with that ID.
+/// Overrides without a hash can't tell, so they count as unchanged.
+pub fn upstream(context: &Context, desktop_id: &str) -> Option<Upstream> {
+    let keyfile = KeyFile::open(&override_path(context, desktop_id)).ok()?;
+    let source = keyfile.get_string(DESKTOP_ENTRY_GROUP, SOURCE_KEY)?;
+
+    let contents = match fs::read(&source) {
+        Ok(contents) => contents,
+        Err(_) => return Some(Upstream::Removed)
+    };
+    match keyfile.get_string(DESKTOP_ENTRY_GROUP, HASH_KEY) {
+        Some(ref hash) if *hash != content_hash(&contents) => Some(Upstream::Changed),
+        _ => Some(Upstream::Unchanged)
+    }
+}
+
+
+/// Removes an override, bringing the original back. Files we didn't make are left alone.
+/// Returns whether there was one.
+pub fn remove(context: &Context, desktop_id: &str) -> io::Result<bool> {
+    if source(context, desktop_id).is_none() { return Ok(false) }
+    fs::remove_file(override_path(context, desktop_id))?;
+    Ok(true)
+}
+
+
+/// Desktop file IDs of every override, with the file each was copied from, sorted by ID
+pub fn list(context: &Context) -> Vec<(String, PathBuf)> {
+    let mut ids: Vec<String> = match fs::read_dir(context.applications_dir()) {
+        Ok(files) => files.filter_map(|f| f.ok())
+            .filter_map(|f| f.file_name().to_str().map(String::from))
+            .filter(|name| name.ends_with(".desktop"))
+            .collect(),
+        Err(_) => Vec::new()
+    };
+    ids.sort();
+
+    ids.into_iter()
+        .filter_map(|id| source(context, &id).map(|source| (id, source)))
+        .collect()
+}
+
+
+/// Adds arguments and environment variables to the main command and to those of actions that run the same program
+/// (or the same Flatpak), leaving actions that start something else alone.
+/// Arguments go before field codes like %U, so they come before whatever the program is asked to open.
+/// `flatpak run` takes options before the app ID and passes anything after it on to the app, and variables have
+/// to go through --env= there, since the sandbox doesn't see the environment it's started with.
+pub fn customize_commands(keyfile: &mut KeyFile, args: &[String], env: &[(String, String)]) {
+    let main = match keyfile.get_string(DESKTOP_ENTRY_GROUP, "Exec") {
+        Some(command) => runs(&Exec::parse(&command)),
+        None => return
+    };
+    let groups: Vec<String> = keyfile.groups().into_iter()
+        .filter(|group| *group == DESKTOP_ENTRY_GROUP || group.starts_with("Desktop Action "))
+        .map(String::from)
+        .collect();
+
+    for group in groups {
+        let mut exec = match keyfile.get_string(&group, "Exec") {
+            Some(command) => Exec::parse(&command),
+            None => continue
+        };
+        if runs(&exec) != main { continue }
+
+        let mut new_args = exec.args().to_vec();
+        match packages::package_position(&exec) {
+            Some((PackageKind::Flatpak, position)) => {
+                for (i, arg) in args.iter().enumerate() {
+                    new_args.insert(position + 1 + i, arg.clone());
+                }
+                for (key, value) in env.iter().rev() {
+                    new_args.insert(position, format!("--env={}={}", key, value));
+                }
+            }
+            _ => {
+                let position = new_args.iter().position(|arg| exec::is_field_code(arg)).unwrap_or(new_args.len());
+                for (i, arg) in args.iter().enumerate() {
+                    new_args.insert(position + i, arg.clone());
+                }
+                for (key, value) in env {
+                    exec.set_env_var(key, value);
+                }
+            }
+        }
+        exec.set_args(new_args);
+        keyfile.set(&group, "Exec", &exec.to_string());
+    }
+}
+
+
+/// The package a command wraps, or else its program
+fn runs(exec: &Exec) -> String {
+    match packages::wrapped_by(exec) {
+        Some((kind, id)) => format!("{} {}", kind.describe(), id),
+        None => exec.program().to_string()
+    }
+}
+
+
+fn content_hash(contents: &[u8]) -> String {
+    format!("{:016x}", icons::content_hash(contents))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+
+    #[test]
+    fn flatpak_commands_get_options_before_the_app_and_arguments_after_it() {
+        let mut keyfile = KeyFile::parse("[Desktop Entry]\nExec=/usr/bin/flatpak run --branch=stable --command foo org.app.App %U\n").unwrap();
+        customize_commands(&mut keyfile, &[String::from("--verbose")], &[(String::from("LANG"), String::from("C"))]);
+        assert_eq!(keyfile.get_string(DESKTOP_ENTRY_GROUP, "Exec").unwrap(),
+                   "/usr/bin/flatpak run --branch=stable --command foo --env=LANG=C org.app.App --verbose %U");
+        assert_eq!(content_hash(b"[Desktop Entry]\n"), "d946031c915f7b61");
+    }
+
+    #[test]
+    fn overrides_shadow_system_launchers_until_they_change() {
+        let root = TestDir::new("overrides");
+        let system = root.join("share").join("applications");
+        fs::create_dir_all(&system).unwrap();
+        fs::write(system.join("google-chrome.desktop"), "[Desktop Entry]\nName=Google Chrome\nExec=/usr/bin/google-chrome-stable %U\n\n\
+                                                         [Desktop Action new-window]\nExec=/usr/bin/google-chrome-stable\n\n\
+                                                         [Desktop Action settings]\nExec=gnome-control-center default-apps\n").unwrap();
+
+        let mut context = root.context();
+        context.set_system_data_dirs(vec![root.join("share")]);
+
+        let source = system_file(&context, "google-chrome.desktop").unwrap();
+        let path = write(&context, "google-chrome.desktop", &source, |keyfile| {
+            customize_commands(keyfile, &[String::from("--force-device-scale-factor=2")], &[(String::from("GTK_THEME"), String::from("Adwaita"))])
+        }).unwrap();
+        let keyfile = KeyFile::open(&path).unwrap();
+        assert_eq!(keyfile.get_string(DESKTOP_ENTRY_GROUP, "Exec").unwrap(),
+                   "env GTK_THEME=Adwaita /usr/bin/google-chrome-stable --force-device-scale-factor=2 %U");
+        assert_eq!(keyfile.get_string("Desktop Action new-window", "Exec").unwrap(),
+                   "env GTK_THEME=Adwaita /usr/bin/google-chrome-stable --force-device-scale-factor=2");
+        assert_eq!(keyfile.get_string("Desktop Action settings", "Exec").unwrap(), "gnome-control-center default-apps");
+        assert_eq!(upstream(&context, "google-chrome.desktop"), Some(Upstream::Unchanged));
+
+        fs::write(&source, "[Desktop Entry]\nName=Google Chrome\nExec=/usr/bin/google-chrome-stable --new %U\n").unwrap();
+        assert_eq!(upstream(&context, "google-chrome.desktop"), Some(Upstream::Changed));
+        fs::remove_file(&source).unwrap();
+        assert_eq!(upstream(&context, "google-chrome.desktop"), Some(Upstream::Removed));
+        assert_eq!(upstream(&context, "firefox.desktop"), None);
+
+        // A launcher the user made themselves isn't ours to replace
+        fs::write(override_path(&context, "mine.desktop"), "[Desktop Entry]\nName=Mine\n").unwrap();
+        assert!(write(&context, "mine.desktop", &path, |_| {}).is_err());
+        assert!(!remove(&context, "mine.desktop").unwrap());
+    }
+}
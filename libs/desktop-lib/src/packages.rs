@@ -0,0 +1,415 @@
+use std::io;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::context::Context;
+use super::desktop::{self, DesktopEntry};
+use super::exec::Exec;
+use super::keyfile::KeyFile;
+use super::overrides;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PackageKind {
+    Flatpak,
+    Snap,
+}
+
+impl PackageKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            PackageKind::Flatpak => "Flatpak",
+            PackageKind::Snap    => "Snap",
+        }
+    }
+}
+
+
+/// An installed app that runs in its own sandbox, through `flatpak run` or `snap run`
+#[derive(Clone, Debug)]
+pub enum Package {
+    Flatpak(FlatpakApp),
+    Snap(Snap),
+}
+
+impl Package {
+    /// What a target given to `new` stands for, if it isn't a file: an installed Flatpak's app ID,
+    /// or an installed snap's name (only when $PATH doesn't have something else by that name)
+    pub fn find(context: &Context, target: &str) -> Option<Package> {
+        if target.contains('/') || Path::new(target).exists() { return None }
+
+        if is_app_id(target) {
+            if let Some(app) = FlatpakApp::find(context, target) { return Some(Package::Flatpak(app)) }
+        }
+        match desktop::find_program(target) {
+            Some(ref program) if !is_snap_program(program) => None,
+            _ => Snap::find(target).map(Package::Snap)
+        }
+    }
+
+    /// The installed package an entry's command runs
+    pub fn find_installed(context: &Context, kind: PackageKind, id: &str) -> Option<Package> {
+        match kind {
+            PackageKind::Flatpak => FlatpakApp::find(context, id).map(Package::Flatpak),
+            PackageKind::Snap    => Snap::find(id).map(Package::Snap),
+        }
+    }
+
+
+    pub fn kind(&self) -> PackageKind {
+        match self {
+            Package::Flatpak(_) => PackageKind::Flatpak,
+            Package::Snap(_)    => PackageKind::Snap,
+        }
+    }
+
+    /// The app ID or snap name (with .app when it isn't the snap's main app)
+    pub fn id(&self) -> &str {
+        match self {
+            Package::Flatpak(app) => &app.id,
+            Package::Snap(snap)   => &snap.command,
+        }
+    }
+
+    pub fn exec(&self) -> Exec {
+        let tool = match self.kind() { PackageKind::Flatpak => "flatpak", PackageKind::Snap => "snap" };
+        Exec::new(tool, vec![String::from("run"), self.id().to_string()])
+    }
+
+    /// The desktop file the package exports for itself
+    pub fn desktop_file(&self) -> Option<PathBuf> {
+        match self {
+            Package::Flatpak(app) => app.desktop_file(),
+            Package::Snap(snap)   => snap.desktop_file(),
+        }
+    }
+
+    /// The exported desktop file as an entry, for its name, icon and categories
+    pub fn exported_entry(&self) -> Option<DesktopEntry> {
+        KeyFile::open(&self.desktop_file()?).ok().map(|keyfile| DesktopEntry::from_keyfile(&keyfile))
+    }
+
+    /// One line about what's installed, e.g. "Flatpak org.gnome.Maps (stable, system installation, runtime org.gnome.Platform/x86_64/45)"
+    pub fn describe(&self) -> String {
+        match self {
+            Package::Flatpak(app) => format!(
+                "Flatpak {} ({}, {} installation, runtime {})",
+                app.id, app.branch, if app.user { "user" } else { "system" }, app.runtime
+            ),
+            Package::Snap(snap) if snap.version.is_empty() => format!("Snap {}", snap.command),
+            Package::Snap(snap) => format!("Snap {} (version {})", snap.command, snap.version),
+        }
+    }
+}
+
+
+/// Options of `flatpak run` and `snap run` that take the next argument as their value (unless given as --option=value),
+/// so that argument isn't the app
+const RUN_OPTIONS_WITH_VALUES: &[&str] = &[
+    "--arch", "--branch", "--command", "--commit", "--cwd", "--runtime", "--runtime-version", "--runtime-commit",
+    "--env", "--unset-env", "--env-fd", "--filesystem", "--nofilesystem", "--socket", "--nosocket", "--share", "--unshare",
+    "--device", "--nodevice", "--allow", "--disallow", "--talk-name", "--no-talk-name", "--own-name",
+    "--system-talk-name", "--system-no-talk-name", "--system-own-name", "--add-policy", "--remove-policy",
+    "--persist", "--app-path", "--usr-path", "--instance-id-fd", "--parent-pid", "--a11y-own-name",
+];
+
+
+/// Which package a command runs, going by `flatpak run [options] <id>`, `snap run <name>` and /snap/bin/<name>
+pub fn wrapped_by(exec: &Exec) -> Option<(PackageKind, String)> {
+    if is_snap_program(Path::new(exec.program())) {
+        let program = Path::new(exec.program()).file_name()?.to_str()?;
+        return Some((PackageKind::Snap, program.to_string()));
+    }
+    let (kind, position) = package_position(exec)?;
+    Some((kind, exec.args()[position].clone()))
+}
+
+
+/// Where the app ID or snap name is in a `flatpak run` or `snap run` command's arguments
+pub fn package_position(exec: &Exec) -> Option<(PackageKind, usize)> {
+    let kind = match Path::new(exec.program()).file_name()?.to_str()? {
+        "flatpak" => PackageKind::Flatpak,
+        "snap"    => PackageKind::Snap,
+        _         => return None
+    };
+    let args = exec.args();
+    if args.first().map(String::as_str) != Some("run") { return None }
+
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "--" { return if i + 1 < args.len() { Some((kind, i + 1)) } else { None } }
+        if !arg.starts_with('-') { return Some((kind, i)) }
+        if RUN_OPTIONS_WITH_VALUES.contains(&arg.as_str()) { i += 1 }
+        i += 1;
+    }
+    None
+}
+
+
+/////////////////////////////////////////////////////////
+//
+//              FLATPAK
+//
+/////////////////////////////////////////////////////////
+
+/// A folder flatpak installs apps into
+#[derive(Clone, Debug, PartialEq)]
+pub struct Installation {
+    pub path: PathBuf,
+    pub user: bool,
+}
+
+
+/// The user's installation, then the system's. $FLATPAK_USER_DIR and $FLATPAK_SYSTEM_DIR move them, like they do for flatpak.
+pub fn flatpak_installations(context: &Context) -> Vec<Installation> {
+    let dir = |var: &str, default: PathBuf| match env::var_os(var) {
+        Some(ref value) if !value.is_empty() => PathBuf::from(value),
+        _ => default
+    };
+
+    vec![
+        Installation { path: dir("FLATPAK_USER_DIR", context.data_home().join("flatpak")), user: true },
+        Installation { path: dir("FLATPAK_SYSTEM_DIR", PathBuf::from("/var/lib/flatpak")), user: false },
+    ]
+}
+
+
+/// Flatpak app IDs are reverse DNS with at least three parts, e.g. org.gnome.Maps
+pub fn is_app_id(id: &str) -> bool {
+    let parts: Vec<&str> = id.split('.').collect();
+    parts.len() >= 3 && parts.iter().all(|part| {
+        part.chars().next().map(|c| !c.is_ascii_digit()).unwrap_or(false)
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    })
+}
+
+
+#[derive(Clone, Debug)]
+pub struct FlatpakApp {
+    pub id: String,
+    /// The installation it's in, e.g. /var/lib/flatpak
+    pub installation: PathBuf,
+    pub user: bool,
+    /// The branch that's current, e.g. stable
+    pub branch: String,
+    pub runtime: String,
+    /// The program `flatpak run` starts inside the sandbox
+    pub command: String,
+}
+
+impl FlatpakApp {
+    /// Looks in the user's installation first, which is also what `flatpak run` prefers
+    pub fn find(context: &Context, id: &str) -> Option<FlatpakApp> {
+        flatpak_installations(context).iter().filter_map(|i| FlatpakApp::read(i, id)).next()
+    }
+
+    /// Every installed app, user installation first, each sorted by ID
+    pub fn installed(context: &Context) -> Vec<FlatpakApp> {
+        let mut result = Vec::new();
+        for installation in flatpak_installations(context) {
+            let mut ids: Vec<String> = match fs::read_dir(installation.path.join("app")) {
+                Ok(files) => files.filter_map(|f| f.ok()).filter_map(|f| f.file_name().to_str().map(String::from)).collect(),
+                Err(_) => continue
+            };
+            ids.sort();
+            result.extend(ids.iter().filter_map(|id| FlatpakApp::read(&installation, id)));
+        }
+        result
+    }
+
+    /// app/<id>/current is a symlink to <arch>/<branch>, and active/metadata has the [Application] group.
+    /// The metadata's other groups have D-Bus names as keys, which desktop files wouldn't allow, hence parse_lenient.
+    fn read(installation: &Installation, id: &str) -> Option<FlatpakApp> {
+        let current = installation.path.join("app").join(id).join("current");
+        let text = fs::read_to_string(current.join("active").join("metadata")).ok()?;
+        let metadata = KeyFile::parse_lenient(&text);
+        let branch = fs::read_link(&current).ok()
+            .and_then(|target| target.file_name().map(|b| b.to_string_lossy().to_string()))
+            .unwrap_or_default();
+
+        Some(FlatpakApp {
+            id: id.to_string(),
+            installation: installation.path.clone(),
+            user: installation.user,
+            branch: branch,
+            runtime: metadata.get_string("Application", "runtime").unwrap_or_default(),
+            command: metadata.get_string("Application", "command").unwrap_or_default(),
+        })
+    }
+
+    /// Exported desktop files are named after the app ID
+    pub fn desktop_file(&self) -> Option<PathBuf> {
+        let path = self.installation.join("exports").join("share").join("applications").join(format!("{}.desktop", self.id));
+        if path.is_file() { Some(path) } else { None }
+    }
+
+
+    /// Copies the app's exported desktop file over as an override with the same ID, adding arguments and
+    /// environment variables to the commands in it (the main one and its actions')
+    pub fn fork(&self, context: &Context, args: &[String], env: &[(String, String)]) -> io::Result<PathBuf> {
+        let source = match self.desktop_file() {
+            Some(path) => path,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't export a desktop file", self.id)))
+        };
+        overrides::write(context, &format!("{}.desktop", self.id), &source, |keyfile| overrides::customize_commands(keyfile, args, env))
+    }
+}
+
+
+/////////////////////////////////////////////////////////
+//
+//              SNAP
+//
+/////////////////////////////////////////////////////////
+
+/// Where snaps are mounted: /snap, or /var/lib/snapd/snap on distributions that don't allow a top-level /snap
+pub const SNAP_MOUNT_DIRS: [&str; 2] = ["/snap", "/var/lib/snapd/snap"];
+/// Where snapd puts the desktop files snaps come with, as <snap>_<app>.desktop
+pub const SNAP_DESKTOP_DIR: &str = "/var/lib/snapd/desktop/applications";
+
+
+#[derive(Clone, Debug)]
+pub struct Snap {
+    pub name: String,
+    /// What `snap run` gets: the snap's name for its main app, <snap>.<app> for the others
+    pub command: String,
+    pub version: String,
+    pub summary: String,
+}
+
+impl Snap {
+    /// `name` is a snap's name or <snap>.<app>
+    pub fn find(name: &str) -> Option<Snap> {
+        SNAP_MOUNT_DIRS.iter().filter_map(|dir| Snap::read(Path::new(dir), name)).next()
+    }
+
+    /// Goes by <mount dir>/<snap>/current/meta/snap.yaml. The app has to be one the snap declares.
+    pub fn read(mount_dir: &Path, name: &str) -> Option<Snap> {
+        let mut parts = name.splitn(2, '.');
+        let snap_name = parts.next()?;
+        let app = parts.next().unwrap_or(snap_name);
+
+        let yaml = fs::read_to_string(mount_dir.join(snap_name).join("current").join("meta").join("snap.yaml")).ok()?;
+        if !snap_apps(&yaml).iter().any(|a| a == app) { return None }
+
+        Some(Snap {
+            name: snap_name.to_string(),
+            command: name.to_string(),
+            version: yaml_value(&yaml, "version").unwrap_or_default(),
+            summary: yaml_value(&yaml, "summary").unwrap_or_default(),
+        })
+    }
+
+    pub fn desktop_file(&self) -> Option<PathBuf> {
+        let app = self.command.split_once('.').map(|(_, app)| app).unwrap_or(&self.name);
+        let path = Path::new(SNAP_DESKTOP_DIR).join(format!("{}_{}.desktop", self.name, app));
+        if path.is_file() { Some(path) } else { None }
+    }
+}
+
+
+/// snapd links every app into /snap/bin
+fn is_snap_program(program: &Path) -> bool {
+    program.parent() == Some(Path::new("/snap/bin"))
+}
+
+
+/// A top-level `key: value` from snap.yaml, unquoted. Just enough YAML for the fields snapd writes on one line.
+fn yaml_value(yaml: &str, key: &str) -> Option<String> {
+    yaml.lines()
+        .find(|line| line.starts_with(key) && line[key.len()..].starts_with(':'))
+        .map(|line| line[key.len() + 1..].trim().trim_matches(|c| c == '\'' || c == '"').to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// The names under the top-level `apps:` mapping
+fn snap_apps(yaml: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut indent: Option<usize> = None;
+
+    for line in yaml.lines().skip_while(|line| !line.starts_with("apps:")).skip(1) {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') { continue }
+        let depth = line.len() - line.trim_start().len();
+        if depth == 0 { break }
+
+        let app_indent = *indent.get_or_insert(depth);
+        if depth == app_indent {
+            result.push(line.trim().trim_end_matches(':').to_string());
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+    use super::super::keyfile::DESKTOP_ENTRY_GROUP;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn commands_are_recognized() {
+        assert_eq!(wrapped_by(&Exec::parse("/usr/bin/flatpak run --branch=stable --command=maps org.gnome.Maps @@u %U @@")),
+                   Some((PackageKind::Flatpak, String::from("org.gnome.Maps"))));
+        assert_eq!(wrapped_by(&Exec::parse("snap run spotify")), Some((PackageKind::Snap, String::from("spotify"))));
+        assert_eq!(wrapped_by(&Exec::parse("/snap/bin/code --new-window")), Some((PackageKind::Snap, String::from("code"))));
+        assert_eq!(wrapped_by(&Exec::parse("flatpak update")), None);
+
+        assert!(is_app_id("org.gnome.Maps") && is_app_id("com.valvesoftware.Steam"));
+        assert!(!is_app_id("gnome.Maps") && !is_app_id("org.2048.Game") && !is_app_id("org..Maps"));
+    }
+
+    #[test]
+    fn flatpaks_are_read_and_forked() {
+        // The user installation has to be data_home/flatpak for this
+        if env::var_os("FLATPAK_USER_DIR").is_some() { return }
+
+        let root = TestDir::new("packages");
+        let installation = root.join("data").join("flatpak");
+        let app = installation.join("app").join("org.gnome.Maps");
+        fs::create_dir_all(app.join("x86_64").join("stable").join("active")).unwrap();
+        symlink("x86_64/stable", app.join("current")).unwrap();
+        fs::write(app.join("current").join("active").join("metadata"), "[Application]\nname=org.gnome.Maps\nruntime=org.gnome.Platform/x86_64/45\ncommand=gnome-maps\n\n\
+                                                                    [Session Bus Policy]\norg.freedesktop.Notifications=talk\n").unwrap();
+
+        let exports = installation.join("exports").join("share").join("applications");
+        fs::create_dir_all(&exports).unwrap();
+        fs::write(exports.join("org.gnome.Maps.desktop"), "[Desktop Entry]\nName=Maps\nExec=/usr/bin/flatpak run --branch=stable --command=gnome-maps org.gnome.Maps %U\n\n\
+                                                          [Desktop Action new-window]\nExec=/usr/bin/flatpak run org.gnome.Maps --new-window\n").unwrap();
+        let context = root.context();
+
+        let package = Package::Flatpak(FlatpakApp::find(&context, "org.gnome.Maps").unwrap());
+        assert_eq!(package.describe(), "Flatpak org.gnome.Maps (stable, user installation, runtime org.gnome.Platform/x86_64/45)");
+        assert_eq!(package.exec().to_string(), "flatpak run org.gnome.Maps");
+        assert_eq!(package.exported_entry().unwrap().get_name(), "Maps");
+
+        let app = FlatpakApp::find(&context, "org.gnome.Maps").unwrap();
+        let fork = app.fork(&context, &[String::from("--verbose")], &[(String::from("GDK_SCALE"), String::from("2"))]).unwrap();
+        let forked = KeyFile::open(&fork).unwrap();
+        assert_eq!(forked.get_string(DESKTOP_ENTRY_GROUP, "Exec").unwrap(),
+                   "/usr/bin/flatpak run --branch=stable --command=gnome-maps --env=GDK_SCALE=2 org.gnome.Maps --verbose %U");
+        assert_eq!(forked.get_string("Desktop Action new-window", "Exec").unwrap(),
+                   "/usr/bin/flatpak run --env=GDK_SCALE=2 org.gnome.Maps --verbose --new-window");
+        assert_eq!(overrides::list(&context), vec![(String::from("org.gnome.Maps.desktop"), exports.join("org.gnome.Maps.desktop"))]);
+
+        assert!(overrides::remove(&context, "org.gnome.Maps.desktop").unwrap());
+        assert!(!fork.exists());
+    }
+
+    #[test]
+    fn snaps_are_read_from_their_metadata() {
+        let root = TestDir::new("snaps");
+        let meta = root.join("code").join("current").join("meta");
+        fs::create_dir_all(&meta).unwrap();
+        fs::write(meta.join("snap.yaml"), "name: code\nversion: '1.85.1'\nsummary: Code editing. Redefined.\napps:\n  code:\n    command: electron-launch $SNAP/usr/share/code/bin/code\n  url-handler:\n    command: electron-launch\nbase: core20\n").unwrap();
+
+        let snap = Snap::read(&root, "code").unwrap();
+        assert_eq!((snap.version.as_str(), snap.summary.as_str()), ("1.85.1", "Code editing. Redefined."));
+        assert_eq!(Package::Snap(Snap::read(&root, "code.url-handler").unwrap()).exec().to_string(), "snap run code.url-handler");
+        assert!(Snap::read(&root, "code.command").is_none());
+        assert!(Snap::read(&root, "spotify").is_none());
+    }
+}
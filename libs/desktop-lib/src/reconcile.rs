@@ -0,0 +1,96 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::context::Context;
+
+
+/// A desktop file that exists on only one side of registration.
+/// write_to_apps_dir keeps the source in the data dir and registering installs a copy into the applications dir,
+/// so a failure halfway through saving or deleting leaves one without the other.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Orphan {
+    /// In the data dir but never made it into the menu
+    Unregistered(PathBuf),
+    /// A mkdesktop- file in the menu whose source is gone
+    Unbacked(PathBuf),
+}
+
+impl Orphan {
+    pub fn path(&self) -> &Path {
+        match self {
+            Orphan::Unregistered(path) => path,
+            Orphan::Unbacked(path)     => path,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Orphan::Unregistered(path) => format!("{} isn't registered with the menu", path.display()),
+            Orphan::Unbacked(path)     => format!("{} is in the menu but mkdesktop has no entry for it", path.display()),
+        }
+    }
+}
+
+
+pub fn find_orphans(context: &Context) -> io::Result<Vec<Orphan>> {
+    let sources = desktop_files_in(&context.data_dir())?;
+    let mut registered = desktop_files_in(&context.applications_dir()).unwrap_or_default();
+    registered.extend(desktop_files_in(&context.desktop_directories_dir()).unwrap_or_default());
+
+    let mut result = Vec::new();
+
+    for source in &sources {
+        if !registered.iter().any(|r| r.file_name() == source.file_name()) {
+            result.push(Orphan::Unregistered(source.clone()));
+        }
+    }
+
+    for copy in &registered {
+        let is_ours = copy.file_name().and_then(|n| n.to_str()).map(|n| n.starts_with("mkdesktop-")).unwrap_or(false);
+        if is_ours && !sources.iter().any(|s| s.file_name() == copy.file_name()) {
+            result.push(Orphan::Unbacked(copy.clone()));
+        }
+    }
+
+    Ok(result)
+}
+
+
+/// Registers unregistered entries. Unbacked menu entries are removed from the menu,
+/// or copied back into the data dir when `restore` is set.
+pub fn repair(context: &Context, orphan: &Orphan, restore: bool) -> io::Result<()> {
+    match orphan {
+        Orphan::Unregistered(source) => context.register(source),
+
+        Orphan::Unbacked(copy) if restore => {
+            let filename = copy.file_name().expect("Registered desktop file has no filename");
+            fs::copy(copy, context.data_dir().join(filename))?;
+            Ok(())
+        }
+
+        Orphan::Unbacked(copy) => {
+            let filename = copy.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            context.unregister(filename)?;
+
+            // In case the registrar left it behind
+            if copy.exists() { fs::remove_file(copy)?; }
+            Ok(())
+        }
+    }
+}
+
+
+fn desktop_files_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut result: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|f| f.ok())
+        .map(|f| f.path())
+        .filter(|path| path.is_file())
+        .filter(|path| match path.extension().and_then(|e| e.to_str()) {
+            Some("desktop") | Some("directory") => true,
+            _ => false
+        })
+        .collect();
+    result.sort();
+    Ok(result)
+}
@@ -0,0 +1,156 @@
+use std::io;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use super::desktop::{self, DesktopEntry};
+use super::context::Context;
+use super::icon_sources::{self, IconSource};
+use super::icon_theme::IconSearch;
+
+
+/// An executable found by `mkdesktop scan`, with what we could work out about it
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub executable: PathBuf,
+    pub name: String,
+    /// From a .desktop file shipped for the executable until find_icon looks inside it; empty when there's neither
+    pub icon: String,
+}
+
+impl Candidate {
+    /// Looks inside the executable for an icon (an .exe's resources, an AppImage's .DirIcon), keeping the one from
+    /// its .desktop file when there's nothing there. That means reading every file, so it waits until the user has agreed.
+    pub fn find_icon(&mut self, context: &Context) {
+        // Icons from unrelated .desktop files in the same folder would be wrong for most of what's in there,
+        // so those only count when the .desktop file is for this executable
+        let found = icon_sources::suggest_icons(&self.executable, &IconSearch::new(context), &context.icon_cache_dir()).into_iter()
+            .find(|suggestion| suggestion.source != IconSource::DesktopFile)
+            .map(|suggestion| suggestion.icon);
+        if let Some(icon) = found { self.icon = icon }
+    }
+}
+
+
+/// Every executable file directly inside `dir` (not recursive) that doesn't already have an entry.
+/// Hidden files are skipped. Nothing is opened beyond .desktop files lying next to them (see Candidate::find_icon).
+pub fn scan(context: &Context, dir: &Path) -> io::Result<Vec<Candidate>> {
+    let existing: Vec<String> = desktop::read_desktop_files(context)?
+        .iter()
+        .map(|entry| entry.get_exec_command().program().to_string())
+        .collect();
+
+    let mut executables: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|f| f.ok())
+        .map(|f| f.path())
+        .filter(|path| is_executable(path))
+        .filter(|path| !path.file_name().and_then(|n| n.to_str()).unwrap_or(".").starts_with('.'))
+        .collect();
+    executables.sort();
+
+    let mut result = Vec::new();
+    for executable in executables {
+        let executable = fs::canonicalize(&executable).unwrap_or(executable);
+        if existing.iter().any(|program| Path::new(program) == executable) { continue }
+
+        let from_desktop_file = matching_desktop_file(&executable);
+
+        let name = match &from_desktop_file {
+            Some(entry) if !entry.get_name().is_empty() => entry.get_name().to_string(),
+            _ => guess_name(&executable)
+        };
+
+        let icon = from_desktop_file.map(|entry| entry.get_icon().to_string()).unwrap_or_default();
+
+        result.push(Candidate { executable: executable, name: name, icon: icon });
+    }
+
+    Ok(result)
+}
+
+
+pub fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_)       => false
+    }
+}
+
+
+/// Turns a filename like "my_tool-2.1.0-x86_64.AppImage" into "My Tool"
+pub fn guess_name(executable: &Path) -> String {
+    const EXTENSIONS: &[&str] = &["appimage", "sh", "bash", "py", "pl", "rb", "run", "bin", "x86_64", "exe"];
+    const ARCHITECTURES: &[&str] = &["x86", "amd64", "x64", "i386", "i686", "aarch64", "arm64", "linux"];
+
+    let mut stem = executable.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+    while let Some(dot) = stem.rfind('.') {
+        if !EXTENSIONS.contains(&stem[dot + 1..].to_lowercase().as_str()) { break }
+        stem.truncate(dot);
+    }
+
+    let words: Vec<&str> = stem
+        .split(|c| c == '-' || c == '_' || c == ' ')
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    // Drop trailing versions and architectures, but never the first word
+    let mut keep = words.len();
+    while keep > 1 {
+        let word = words[keep - 1].to_lowercase();
+        let is_version = word.trim_start_matches('v').chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false)
+            && word.trim_start_matches('v').chars().all(|c| c.is_ascii_digit() || c == '.');
+        if !is_version && !ARCHITECTURES.contains(&word.as_str()) { break }
+        keep -= 1;
+    }
+
+    let name: Vec<String> = words[..keep].iter().map(|word| capitalize(word)).collect();
+    if name.is_empty() { stem } else { name.join(" ") }
+}
+
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None        => String::new()
+    }
+}
+
+
+/// A .desktop file next to the executable whose Exec runs it (e.g. shipped alongside an unpacked tarball)
+fn matching_desktop_file(executable: &Path) -> Option<DesktopEntry> {
+    let folder = executable.parent()?;
+    let file_name = executable.file_name()?;
+
+    for file in fs::read_dir(folder).ok()?.filter_map(|f| f.ok()) {
+        let path = file.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("desktop") { continue }
+
+        let entry = match fs::File::open(&path).and_then(|f| DesktopEntry::read(&mut io::BufReader::new(f))) {
+            Ok(entry) => entry,
+            Err(_)    => continue
+        };
+
+        if Path::new(entry.get_exec_command().program()).file_name() == Some(file_name) {
+            return Some(entry);
+        }
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::guess_name;
+    use std::path::Path;
+
+    #[test]
+    fn guess_name_cleans_up_filenames() {
+        assert_eq!(guess_name(Path::new("/home/me/bin/my_tool-2.1.0-x86_64.AppImage")), "My Tool");
+        assert_eq!(guess_name(Path::new("backup.sh")), "Backup");
+        assert_eq!(guess_name(Path::new("firefox")), "Firefox");
+        assert_eq!(guess_name(Path::new("7zip")), "7zip");
+        assert_eq!(guess_name(Path::new("steam-runtime-v2")), "Steam Runtime");
+    }
+}
@@ -0,0 +1,473 @@
+extern crate gio;
+
+use std::io;
+use std::fs;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use gio::prelude::*;
+use gio::{Settings, SettingsBackend, SettingsSchema, SettingsSchemaSource};
+
+use super::context::Context;
+use super::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+use super::mime;
+
+
+pub const MEDIA_KEYS_SCHEMA: &str = "org.gnome.settings-daemon.plugins.media-keys";
+pub const CUSTOM_KEYBINDING_SCHEMA: &str = "org.gnome.settings-daemon.plugins.media-keys.custom-keybinding";
+const CUSTOM_KEYBINDINGS_KEY: &str = "custom-keybindings";
+const CUSTOM_KEYBINDINGS_PATH: &str = "/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/";
+
+/// kglobalshortcutsrc keys for a desktop file's shortcut
+const LAUNCH_KEY: &str = "_launch";
+const FRIENDLY_NAME_KEY: &str = "_k_friendly_name";
+/// Plasma 5.27 and later keep them in a [services][<desktop file ID>] group
+const SERVICES_PREFIX: &str = "services][";
+
+
+/// A key combination like Ctrl+Alt+T. Kept as modifiers and a key so it can be written either GNOME's
+/// way (<Control><Alt>t) or KDE's (Ctrl+Alt+T).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accelerator {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    logo: bool,
+    /// GTK's name for the key: lowercase for letters, "F5", "Return", "space"...
+    key: String,
+}
+
+impl Accelerator {
+    /// Takes either style, and mixtures like <Super>+T. There has to be exactly one non-modifier key.
+    pub fn parse(text: &str) -> Option<Accelerator> {
+        let mut result = Accelerator { ctrl: false, alt: false, shift: false, logo: false, key: String::new() };
+        let spaced = text.replace(&['<', '>', '+'][..], " ");
+
+        for token in spaced.split_whitespace() {
+            match token.to_lowercase().as_str() {
+                "ctrl" | "control" | "primary"          => result.ctrl = true,
+                "alt" | "mod1"                          => result.alt = true,
+                "shift"                                 => result.shift = true,
+                "super" | "meta" | "win" | "logo" | "mod4" => result.logo = true,
+                _ if !result.key.is_empty()             => return None,
+                key                                     => result.key = key_name(key, token),
+            }
+        }
+
+        if result.key.is_empty() { None } else { Some(result) }
+    }
+
+    pub fn to_gtk(&self) -> String {
+        let mut result = String::new();
+        if self.ctrl  { result.push_str("<Control>") }
+        if self.alt   { result.push_str("<Alt>") }
+        if self.shift { result.push_str("<Shift>") }
+        if self.logo  { result.push_str("<Super>") }
+        result + &self.key
+    }
+
+    pub fn to_kde(&self) -> String {
+        let mut parts = Vec::new();
+        if self.ctrl  { parts.push("Ctrl") }
+        if self.alt   { parts.push("Alt") }
+        if self.shift { parts.push("Shift") }
+        if self.logo  { parts.push("Meta") }
+
+        let key = match self.key.as_str() {
+            "space" => String::from("Space"),
+            key if key.chars().count() == 1 => key.to_uppercase(),
+            key => key.to_string()
+        };
+        parts.push(&key);
+        parts.join("+")
+    }
+}
+
+impl fmt::Display for Accelerator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_kde())
+    }
+}
+
+
+/// `lower` is `original` lowercased; single characters are kept that way, names get GTK's spelling
+fn key_name(lower: &str, original: &str) -> String {
+    match lower {
+        "space"            => String::from("space"),
+        "return" | "enter" => String::from("Return"),
+        "esc" | "escape"   => String::from("Escape"),
+        "tab"              => String::from("Tab"),
+        "print"            => String::from("Print"),
+        "del" | "delete"   => String::from("Delete"),
+        "home"             => String::from("Home"),
+        "end"              => String::from("End"),
+        key if key.chars().count() == 1 => key.to_string(),
+        key if key.starts_with('f') && key.len() > 1 && key[1..].chars().all(|c| c.is_ascii_digit()) => key.to_uppercase(),
+        _ => original.to_string()
+    }
+}
+
+
+/// GNOME's custom keybindings, which are a list of paths in the media-keys schema, each pointing at
+/// a relocatable custom-keybinding schema with a name, a command and a binding.
+/// Ours go at .../custom-keybindings/mkdesktop-<id>/ so they can be found again by desktop file ID.
+pub struct Gnome {
+    media_keys: Settings,
+    binding_schema: SettingsSchema,
+    backend: Option<SettingsBackend>,
+}
+
+impl Gnome {
+    /// The context's GSettings, or None where it has none or gnome-settings-daemon's schemas aren't installed
+    /// (GSettings aborts on schemas it doesn't know, so they're looked up first)
+    pub fn open(context: &Context) -> Option<Gnome> {
+        let gsettings = context.gsettings()?;
+        Gnome::from_source(&gsettings.schemas, gsettings.backend.clone())
+    }
+
+    /// Settings going through `backend` instead of dconf, e.g. gio::memory_settings_backend_new() for tests
+    pub fn from_source(source: &SettingsSchemaSource, backend: Option<SettingsBackend>) -> Option<Gnome> {
+        let media_keys = source.lookup(MEDIA_KEYS_SCHEMA, true)?;
+        let binding_schema = source.lookup(CUSTOM_KEYBINDING_SCHEMA, true)?;
+        Some(Gnome {
+            media_keys: Settings::new_full(&media_keys, backend.as_ref(), None),
+            binding_schema: binding_schema,
+            backend: backend,
+        })
+    }
+
+
+    pub fn get(&self, desktop_id: &str) -> Option<Accelerator> {
+        let path = binding_path(desktop_id);
+        if !self.paths().contains(&path) { return None }
+        Accelerator::parse(&self.binding(&path).get_string("binding")?.to_string())
+    }
+
+    /// Binds the keys to launching the entry, replacing whatever keys it had before
+    pub fn set(&self, desktop_id: &str, name: &str, accelerator: &Accelerator) -> io::Result<()> {
+        let path = binding_path(desktop_id);
+        let binding = self.binding(&path);
+        binding.set_string("name", name).map_err(settings_error)?;
+        binding.set_string("command", &launch_command(desktop_id)).map_err(settings_error)?;
+        binding.set_string("binding", &accelerator.to_gtk()).map_err(settings_error)?;
+
+        let mut paths = self.paths();
+        if !paths.contains(&path) {
+            paths.push(path);
+            self.set_paths(&paths)?;
+        }
+        Settings::sync();
+        Ok(())
+    }
+
+    pub fn remove(&self, desktop_id: &str) -> io::Result<()> {
+        let path = binding_path(desktop_id);
+        let mut paths = self.paths();
+        if !paths.contains(&path) { return Ok(()) }
+
+        let binding = self.binding(&path);
+        for key in &["name", "command", "binding"] { binding.reset(key) }
+        paths.retain(|p| p != &path);
+        self.set_paths(&paths)?;
+        Settings::sync();
+        Ok(())
+    }
+
+    /// The name of a custom keybinding (ours or anyone's) using these keys
+    pub fn taken_by(&self, accelerator: &Accelerator, except_id: &str) -> Option<String> {
+        let ours = binding_path(except_id);
+        self.paths().iter()
+            .filter(|path| **path != ours)
+            .map(|path| self.binding(path))
+            .find(|binding| binding.get_string("binding").and_then(|b| Accelerator::parse(&b.to_string())).as_ref() == Some(accelerator))
+            .map(|binding| binding.get_string("name").map(|n| n.to_string()).unwrap_or_default())
+    }
+
+    /// Desktop file IDs that have one of our keybindings
+    pub fn desktop_ids(&self) -> Vec<String> {
+        self.paths().iter()
+            .filter_map(|path| path.trim_end_matches('/').rsplit('/').next().map(String::from))
+            .filter(|name| name.starts_with("mkdesktop-"))
+            .filter_map(|name| self.binding(&format!("{}{}/", CUSTOM_KEYBINDINGS_PATH, name)).get_string("command"))
+            .filter_map(|command| command.split(' ').nth(1).map(String::from))
+            .collect()
+    }
+
+
+    fn binding(&self, path: &str) -> Settings {
+        Settings::new_full(&self.binding_schema, self.backend.as_ref(), Some(path))
+    }
+
+    fn paths(&self) -> Vec<String> {
+        self.media_keys.get_strv(CUSTOM_KEYBINDINGS_KEY).iter().map(|p| p.to_string()).collect()
+    }
+
+    fn set_paths(&self, paths: &[String]) -> io::Result<()> {
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        self.media_keys.set_strv(CUSTOM_KEYBINDINGS_KEY, &paths).map_err(settings_error)
+    }
+}
+
+
+/// GSettings paths only allow a limited set of characters, so the rest of the ID becomes dashes
+fn binding_path(desktop_id: &str) -> String {
+    let name: String = desktop_id.trim_end_matches(".desktop").chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '-' })
+        .collect();
+    let name = if name.starts_with("mkdesktop-") { name } else { format!("mkdesktop-{}", name) };
+    format!("{}{}/", CUSTOM_KEYBINDINGS_PATH, name)
+}
+
+/// GNOME runs a command rather than a desktop file, so it goes through gtk-launch,
+/// which keeps the shortcut working when the entry's Exec changes
+fn launch_command(desktop_id: &str) -> String {
+    format!("gtk-launch {}", desktop_id)
+}
+
+fn settings_error<E: fmt::Display>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("GSettings refused the change - {}", error))
+}
+
+
+/// KDE keeps global shortcuts in kglobalshortcutsrc, where a [services][<desktop file ID>] group makes kglobalaccel
+/// launch that file. Older Plasma used a group named after the ID alone, which is still read and cleaned up.
+/// kglobalaccel reads the file when it starts, so changes count from the next login.
+pub struct Kde {
+    path: PathBuf,
+    keyfile: KeyFile,
+}
+
+impl Kde {
+    /// A missing file is an empty one. KConfig's nested groups and loose syntax are read the way KDE reads them.
+    pub fn open(context: &Context) -> io::Result<Kde> {
+        let path = context.config_home().join("kglobalshortcutsrc");
+        let keyfile = KeyFile::open_lenient(&path)?;
+        Ok(Kde { path: path, keyfile: keyfile })
+    }
+
+    /// Whether KDE is the desktop, or has been: there's no point writing its config otherwise
+    pub fn in_use(context: &Context) -> bool {
+        mime::current_desktops().iter().any(|d| d == "kde") || context.config_home().join("kglobalshortcutsrc").exists()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(folder) = self.path.parent() { fs::create_dir_all(folder)?; }
+        fs::write(&self.path, self.keyfile.to_bytes())
+    }
+
+
+    pub fn get(&self, desktop_id: &str) -> Option<Accelerator> {
+        let value = self.keyfile.get_string(&services_group(desktop_id), LAUNCH_KEY)
+            .or_else(|| self.keyfile.get_string(desktop_id, LAUNCH_KEY))?;
+        Accelerator::parse(first_shortcut(&value))
+    }
+
+    /// Written the current way, replacing one in the old format. The name is only for the old format,
+    /// where _launch was "active shortcut,default shortcut,description"; KDE takes it from the desktop file now.
+    pub fn set(&mut self, desktop_id: &str, _name: &str, accelerator: &Accelerator) {
+        self.remove_legacy(desktop_id);
+        self.keyfile.set(&services_group(desktop_id), LAUNCH_KEY, &accelerator.to_kde());
+    }
+
+    pub fn remove(&mut self, desktop_id: &str) -> bool {
+        let removed = self.keyfile.unset(&services_group(desktop_id), LAUNCH_KEY);
+        self.remove_legacy(desktop_id) || removed
+    }
+
+    fn remove_legacy(&mut self, desktop_id: &str) -> bool {
+        let removed = self.keyfile.unset(desktop_id, LAUNCH_KEY);
+        self.keyfile.unset(desktop_id, FRIENDLY_NAME_KEY);
+        removed
+    }
+
+    /// "Component: action" of whatever else uses these keys in kglobalshortcutsrc
+    pub fn taken_by(&self, accelerator: &Accelerator, except_id: &str) -> Option<String> {
+        for group in self.keyfile.groups().into_iter().filter(|g| component(g) != except_id) {
+            for key in self.keyfile.keys(group).into_iter().filter(|k| !k.starts_with("_k_")) {
+                let value = self.keyfile.get_string(group, key).unwrap_or_default();
+                let active = value.split(',').next().unwrap_or("");
+                if active.split('\t').any(|keys| Accelerator::parse(keys).as_ref() == Some(accelerator)) {
+                    return Some(format!("{}: {}", component(group), key));
+                }
+            }
+        }
+        None
+    }
+
+    /// Desktop file IDs that have a launch shortcut, in either format
+    pub fn desktop_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.keyfile.groups().into_iter()
+            .filter(|g| component(g).ends_with(".desktop") && self.keyfile.get(g, LAUNCH_KEY).map(|v| !first_shortcut(v).is_empty()).unwrap_or(false))
+            .map(|g| component(g).to_string())
+            .collect();
+        ids.dedup();
+        ids
+    }
+}
+
+
+fn services_group(desktop_id: &str) -> String {
+    format!("{}{}", SERVICES_PREFIX, desktop_id)
+}
+
+/// The desktop file ID or component a group is for, whichever way it's written
+fn component(group: &str) -> &str {
+    group.trim_start_matches(SERVICES_PREFIX)
+}
+
+
+/// The older khotkeys daemon (Plasma before 5.25) keeps its own custom shortcuts in khotkeysrc,
+/// as Data_N groups with a Data_NTriggers0 holding Key=. Only read, to find keys that are already taken.
+pub fn khotkeys_taken_by(context: &Context, accelerator: &Accelerator) -> Option<String> {
+    let text = fs::read_to_string(context.config_home().join("khotkeysrc")).ok()?;
+    let keyfile = KeyFile::parse_lenient(&text);
+
+    keyfile.groups().into_iter()
+        .filter(|group| keyfile.get(group, "Type") == Some("SHORTCUT"))
+        .find(|group| keyfile.get_string(group, "Key").and_then(|k| Accelerator::parse(&k)).as_ref() == Some(accelerator))
+        .map(|group| {
+            let action = group.find("Triggers").map(|i| &group[..i]).unwrap_or(group);
+            keyfile.get_string(action, "Name").unwrap_or_else(|| action.to_string())
+        })
+}
+
+
+/// "none" is how KDE writes no shortcut at all; several are separated by tabs
+fn first_shortcut(value: &str) -> &str {
+    let active = value.split(',').next().unwrap_or("").split('\t').next().unwrap_or("");
+    if active == "none" { "" } else { active }
+}
+
+
+/// For when an entry's file is renamed: its shortcuts follow it, under the name in its file at `source`
+pub fn rename_entry(context: &Context, old_id: &str, new_id: &str, source: &Path) -> io::Result<()> {
+    if old_id == new_id { return Ok(()) }
+    let name = KeyFile::open(source)?.get_string(DESKTOP_ENTRY_GROUP, "Name").unwrap_or_else(|| new_id.to_string());
+    let name = name.as_str();
+
+    if let Some(gnome) = Gnome::open(context) {
+        if let Some(accelerator) = gnome.get(old_id) {
+            gnome.remove(old_id)?;
+            gnome.set(new_id, name, &accelerator)?;
+        }
+    }
+
+    let mut kde = Kde::open(context)?;
+    if let Some(accelerator) = kde.get(old_id) {
+        kde.remove(old_id);
+        kde.set(new_id, name, &accelerator);
+        kde.save()?;
+    }
+    Ok(())
+}
+
+
+/// Takes a deleted entry's shortcuts away
+pub fn forget_entry(context: &Context, desktop_id: &str) -> io::Result<()> {
+    if let Some(gnome) = Gnome::open(context) { gnome.remove(desktop_id)?; }
+
+    let mut kde = Kde::open(context)?;
+    if kde.remove(desktop_id) { kde.save()?; }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+    use super::super::context::{TestDir, GSettingsSource};
+
+    const SCHEMAS: &str = r#"<schemalist>
+  <schema id="org.gnome.settings-daemon.plugins.media-keys" path="/org/gnome/settings-daemon/plugins/media-keys/">
+    <key name="custom-keybindings" type="as"><default>[]</default></key>
+  </schema>
+  <schema id="org.gnome.settings-daemon.plugins.media-keys.custom-keybinding">
+    <key name="name" type="s"><default>''</default></key>
+    <key name="command" type="s"><default>''</default></key>
+    <key name="binding" type="s"><default>''</default></key>
+  </schema>
+</schemalist>
+"#;
+
+    #[test]
+    fn accelerators_read_and_write_both_styles() {
+        let gnome_style = Accelerator::parse("<Control><Alt>t").unwrap();
+        assert_eq!(Accelerator::parse("Ctrl+Alt+T"), Some(gnome_style.clone()));
+        assert_eq!(gnome_style.to_kde(), "Ctrl+Alt+T");
+        assert_eq!(Accelerator::parse("meta+f5").unwrap().to_gtk(), "<Super>F5");
+        assert_eq!(Accelerator::parse("Super+space").unwrap().to_kde(), "Meta+Space");
+        assert_eq!(Accelerator::parse("Ctrl+Alt"), None);
+        assert_eq!(Accelerator::parse("Ctrl+A+B"), None);
+    }
+
+    #[test]
+    fn gnome_keybindings_go_through_gsettings() {
+        // A schema source of our own, so this runs without gnome-settings-daemon installed
+        let root = TestDir::new("shortcut-gnome");
+        fs::write(root.join("media-keys.gschema.xml"), SCHEMAS).unwrap();
+        let status = Command::new("glib-compile-schemas").arg(&*root).status().expect("glib-compile-schemas is needed for this test");
+        assert!(status.success());
+
+        let schemas = SettingsSchemaSource::new_from_directory(&root, None, true).unwrap();
+        let mut context = root.context();
+        context.set_gsettings(Some(GSettingsSource { schemas: schemas, backend: gio::memory_settings_backend_new() }));
+        let gnome = Gnome::open(&context).unwrap();
+        let keys = Accelerator::parse("Super+T").unwrap();
+
+        gnome.set("mkdesktop-Tool.desktop", "Tool", &keys).unwrap();
+        assert_eq!(gnome.get("mkdesktop-Tool.desktop"), Some(keys.clone()));
+        assert_eq!(gnome.paths(), vec![format!("{}mkdesktop-Tool/", CUSTOM_KEYBINDINGS_PATH)]);
+        assert_eq!(gnome.binding(&gnome.paths()[0]).get_string("command").unwrap().to_string(), "gtk-launch mkdesktop-Tool.desktop");
+        assert_eq!(gnome.desktop_ids(), vec!["mkdesktop-Tool.desktop"]);
+
+        assert_eq!(gnome.taken_by(&keys, "mkdesktop-Other.desktop"), Some(String::from("Tool")));
+        assert_eq!(gnome.taken_by(&keys, "mkdesktop-Tool.desktop"), None);
+
+        // Renaming and deleting an entry go through the context's settings too
+        fs::write(root.join("gadget.desktop"), "[Desktop Entry]\nName=Gadget\nExec=gadget\n").unwrap();
+        rename_entry(&context, "mkdesktop-Tool.desktop", "mkdesktop-Gadget.desktop", &root.join("gadget.desktop")).unwrap();
+        assert_eq!(gnome.desktop_ids(), vec!["mkdesktop-Gadget.desktop"]);
+        assert_eq!(gnome.get("mkdesktop-Gadget.desktop"), Some(keys.clone()));
+
+        forget_entry(&context, "mkdesktop-Gadget.desktop").unwrap();
+        assert_eq!(gnome.get("mkdesktop-Gadget.desktop"), None);
+        assert!(gnome.paths().is_empty());
+    }
+
+    #[test]
+    fn kde_shortcuts_go_into_kglobalshortcutsrc() {
+        let root = TestDir::new("shortcut-kde");
+        let config = root.join("config");
+        fs::create_dir_all(&config).unwrap();
+        fs::write(config.join("kglobalshortcutsrc"), "[kwin]\nWalk Through Windows=Alt+Tab,Alt+Tab,Walk Through Windows\n\n\
+                                                   [services][org.kde.konsole.desktop]\n_launch=Ctrl+Alt+K\n\n\
+                                                   [old.desktop]\n_k_friendly_name=Old\n_launch=Meta+O,none,Old\n").unwrap();
+        fs::write(config.join("khotkeysrc"), "[Data_1]\nName=Screenshot\nType=SIMPLE_ACTION_DATA\n\n[Data_1Triggers0]\nKey=Print\nType=SHORTCUT\n").unwrap();
+        let context = root.context();
+
+        let mut kde = Kde::open(&context).unwrap();
+        assert_eq!(kde.taken_by(&Accelerator::parse("Alt+Tab").unwrap(), "tool.desktop"), Some(String::from("kwin: Walk Through Windows")));
+        assert_eq!(khotkeys_taken_by(&context, &Accelerator::parse("Print").unwrap()), Some(String::from("Screenshot")));
+        assert_eq!(kde.taken_by(&Accelerator::parse("Ctrl+Alt+K").unwrap(), "tool.desktop"), Some(String::from("org.kde.konsole.desktop: _launch")));
+        assert_eq!(kde.get("old.desktop"), Accelerator::parse("Meta+O"));
+
+        kde.set("tool.desktop", "Tool", &Accelerator::parse("<Control><Alt>t").unwrap());
+        kde.save().unwrap();
+        let text = fs::read_to_string(config.join("kglobalshortcutsrc")).unwrap();
+        assert!(text.contains("[services][org.kde.konsole.desktop]\n_launch=Ctrl+Alt+K\n"), "{}", text);
+        assert!(text.contains("[services][tool.desktop]\n_launch=Ctrl+Alt+T\n"), "{}", text);
+
+        fs::write(root.join("gadget.desktop"), "[Desktop Entry]\nName=Gadget\nExec=gadget\n").unwrap();
+        rename_entry(&context, "tool.desktop", "gadget.desktop", &root.join("gadget.desktop")).unwrap();
+        rename_entry(&context, "old.desktop", "new.desktop", &root.join("gadget.desktop")).unwrap();
+        let kde = Kde::open(&context).unwrap();
+        assert_eq!(kde.desktop_ids(), vec!["org.kde.konsole.desktop", "gadget.desktop", "new.desktop"]);
+        assert_eq!(kde.keyfile.get("services][gadget.desktop", LAUNCH_KEY), Some("Ctrl+Alt+T"));
+        assert_eq!(kde.keyfile.get("old.desktop", LAUNCH_KEY), None);
+
+        forget_entry(&context, "gadget.desktop").unwrap();
+        forget_entry(&context, "new.desktop").unwrap();
+        assert_eq!(Kde::open(&context).unwrap().desktop_ids(), vec!["org.kde.konsole.desktop"]);
+    }
+}
@@ -0,0 +1,180 @@
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::context::Context;
+use super::desktop::{self, DesktopEntry};
+use super::exec::Exec;
+
+include!(concat!(env!("OUT_DIR"), "/webapp-user.js.rs"));
+include!(concat!(env!("OUT_DIR"), "/webapp-userChrome.css.rs"));
+
+
+/// Browsers looked for on $PATH when none is given, in order.
+/// Chromium-based ones come first since their --app mode is the real thing.
+const KNOWN_BROWSERS: [&str; 9] = [
+    "chromium", "chromium-browser", "google-chrome", "google-chrome-stable", "brave-browser", "microsoft-edge", "vivaldi",
+    "firefox", "firefox-esr",
+];
+
+const CHROMIUM_NAMES: [&str; 5] = ["chrom", "brave", "edge", "vivaldi", "opera"];
+const FIREFOX_NAMES: [&str; 3] = ["firefox", "librewolf", "waterfox"];
+
+/// Where the key with the app's URL goes, so web apps can be told apart from other entries
+pub const WEBAPP_URL_KEY: &str = "X-Mkdesktop-WebApp";
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrowserKind {
+    Chromium,
+    Firefox,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Browser {
+    pub kind: BrowserKind,
+    pub program: String,
+}
+
+impl Browser {
+    /// Goes by the program's filename, so /opt/brave.com/brave/brave and a wrapper called firefox-nightly both work
+    pub fn from_program(program: &str) -> Option<Browser> {
+        let name = Path::new(program).file_name()?.to_str()?.to_lowercase();
+
+        let kind = if FIREFOX_NAMES.iter().any(|n| name.contains(n))       { BrowserKind::Firefox }
+                   else if CHROMIUM_NAMES.iter().any(|n| name.contains(n)) { BrowserKind::Chromium }
+                   else { return None };
+
+        Some(Browser { kind: kind, program: program.to_string() })
+    }
+
+    /// The first known browser that's installed
+    pub fn detect() -> Option<Browser> {
+        KNOWN_BROWSERS.iter()
+            .find(|program| desktop::find_program(program).is_some())
+            .and_then(|program| Browser::from_program(program))
+    }
+}
+
+
+/// A site opened in its own browser window, with a profile of its own so its cookies are kept apart
+/// and it can run next to the browser's normal windows
+#[derive(Clone, Debug)]
+pub struct WebApp {
+    pub name: String,
+    pub url: String,
+    pub browser: Browser,
+    /// Fullscreen with no browser UI at all, for kiosks and wall displays
+    pub kiosk: bool,
+}
+
+impl WebApp {
+    /// Names both the profile folder and the window class, e.g. mkdesktop-webapp-Wiki
+    pub fn id(&self) -> String {
+        let filename = desktop::name_to_filename(&self.name);
+        format!("mkdesktop-webapp-{}", filename.trim_start_matches("mkdesktop-").trim_end_matches(".desktop"))
+    }
+
+    pub fn profile_dir(&self, context: &Context) -> PathBuf {
+        context.webapp_profiles_dir().join(self.id())
+    }
+
+
+    /// The browser command line. The window class is set explicitly so it matches StartupWMClass
+    /// and docks group the app's windows under its own launcher instead of the browser's.
+    pub fn exec(&self, context: &Context) -> Exec {
+        let profile = self.profile_dir(context).to_string_lossy().into_owned();
+        let url = self.url.clone();
+        let id = self.id();
+
+        let mut args = Vec::new();
+        match self.browser.kind {
+            BrowserKind::Chromium => {
+                args.push(format!("--user-data-dir={}", profile));
+                args.push(format!("--class={}", id));
+                if self.kiosk { args.push(String::from("--kiosk")); args.push(url) }
+                else          { args.push(format!("--app={}", url)) }
+            }
+            BrowserKind::Firefox => {
+                for arg in &["--no-remote", "--profile", &profile, "--class", &id, "--name", &id] {
+                    args.push(arg.to_string());
+                }
+                if self.kiosk { args.push(String::from("--kiosk")) }
+                args.push(url);
+            }
+        }
+
+        Exec::new(&self.browser.program, args)
+    }
+
+
+    /// Creates the profile folder. Firefox dropped its site-specific browser mode,
+    /// so its profile gets a userChrome.css that hides the tabs and toolbars instead.
+    pub fn prepare_profile(&self, context: &Context) -> io::Result<()> {
+        let dir = self.profile_dir(context);
+        fs::create_dir_all(&dir)?;
+
+        if self.browser.kind == BrowserKind::Firefox {
+            fs::write(dir.join("user.js"), WEBAPP_USER_JS)?;
+            fs::create_dir_all(dir.join("chrome"))?;
+            fs::write(dir.join("chrome").join("userChrome.css"), WEBAPP_USERCHROME_CSS)?;
+        }
+        Ok(())
+    }
+
+
+    pub fn to_entry(&self, context: &Context, comment: &str, icon: &str, categories: &str) -> DesktopEntry {
+        let mut entry = DesktopEntry::new(&self.name, comment, "", self.exec(context), icon, categories);
+        entry.set_extra_key("StartupWMClass", &self.id());
+        entry.set_extra_key(WEBAPP_URL_KEY, &self.url);
+        entry
+    }
+}
+
+
+/// Page addresses typed without a scheme get https://, like a browser's address bar would
+pub fn normalize_url(url: &str) -> String {
+    let url = url.trim();
+    if url.contains("://") || url.starts_with("file:") { url.to_string() }
+    else                                               { format!("https://{}", url) }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::{Context, CommandRegistrar};
+
+    #[test]
+    fn webapps_get_their_own_profile_and_window_class() {
+        let context = Context::new(Path::new("/data"), Path::new("/config"), Box::new(CommandRegistrar::new("true")));
+        let mut app = WebApp {
+            name: String::from("Team Wiki"),
+            url: normalize_url("wiki.example.com"),
+            browser: Browser::from_program("/usr/bin/chromium").unwrap(),
+            kiosk: false,
+        };
+
+        assert_eq!(app.id(), "mkdesktop-webapp-Team-Wiki");
+        assert_eq!(app.exec(&context).args(), &[
+            "--user-data-dir=/data/mkdesktop-webapps/mkdesktop-webapp-Team-Wiki",
+            "--class=mkdesktop-webapp-Team-Wiki",
+            "--app=https://wiki.example.com",
+        ]);
+
+        app.browser = Browser::from_program("firefox").unwrap();
+        app.kiosk = true;
+        let args = app.exec(&context).args().to_vec();
+        assert_eq!(&args[..3], &["--no-remote", "--profile", "/data/mkdesktop-webapps/mkdesktop-webapp-Team-Wiki"]);
+        assert_eq!(&args[args.len() - 2..], &["--kiosk", "https://wiki.example.com"]);
+
+        app.url = normalize_url("wiki.example.com/Team%20Page");
+        assert_eq!(app.exec(&context).launch_args().last().unwrap(), "https://wiki.example.com/Team%20Page");
+        assert!(app.exec(&context).to_string().ends_with(" https://wiki.example.com/Team%%20Page"));
+
+        let entry = app.to_entry(&context, "", "", "Network;");
+        assert_eq!(entry.get_extra_key("StartupWMClass").unwrap(), "mkdesktop-webapp-Team-Wiki");
+        assert!(Browser::from_program("gedit").is_none());
+    }
+}
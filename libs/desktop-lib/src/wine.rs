@@ -0,0 +1,267 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::context::Context;
+use super::desktop::{self, DesktopEntry};
+use super::exec::Exec;
+
+
+/// What a Windows file needs Wine to do with it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WineTarget {
+    /// A program, run directly
+    Executable,
+    /// A .lnk shortcut, like the ones installers put into the prefix's Start Menu
+    Shortcut,
+    /// A Windows Installer package, run through msiexec
+    Installer,
+}
+
+impl WineTarget {
+    pub fn of(path: &Path) -> Option<WineTarget> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "exe" => Some(WineTarget::Executable),
+            "lnk" => Some(WineTarget::Shortcut),
+            "msi" => Some(WineTarget::Installer),
+            _     => None
+        }
+    }
+}
+
+
+/// $WINEPREFIX, or ~/.wine like wine itself defaults to
+pub fn default_prefix(context: &Context) -> PathBuf {
+    match env::var_os("WINEPREFIX") {
+        Some(ref prefix) if !prefix.is_empty() => PathBuf::from(prefix),
+        _ => context.home().join(".wine")
+    }
+}
+
+
+/// Prefixes found where wine and winetricks put them (~/.wine, ~/.wine-*, ~/.local/share/wineprefixes/*).
+/// A folder counts if it has a system.reg, which wine writes when it sets a prefix up.
+pub fn find_prefixes(context: &Context) -> Vec<PathBuf> {
+    let home = context.home();
+    let mut candidates = vec![default_prefix(context), home.join(".wine")];
+
+    if let Ok(files) = fs::read_dir(home) {
+        let mut others: Vec<PathBuf> = files.filter_map(|f| f.ok())
+            .filter(|f| f.file_name().to_str().map(|n| n.starts_with(".wine-")).unwrap_or(false))
+            .map(|f| f.path())
+            .collect();
+        others.sort();
+        candidates.extend(others);
+    }
+    if let Ok(files) = fs::read_dir(home.join(".local").join("share").join("wineprefixes")) {
+        let mut others: Vec<PathBuf> = files.filter_map(|f| f.ok()).map(|f| f.path()).collect();
+        others.sort();
+        candidates.extend(others);
+    }
+
+    let mut result: Vec<PathBuf> = Vec::new();
+    for candidate in candidates {
+        if candidate.join("system.reg").is_file() && !result.contains(&candidate) { result.push(candidate) }
+    }
+    result
+}
+
+
+/// Where a Windows path like C:\Program Files\Foo\foo.exe is inside a prefix.
+/// C: is always drive_c; other drives go through the dosdevices symlinks.
+pub fn unix_path(prefix: &Path, windows_path: &str) -> Option<PathBuf> {
+    let mut chars = windows_path.chars();
+    let drive = chars.next()?.to_ascii_lowercase();
+    if !drive.is_ascii_alphabetic() || chars.next()? != ':' { return None }
+
+    let mut result = if drive == 'c' { prefix.join("drive_c") }
+                     else            { prefix.join("dosdevices").join(format!("{}:", drive)) };
+    for part in chars.as_str().split('\\').filter(|p| !p.is_empty()) {
+        result.push(part);
+    }
+    Some(result)
+}
+
+
+/// The Windows path a .lnk file points at, from its LinkInfo (MS-SHLLINK 2.3).
+/// Only local targets are understood; network shares and bare ID lists give None.
+pub fn shortcut_target(data: &[u8]) -> Option<String> {
+    const HAS_LINK_TARGET_ID_LIST: u32 = 0x1;
+    const HAS_LINK_INFO: u32 = 0x2;
+    const VOLUME_ID_AND_LOCAL_BASE_PATH: u32 = 0x1;
+
+    if read_u32(data, 0)? != 0x4C { return None }
+    let flags = read_u32(data, 0x14)?;
+    if flags & HAS_LINK_INFO == 0 { return None }
+
+    let mut offset = 0x4C;
+    if flags & HAS_LINK_TARGET_ID_LIST != 0 {
+        offset += 2 + read_u16(data, offset)? as usize;
+    }
+
+    let info = data.get(offset..)?;
+    if read_u32(info, 8)? & VOLUME_ID_AND_LOCAL_BASE_PATH == 0 { return None }
+    let base = c_string(info, read_u32(info, 16)? as usize)?;
+    let suffix = c_string(info, read_u32(info, 24)? as usize).unwrap_or_default();
+
+    if suffix.is_empty() || base.ends_with('\\') { Some(base + &suffix) }
+    else                                         { Some(format!("{}\\{}", base, suffix)) }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from(bytes[0]) | u16::from(bytes[1]) << 8)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from(read_u16(data, offset)?) | u32::from(read_u16(data, offset + 2)?) << 16)
+}
+
+/// A NUL-terminated string in the system codepage, read as Latin-1 since that's right for plain ASCII paths
+fn c_string(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let end = bytes.iter().position(|b| *b == 0)?;
+    Some(bytes[..end].iter().map(|b| *b as char).collect())
+}
+
+
+/// A Windows program (or shortcut, or installer) run with wine in a particular prefix
+#[derive(Clone, Debug)]
+pub struct WineApp {
+    pub target: PathBuf,
+    pub kind: WineTarget,
+    pub prefix: PathBuf,
+    /// wine, or wine64/a wrapper like wine-staging
+    pub wine: String,
+}
+
+impl WineApp {
+    pub fn new(target: &Path, prefix: &Path) -> Option<WineApp> {
+        let wine = if desktop::find_program("wine").is_none() && desktop::find_program("wine64").is_some() { "wine64" }
+                   else { "wine" };
+        Some(WineApp {
+            target: target.to_path_buf(),
+            kind: WineTarget::of(target)?,
+            prefix: prefix.to_path_buf(),
+            wine: wine.to_string(),
+        })
+    }
+
+    /// The .exe that ends up running: the target itself, or what a shortcut points at (if it's in the prefix)
+    pub fn executable(&self) -> Option<PathBuf> {
+        match self.kind {
+            WineTarget::Executable => Some(self.target.clone()),
+            WineTarget::Shortcut => {
+                let windows_path = shortcut_target(&fs::read(&self.target).ok()?)?;
+                unix_path(&self.prefix, &windows_path)
+            }
+            WineTarget::Installer => None
+        }
+    }
+
+    /// `env WINEPREFIX=... wine ...`. Shortcuts are opened with `start /unix` so wine applies
+    /// their arguments and working folder itself.
+    pub fn exec(&self) -> Exec {
+        let target = self.target.to_string_lossy().to_string();
+        let args = match self.kind {
+            WineTarget::Executable => vec![target],
+            WineTarget::Shortcut   => vec![String::from("start"), String::from("/unix"), target],
+            WineTarget::Installer  => vec![String::from("msiexec"), String::from("/i"), target],
+        };
+
+        let mut exec = Exec::new(&self.wine, args);
+        exec.set_env_var("WINEPREFIX", &self.prefix.to_string_lossy());
+        exec
+    }
+
+    /// Wine names windows after the lowercased .exe, e.g. notepad.exe, which is what docks match StartupWMClass against
+    pub fn wm_class(&self) -> String {
+        let exe = match self.kind {
+            WineTarget::Installer => Some(PathBuf::from("msiexec.exe")),
+            _ => self.executable()
+        };
+        exe.and_then(|e| e.file_name().map(|n| n.to_string_lossy().to_lowercase()))
+            .unwrap_or_default()
+    }
+
+    /// Windows programs tend to look for their files next to themselves
+    pub fn working_dir(&self) -> Option<PathBuf> {
+        self.executable().unwrap_or_else(|| self.target.clone()).parent().map(Path::to_path_buf)
+    }
+
+    pub fn apply_to(&self, entry: &mut DesktopEntry) {
+        let wm_class = self.wm_class();
+        if !wm_class.is_empty() { entry.set_extra_key("StartupWMClass", &wm_class) }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::context::TestDir;
+
+    /// A minimal .lnk with no ID list and a LinkInfo holding just the local base path
+    fn shortcut_to(path: &str) -> Vec<u8> {
+        let mut data = vec![0u8; 0x4C];
+        data[0] = 0x4C;
+        data[0x14] = 0x2;
+
+        let header_size = 28u32;
+        let size = header_size + path.len() as u32 + 2;
+        for value in &[size, header_size, 1, 0, header_size, 0, header_size + path.len() as u32 + 1] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(path.as_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    #[test]
+    fn wine_apps_run_in_their_prefix() {
+        let prefix = Path::new("/home/me/.wine-games");
+
+        let app = WineApp::new(Path::new("/games/Tool/Tool.EXE"), prefix).unwrap();
+        let exec = app.exec();
+        assert_eq!(exec.args(), &["/games/Tool/Tool.EXE"]);
+        assert_eq!(exec.env().get("WINEPREFIX").unwrap(), "/home/me/.wine-games");
+        assert_eq!(app.wm_class(), "tool.exe");
+        assert_eq!(app.working_dir(), Some(PathBuf::from("/games/Tool")));
+
+        let installer = WineApp::new(Path::new("/tmp/setup.msi"), prefix).unwrap();
+        assert_eq!(installer.exec().args(), &["msiexec", "/i", "/tmp/setup.msi"]);
+        assert_eq!(installer.wm_class(), "msiexec.exe");
+        assert!(WineApp::new(Path::new("/usr/bin/tool"), prefix).is_none());
+    }
+
+    #[test]
+    fn prefixes_are_found_by_their_system_reg() {
+        // Wine's own default has to be ~/.wine for this
+        if env::var_os("WINEPREFIX").is_some() { return }
+
+        let root = TestDir::new("wine");
+        let context = root.context();
+        let home = root.join("home");
+        assert_eq!(default_prefix(&context), home.join(".wine"));
+
+        let steam = home.join(".local").join("share").join("wineprefixes").join("steam");
+        for prefix in &[home.join(".wine-games"), home.join(".wine"), steam.clone()] {
+            fs::create_dir_all(prefix).unwrap();
+            fs::write(prefix.join("system.reg"), "WINE REGISTRY Version 2\n").unwrap();
+        }
+        fs::create_dir_all(home.join(".wine-empty")).unwrap();
+
+        assert_eq!(find_prefixes(&context), vec![home.join(".wine"), home.join(".wine-games"), steam]);
+    }
+
+    #[test]
+    fn shortcuts_point_into_the_prefix() {
+        let lnk = shortcut_to("C:\\Program Files\\Tool\\Tool.exe");
+        assert_eq!(shortcut_target(&lnk), Some(String::from("C:\\Program Files\\Tool\\Tool.exe")));
+        assert_eq!(unix_path(Path::new("/p"), "C:\\Program Files\\Tool\\Tool.exe"), Some(PathBuf::from("/p/drive_c/Program Files/Tool/Tool.exe")));
+        assert_eq!(unix_path(Path::new("/p"), "D:\\setup.exe"), Some(PathBuf::from("/p/dosdevices/d:/setup.exe")));
+        assert_eq!(unix_path(Path::new("/p"), "\\\\server\\share"), None);
+        assert_eq!(shortcut_target(b"not a shortcut"), None);
+    }
+}
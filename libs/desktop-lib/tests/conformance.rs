@@ -0,0 +1,110 @@
+//! Parses every file in tests/corpus and compares the result with the .tree file next to it.
+//! Files that shouldn't parse have the expected error in their .tree instead.
+//! Run with MKDESKTOP_BLESS=1 to write out the .tree files after an intended change (and review the diff).
+
+extern crate desktop_lib;
+
+use std::io;
+use std::fs;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use desktop_lib::desktop::DesktopEntry;
+use desktop_lib::keyfile::{KeyFile, DESKTOP_ENTRY_GROUP};
+
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+    let mut result: Vec<PathBuf> = fs::read_dir(&dir).expect("Couldn't read the corpus")
+        .map(|f| f.unwrap().path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("desktop"))
+        .collect();
+    result.sort();
+    result
+}
+
+
+/// Every group and key with its unescaped value, then how DesktopEntry reads the [Desktop Entry] group
+fn tree(keyfile: &KeyFile) -> String {
+    let mut result = String::new();
+
+    for group in keyfile.groups() {
+        result += &format!("[{}]\n", group);
+        for key in keyfile.keys(group) {
+            result += &format!("  {} = {:?}\n", key, keyfile.get_string(group, key).unwrap());
+        }
+    }
+
+    if keyfile.has_group(DESKTOP_ENTRY_GROUP) {
+        let entry = DesktopEntry::from_keyfile(keyfile);
+        let exec = entry.get_exec_command();
+        result += "--\n";
+        result += &format!("name: {:?}\n", entry.get_name());
+        result += &format!("env: {:?}\n", exec.env());
+        result += &format!("program: {:?}\n", exec.program());
+        result += &format!("args: {:?}\n", exec.args());
+    }
+
+    result
+}
+
+
+fn parse(bytes: &[u8]) -> Result<KeyFile, String> {
+    KeyFile::read(&mut io::Cursor::new(bytes)).map_err(|e| e.to_string())
+}
+
+
+#[test]
+fn corpus_parses_to_expected_trees() {
+    let bless = env::var_os("MKDESKTOP_BLESS").is_some();
+    let mut failures = Vec::new();
+
+    for path in corpus() {
+        let actual = match parse(&fs::read(&path).unwrap()) {
+            Ok(keyfile) => tree(&keyfile),
+            Err(error)  => format!("error: {}\n", error)
+        };
+
+        let expected_path = path.with_extension("tree");
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!("{}\n--- expected\n{}--- actual\n{}", path.display(), expected, actual));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+
+#[test]
+fn corpus_survives_being_written_back() {
+    for path in corpus() {
+        let keyfile = match parse(&fs::read(&path).unwrap()) {
+            Ok(k)  => k,
+            Err(_) => continue
+        };
+
+        let written = keyfile.to_bytes();
+        let reparsed = parse(&written).unwrap_or_else(|e| panic!("{} doesn't reparse: {}", path.display(), e));
+        assert_eq!(tree(&reparsed), tree(&keyfile), "{}", path.display());
+        assert_eq!(reparsed.to_bytes(), written, "{}", path.display());
+
+        // DesktopEntry only writes what it knows about, but that much has to come back the same
+        if keyfile.has_group(DESKTOP_ENTRY_GROUP) {
+            let entry = DesktopEntry::from_keyfile(&keyfile);
+            let mut first = Vec::new();
+            entry.write(&mut first).unwrap();
+
+            let reread = DesktopEntry::read(&mut io::Cursor::new(&first))
+                .unwrap_or_else(|e| panic!("{} doesn't reparse as an entry: {}", path.display(), e));
+            let mut second = Vec::new();
+            reread.write(&mut second).unwrap();
+            assert_eq!(String::from_utf8(second).unwrap(), String::from_utf8(first).unwrap(), "{}", path.display());
+        }
+    }
+}
@@ -2,7 +2,10 @@
 extern crate clap;
 extern crate desktop_lib;
 
+use std::path::Path;
+
 use desktop_lib::{cli, desktop, gui};
+use desktop_lib::context::Context;
 
 
 fn main() {
@@ -12,6 +15,217 @@ fn main() {
         (author:  "Nigel Baillie <metreckk@gmail.com>")
         (about:   "Creates/updates .desktop files in the applications directory with ease")
 
+        (@subcommand new =>
+            (about: "Makes a new entry for an executable")
+            (@arg FILE: required_unless[exec url type]           "Executable file, or an installed Flatpak's app ID or snap's name")
+            (@arg ARGS: +last ...                                "Arguments for the program, after --")
+            (@arg exec:        --exec        -e   +takes_value   "Command to run instead of <FILE>, e.g. \"firefox --private-window\"")
+            (@arg type:        --type             +takes_value   "application (the default), link or directory (a menu folder)")
+            (@arg url:         --url              +takes_value   "URL for a link entry (implies --type link)")
+            (@arg arg:         --arg  +takes_value +multiple number_of_values(1) +allow_hyphen_values "Argument for the program (can be repeated)")
+            (@arg env:         --env  +takes_value +multiple number_of_values(1) "Environment variable as KEY=VALUE (can be repeated)")
+            (@arg name:        --name        -n   +takes_value   "Name of program")
+            (@arg icon:        --icon        -i   +takes_value   "Path to icon")
+            (@arg categories:  --categories  -c   +takes_value   "Semicolon-separated categories")
+            (@arg mime:        --mime +takes_value +multiple number_of_values(1) "MIME type the program opens, or a file extension like .md to look one up (can be repeated)")
+            (@arg path:        --path        -p   +takes_value   "Working directory for when <FILE> gets run (defaults to $PWD)")
+            (@arg comment:     --tooltip     -t   +takes_value   "Tooltip when user hovers over application in launcher")
+            (@arg on_desktop:  --("on-desktop")                  "Also put an icon for the entry on the desktop")
+            (@arg wine_prefix: --("wine-prefix") +takes_value   "Wine prefix to run a .exe, .lnk or .msi <FILE> in (defaults to $WINEPREFIX or ~/.wine)")
+            (@arg yes: -y --yes                                  "Create desktop entry without asking about anything")
+        )
+        (@subcommand webapp =>
+            (about: "Makes an entry that opens a web page in a browser window of its own, with its own profile")
+            (@arg URL: +required                                 "Address of the web app")
+            (@arg name:        --name        -n   +takes_value   "Name of the web app")
+            (@arg browser:     --browser     -b   +takes_value   "Chromium-based browser or Firefox to use (found on $PATH if left out)")
+            (@arg kiosk:       --kiosk                           "Open fullscreen with no browser controls at all")
+            (@arg icon:        --icon        -i   +takes_value   "Path to icon (the browser's icon if left out)")
+            (@arg categories:  --categories  -c   +takes_value   "Semicolon-separated categories (defaults to Network)")
+            (@arg comment:     --tooltip     -t   +takes_value   "Tooltip when user hovers over the web app in launcher")
+            (@arg yes: -y --yes                                  "Create the entry without asking about anything")
+        )
+        (@subcommand edit =>
+            (about: "Changes an existing entry")
+            (@arg ENTRY: +required                               "Entry index or entry name")
+            (@arg ARGS: +last ...                                "New arguments for the program, after --")
+            (@arg exec:        --exec        -e   +takes_value   "New command, e.g. \"firefox --private-window\"")
+            (@arg type:        --type             +takes_value   "Change the entry to an application, link or directory")
+            (@arg url:         --url              +takes_value   "New URL for a link entry")
+            (@arg arg:         --arg  +takes_value +multiple number_of_values(1) +allow_hyphen_values "New argument for the program (can be repeated, replaces the current ones)")
+            (@arg env:         --env  +takes_value +multiple number_of_values(1) "Sets an environment variable as KEY=VALUE, or removes it when given just KEY (can be repeated)")
+            (@arg name:        --name        -n   +takes_value   "Name of program")
+            (@arg icon:        --icon        -i   +takes_value   "Path to icon")
+            (@arg categories:  --categories  -c   +takes_value   "Semicolon-separated categories")
+            (@arg mime:        --mime +takes_value +multiple number_of_values(1) "MIME type the program opens, or a file extension like .md (can be repeated, replaces the current ones)")
+            (@arg path:        --path        -p   +takes_value   "Working directory for when the program gets run")
+            (@arg comment:     --tooltip     -t   +takes_value   "Tooltip when user hovers over application in launcher")
+            (@arg on_desktop:  --("on-desktop")                  "Put an icon for the entry on the desktop")
+            (@arg off_desktop: --("off-desktop") conflicts_with[on_desktop] "Take the entry's icon off the desktop")
+            (@arg yes: -y --yes                                  "Update desktop entry without asking about anything")
+        )
+        (@subcommand show =>
+            (about: "Prints an entry's desktop file")
+            (@arg ENTRY: +required                               "Entry index or entry name")
+        )
+        (@subcommand list =>
+            (about: "Lists the desktop files managed by mkdesktop")
+        )
+        (@subcommand set =>
+            (about: "Sets keys in an entry's desktop file, e.g. `mkdesktop set steam Name[de]=Dampf X-Foo=bar` \
+                     or `mkdesktop set --all-in-category Game Terminal=false`")
+            (@arg ENTRY: required_unless[match all_in_category]  "Entry index or entry name (left out with --match/--all-in-category)")
+            (@arg ASSIGNMENTS: ...                               "Key=Value or Key[locale]=Value")
+            (@arg match:           --match           +takes_value "Every entry whose name matches a glob like 'steam-*'")
+            (@arg all_in_category: --("all-in-category") +takes_value "Every entry in a category")
+            (@arg yes: -y --yes                                  "Don't ask before changing several entries")
+        )
+        (@subcommand unset =>
+            (about: "Removes keys from an entry's desktop file")
+            (@arg ENTRY: required_unless[match all_in_category]  "Entry index or entry name (left out with --match/--all-in-category)")
+            (@arg KEYS: ...                                      "Key or Key[locale]")
+            (@arg match:           --match           +takes_value "Every entry whose name matches a glob like 'steam-*'")
+            (@arg all_in_category: --("all-in-category") +takes_value "Every entry in a category")
+            (@arg yes: -y --yes                                  "Don't ask before changing several entries")
+        )
+        (@subcommand rm =>
+            (about: "Removes an entry, or several with --match/--all-in-category")
+            (@arg ENTRY: required_unless[match all_in_category] conflicts_with[match all_in_category] "Entry index or entry name")
+            (@arg match:           --match           +takes_value "Every entry whose name matches a glob like 'steam-*'")
+            (@arg all_in_category: --("all-in-category") +takes_value "Every entry in a category")
+            (@arg yes: -y --yes                                  "Don't ask before removing several entries")
+        )
+        (@subcommand scan =>
+            (about: "Makes an entry for every executable in a folder, guessing names and icons")
+            (@arg DIR: +required                                 "Folder to look in (not recursive)")
+            (@arg yes: -y --yes                                  "Create the entries without asking")
+        )
+        (@subcommand run =>
+            (about: "Launches an entry")
+            (@arg ENTRY: +required                               "Entry index or entry name")
+        )
+        (@subcommand gui =>
+            (about: "Starts the GUI (the entry list, or the editor when given an entry or --new)")
+            (@arg ENTRY:                                         "Entry index or entry name to edit")
+            (@arg new:    --new   conflicts_with[ENTRY]          "Open the editor for a new entry")
+        )
+        (@subcommand validate =>
+            (about: "Checks an entry (or all of them) for problems")
+            (@arg ENTRY:                                         "Entry index or entry name")
+        )
+        (@subcommand doctor =>
+            (about: "Checks every entry for broken commands, folders, icons, duplicates and stale menu registrations")
+            (@arg fix: --fix                                     "Repair what can be repaired (asks first, unless -y)")
+            (@arg yes: -y --yes requires[fix]                    "Apply every fix without asking")
+        )
+        (@subcommand reconcile =>
+            (about: "Finds entries that aren't registered with the menu, and mkdesktop- menu entries with no entry behind them, and fixes both")
+            (@arg restore: --restore                             "Copy unbacked menu entries back into mkdesktop instead of removing them")
+            (@arg yes: -y --yes                                  "Repair without asking")
+        )
+        (@subcommand default =>
+            (about: "Makes an entry the default application for MIME types (lists the ones it opens when given none)")
+            (@arg ENTRY: +required                               "Entry index or entry name")
+            (@arg MIME_TYPES: ...                                "MIME types, or file extensions like .md to look them up")
+            (@arg remove: --remove                               "Stop offering the entry for these types instead")
+        )
+        (@subcommand scheme =>
+            (about: "Makes entries open links like ourapp://... (lists the schemes with a handler when given no subcommand)")
+            (@subcommand register =>
+                (about: "Makes an entry the handler for a URL scheme, adding %u to its command if needed")
+                (@arg SCHEME: +required                          "URL scheme, e.g. ourapp")
+                (@arg ENTRY: +required                           "Entry index or entry name")
+            )
+            (@subcommand test =>
+                (about: "Shows which launcher would open a URL, and the command it would run")
+                (@arg URL: +required                             "URL to try, e.g. ourapp://open?id=1")
+            )
+            (@subcommand list =>
+                (about: "Lists the URL schemes with a default handler")
+            )
+        )
+        (@subcommand autostart =>
+            (about: "Starts entries at login (lists what starts at login when given no subcommand)")
+            (@subcommand enable =>
+                (about: "Starts an entry at login")
+                (@arg ENTRY: +required                           "Entry index or entry name, or the desktop file ID of a system autostart entry")
+                (@arg delay: --delay +takes_value                "Seconds to wait after logging in (X-GNOME-Autostart-Delay)")
+            )
+            (@subcommand disable =>
+                (about: "Stops an entry from starting at login (system ones are overridden with Hidden=true)")
+                (@arg ENTRY: +required                           "Entry index or entry name, or the desktop file ID of a system autostart entry")
+            )
+            (@subcommand list =>
+                (about: "Lists what starts at login")
+            )
+        )
+        (@subcommand shortcut =>
+            (about: "Launches entries with global keyboard shortcuts in GNOME and KDE (lists them when given no subcommand)")
+            (@subcommand set =>
+                (about: "Gives an entry a keyboard shortcut, replacing the one it had")
+                (@arg ENTRY: +required                           "Entry index or entry name")
+                (@arg KEYS: +required                            "Key combination, e.g. Ctrl+Alt+T or <Super>t")
+                (@arg force: --force                             "Take the keys even if something else uses them")
+            )
+            (@subcommand remove =>
+                (about: "Takes an entry's keyboard shortcut away")
+                (@arg ENTRY: +required                           "Entry index or entry name")
+            )
+            (@subcommand list =>
+                (about: "Lists entries with keyboard shortcuts")
+            )
+        )
+        (@subcommand flatpak =>
+            (about: "Customizes installed Flatpak apps' own launchers (lists the apps when given no subcommand)")
+            (@subcommand fork =>
+                (about: "Copies an app's exported launcher into the user's applications folder, with extra arguments and environment")
+                (@arg APP_ID: +required                          "Flatpak app ID, e.g. org.gnome.Maps")
+                (@arg arg:   --arg  +takes_value +multiple number_of_values(1) +allow_hyphen_values "Argument for the app (can be repeated)")
+                (@arg env:   --env  +takes_value +multiple number_of_values(1) "Environment variable as KEY=VALUE, passed into the sandbox (can be repeated)")
+                (@arg reset: --reset conflicts_with[arg env]     "Remove the fork, bringing back the app's own launcher")
+            )
+            (@subcommand list =>
+                (about: "Lists installed Flatpak apps")
+            )
+        )
+        (@subcommand override =>
+            (about: "Copies a system launcher into the user's applications folder with the same ID, so changes to the copy win \
+                     (lists the overrides when given no ID)")
+            (@arg DESKTOP_ID:                                    "Desktop file ID, e.g. google-chrome.desktop (.desktop can be left out)")
+            (@arg arg:   --arg  +takes_value +multiple number_of_values(1) +allow_hyphen_values "Argument for the program, e.g. --arg=--force-device-scale-factor=2 (can be repeated)")
+            (@arg env:   --env  +takes_value +multiple number_of_values(1) "Environment variable as KEY=VALUE (can be repeated)")
+            (@arg set:   --set  +takes_value +multiple number_of_values(1) "Key=Value or Key[locale]=Value to set in the copy (can be repeated)")
+            (@arg reset: --reset requires[DESKTOP_ID] conflicts_with[arg env set] "Remove the override, bringing back the original")
+        )
+        (@subcommand menu =>
+            (about: "Manages submenus of the applications menu (lists them when given no subcommand)")
+            (@subcommand create =>
+                (about: "Makes a new submenu")
+                (@arg MENU: +required                            "Name of the submenu, e.g. \"Company Tools\"")
+                (@arg icon:        --icon        -i   +takes_value   "Path to icon")
+                (@arg comment:     --tooltip     -t   +takes_value   "Tooltip for the submenu")
+            )
+            (@subcommand add =>
+                (about: "Puts entries into a submenu")
+                (@arg MENU: +required                            "Name of the submenu")
+                (@arg ENTRIES: +required ...                     "Entry indices or entry names")
+            )
+            (@subcommand remove =>
+                (about: "Takes entries out of a submenu, or removes the submenu itself when no entries are given")
+                (@arg MENU: +required                            "Name of the submenu")
+                (@arg ENTRIES: ...                               "Entry indices or entry names")
+                (@arg yes: -y --yes                              "Don't ask before removing a whole submenu")
+            )
+            (@subcommand list =>
+                (about: "Lists submenus and what's in them")
+            )
+        )
+        (@subcommand completions =>
+            (about: "Prints a shell completion script")
+            (@arg SHELL: +required "bash, zsh or fish")
+        )
+
+        // Everything below is the old flag-based syntax, kept working for existing scripts and launchers
         (@arg FILE_OR_ENTRY:                                 "Executable file or entry index or entry name")
 
         (@arg name:        --name        -n   +takes_value   "Name of program")
@@ -20,34 +234,186 @@ fn main() {
         (@arg path:        --path        -p   +takes_value   "Working directory for when <FILE> gets run (defaults to $PWD)")
         (@arg comment:     --tooltip     -t   +takes_value   "Tooltip when user hovers over application in launcher")
         (@arg yes: -y                                        "Create/update desktop entry without asking about anything")
-        (@arg rm: --remove --rm                              "Remove selected entry")
-        (@arg status: --status -s                            "View desktop files managed by mkdesktop")
-        (@arg new:    --new                                  "Make a new entry")
-        (@arg gui:    --gui   -g                             "Start GUI")
+        (@arg rm: --remove --rm                              "Remove selected entry (deprecated, use `mkdesktop rm`)")
+        (@arg status: --status -s                            "View desktop files managed by mkdesktop (deprecated, use `mkdesktop list/show`)")
+        (@arg new:    --new                                  "Make a new entry (deprecated, use `mkdesktop new`)")
+        (@arg gui:    --gui   -g                             "Start GUI (deprecated, use `mkdesktop gui`)")
+
+        (@arg complete_entries:    --("complete-entries")    +hidden "Print entry names and indices for shell completion")
+        (@arg complete_categories: --("complete-categories") +hidden "Print category names for shell completion")
+        (@arg complete_menus:      --("complete-menus")      +hidden "Print menu names for shell completion")
+        (@arg complete_mime_types: --("complete-mime-types") +hidden "Print known MIME types for shell completion")
     ).get_matches();
 
-    let entry = match arg_matches.value_of("FILE_OR_ENTRY") {
-        Some(selector) => Some(desktop::select(&selector)),
+    let context = Context::from_env();
+    let ctx = &context;
+    let select = |matches: &clap::ArgMatches| matches.value_of("ENTRY").map(|selector| desktop::select(ctx, selector));
+
+    if let Some(name) = arg_matches.subcommand_name() { warn_if_shadowed(ctx, name) }
+
+    match arg_matches.subcommand() {
+        ("new", Some(m))         => cli::new(ctx, m.value_of("FILE"), m),
+        ("webapp", Some(m))      => cli::webapp(ctx, m.value_of("URL").unwrap(), m),
+        ("edit", Some(m))        => cli::edit(ctx, desktop::select(ctx, m.value_of("ENTRY").unwrap()), m),
+        ("show", Some(m))        => cli::status(ctx, select(m)),
+        ("list", Some(_))        => cli::status(ctx, None),
+        ("set", Some(m))         => { let (targets, rest) = targets(ctx, m, "ASSIGNMENTS"); cli::set(ctx, targets, rest, m.is_present("yes")) }
+        ("unset", Some(m))       => { let (targets, rest) = targets(ctx, m, "KEYS"); cli::unset(ctx, targets, rest, m.is_present("yes")) }
+        ("rm", Some(m))          => cli::remove(ctx, targets(ctx, m, "ENTRY").0, m.is_present("yes")),
+        ("scan", Some(m))        => cli::scan(ctx, m.value_of("DIR").unwrap(), m.is_present("yes")),
+        ("run", Some(m))         => cli::run(select(m)),
+        ("gui", Some(m))         => { let entry = select(m); gui::start(context, entry, m.is_present("new")) }
+        ("validate", Some(m))    => cli::validate(ctx, select(m)),
+        ("doctor", Some(m))      => cli::doctor(ctx, m.is_present("fix"), m.is_present("yes")),
+        ("reconcile", Some(m))   => cli::reconcile(ctx, m.is_present("restore"), m.is_present("yes")),
+        ("default", Some(m))     => cli::default_app(ctx, desktop::select(ctx, m.value_of("ENTRY").unwrap()), values(m, "MIME_TYPES"), m.is_present("remove")),
+        ("scheme", Some(m))      => scheme(ctx, m),
+        ("autostart", Some(m))   => autostart(ctx, m),
+        ("shortcut", Some(m))    => shortcut(ctx, m),
+        ("flatpak", Some(m))     => flatpak(ctx, m),
+        ("override", Some(m))    => override_launcher(ctx, m),
+        ("menu", Some(m))        => menu(ctx, m),
+        ("completions", Some(m)) => cli::print_completion_script(m.value_of("SHELL")),
+        _                        => legacy_main(context, &arg_matches)
+    }
+}
+
+
+/// For set/unset/rm: --match and --all-in-category pick the entries instead of <ENTRY>, in which case
+/// whatever clap took as <ENTRY> is really the first of the `rest` values.
+fn targets<'a>(context: &Context, m: &'a clap::ArgMatches, rest: &str) -> (cli::Targets, Vec<&'a str>) {
+    let mut values: Vec<&str> = m.values_of(rest).map(|v| v.collect()).unwrap_or_default();
+
+    let batch = match (m.value_of("match"), m.value_of("all_in_category")) {
+        (Some(pattern), _)    => desktop::select_matching(context, pattern),
+        (None, Some(category)) => desktop::select_in_category(context, category),
+        (None, None) => return (cli::Targets::One(m.value_of("ENTRY").map(|selector| desktop::select(context, selector))), values)
+    };
+
+    if let Some(first) = m.value_of("ENTRY") { values.insert(0, first) }
+    (cli::Targets::Many(batch), values)
+}
+
+
+fn scheme(context: &Context, m: &clap::ArgMatches) {
+    match m.subcommand() {
+        ("register", Some(m)) => cli::scheme_register(context, m.value_of("SCHEME").unwrap(), desktop::select(context, m.value_of("ENTRY").unwrap())),
+        ("test", Some(m))     => cli::scheme_test(context, m.value_of("URL").unwrap()),
+        _                     => cli::scheme_list(context)
+    }
+}
+
+
+fn autostart(context: &Context, m: &clap::ArgMatches) {
+    match m.subcommand() {
+        ("enable", Some(m))  => cli::autostart_enable(context, m.value_of("ENTRY").unwrap(), m.value_of("delay")),
+        ("disable", Some(m)) => cli::autostart_disable(context, m.value_of("ENTRY").unwrap()),
+        _                    => cli::autostart_list(context)
+    }
+}
+
+
+fn shortcut(context: &Context, m: &clap::ArgMatches) {
+    match m.subcommand() {
+        ("set", Some(m))    => cli::shortcut_set(context, desktop::select(context, m.value_of("ENTRY").unwrap()), m.value_of("KEYS").unwrap(), m.is_present("force")),
+        ("remove", Some(m)) => cli::shortcut_remove(context, desktop::select(context, m.value_of("ENTRY").unwrap())),
+        _                   => cli::shortcut_list(context)
+    }
+}
+
+
+fn flatpak(context: &Context, m: &clap::ArgMatches) {
+    match m.subcommand() {
+        ("fork", Some(m)) => cli::flatpak_fork(context, m.value_of("APP_ID").unwrap(), values(m, "arg"), values(m, "env"), m.is_present("reset")),
+        _                 => cli::flatpak_list(context)
+    }
+}
+
+
+fn override_launcher(context: &Context, m: &clap::ArgMatches) {
+    match m.value_of("DESKTOP_ID") {
+        Some(id) => cli::override_launcher(context, id, values(m, "arg"), values(m, "env"), values(m, "set"), m.is_present("reset")),
+        None     => cli::override_list(context)
+    }
+}
+
+
+fn menu(context: &Context, m: &clap::ArgMatches) {
+    match m.subcommand() {
+        ("create", Some(m)) => cli::menu_create(context, m.value_of("MENU").unwrap(), m.value_of("comment"), m.value_of("icon")),
+        ("add", Some(m))    => cli::menu_add(context, m.value_of("MENU").unwrap(), values(m, "ENTRIES")),
+        ("remove", Some(m)) => cli::menu_remove(context, m.value_of("MENU").unwrap(), values(m, "ENTRIES"), m.is_present("yes")),
+        _                   => cli::menu_list(context)
+    }
+}
+
+fn values<'a>(m: &'a clap::ArgMatches, name: &str) -> Vec<&'a str> {
+    m.values_of(name).map(|v| v.collect()).unwrap_or_default()
+}
+
+
+/// The original flag-based interface. The flags overlap, so the precedence here matters:
+/// --rm, then --gui, then --new, then --status, then create/update.
+fn legacy_main(context: Context, arg_matches: &clap::ArgMatches) {
+    if arg_matches.is_present("complete_entries") {
+        return cli::print_entry_candidates(&context);
+    }
+    if arg_matches.is_present("complete_categories") {
+        return cli::print_category_candidates();
+    }
+    if arg_matches.is_present("complete_menus") {
+        return cli::print_menu_candidates(&context);
+    }
+    if arg_matches.is_present("complete_mime_types") {
+        return cli::print_mime_candidates(&context);
+    }
+
+    let file_or_entry = arg_matches.value_of("FILE_OR_ENTRY");
+    let entry = match file_or_entry {
+        Some(selector) => Some(desktop::select(&context, &selector)),
         None           => None
     };
-    // TODO move cli::create_or_update's entry creation here, and allow new entries to be constructed for any
-    // command (it'll have to be marked as "new" of course)
 
     if arg_matches.is_present("rm") {
-        cli::remove(entry);
+        deprecated("--rm", "mkdesktop rm <ENTRY>");
+        cli::remove(&context, cli::Targets::One(entry), false);
     }
     else if arg_matches.is_present("gui") {
-        gui::start(entry, arg_matches);
+        deprecated("--gui", "mkdesktop gui [ENTRY]");
+        let show_index = arg_matches.is_present("status") || file_or_entry.is_none();
+        gui::start(context, if show_index { None } else { entry }, arg_matches.is_present("new"));
     }
     else if arg_matches.is_present("new") {
-        cli::create_or_update(None, arg_matches);
+        deprecated("--new", "mkdesktop new <FILE>");
+        cli::create_or_update(&context, None, file_or_entry, arg_matches);
     }
-    else if arg_matches.is_present("status") || !arg_matches.is_present("FILE_OR_ENTRY") {
-        cli::status(entry);
+    else if arg_matches.is_present("status") || file_or_entry.is_none() {
+        if arg_matches.is_present("status") {
+            deprecated("--status", "mkdesktop list / mkdesktop show <ENTRY>");
+        }
+        cli::status(&context, entry);
     }
     else {
-        cli::create_or_update(entry, arg_matches);
+        cli::create_or_update(&context, entry, file_or_entry, arg_matches);
     }
 }
 
 
+/// The old syntax took a file or entry as the first argument, so `mkdesktop list` may have meant
+/// an entry named "list" or a program in the current folder. Subcommands win, but not silently.
+/// (clap won't take a subcommand's name as FILE_OR_ENTRY even after --, hence the other spellings.)
+fn warn_if_shadowed(context: &Context, subcommand: &str) {
+    if Path::new(subcommand).exists() {
+        eprintln!("Warning: \"{}\" is taken as the `mkdesktop {}` subcommand; for the file, use `mkdesktop ./{}`",
+                  subcommand, subcommand, subcommand);
+    }
+    if desktop::select(context, subcommand).is_ok() {
+        eprintln!("Warning: \"{}\" is taken as the `mkdesktop {}` subcommand; for the entry, use `mkdesktop show/edit \"{}\"`",
+                  subcommand, subcommand, subcommand);
+    }
+}
+
+
+fn deprecated(flag: &str, replacement: &str) {
+    eprintln!("Warning: {} is deprecated, use `{}` instead", flag, replacement);
+}
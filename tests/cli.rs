@@ -0,0 +1,526 @@
+//! Runs the mkdesktop binary end-to-end against a throwaway XDG tree.
+//! A shell script stands in for xdg-desktop-menu (through $MKDESKTOP_REGISTRAR), copying files into
+//! $XDG_DATA_HOME/applications like the real thing and logging what it was asked to do.
+
+use std::fs;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+
+const FAKE_REGISTRAR: &str = r#"#!/bin/sh
+echo "$@" >> "$MKDESKTOP_TEST_ROOT/registrar.log"
+case "$1" in
+    install)   mkdir -p "$XDG_DATA_HOME/applications" && cp "$2" "$XDG_DATA_HOME/applications/" ;;
+    uninstall) rm -f "$XDG_DATA_HOME/applications/$2" ;;
+esac
+"#;
+
+
+struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Sandbox {
+        let root = env::temp_dir().join(format!("mkdesktop-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        for dir in &["home", "data", "config", "cache", "bin", "xdg", "share"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let sandbox = Sandbox { root: root };
+        sandbox.script("registrar", FAKE_REGISTRAR);
+        sandbox
+    }
+
+
+    /// An executable in the sandbox's bin folder
+    fn script(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.root.join("bin").join(name);
+        fs::write(&path, contents).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+
+    fn mkdesktop(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_mkdesktop"))
+            .args(args)
+            .current_dir(&self.root)
+            .env("HOME", self.root.join("home"))
+            .env("PWD", &self.root)
+            .env("XDG_DATA_HOME", self.root.join("data"))
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("XDG_CACHE_HOME", self.root.join("cache"))
+            .env("XDG_CONFIG_DIRS", self.root.join("xdg"))
+            .env("XDG_DATA_DIRS", self.root.join("share"))
+            .env("MKDESKTOP_REGISTRAR", self.root.join("bin").join("registrar"))
+            .env("MKDESKTOP_TEST_ROOT", &self.root)
+            .env("GSETTINGS_BACKEND", "memory")
+            .env("FLATPAK_SYSTEM_DIR", self.root.join("flatpak"))
+            .stdin(Stdio::null())
+            .output()
+            .expect("Couldn't run mkdesktop")
+    }
+
+    /// Runs mkdesktop, failing the test unless it exits cleanly. Returns stdout.
+    fn ok(&self, args: &[&str]) -> String {
+        let output = self.mkdesktop(args);
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        assert!(output.status.success(), "mkdesktop {:?} failed ({})\n{}{}", args, output.status, stdout, String::from_utf8_lossy(&output.stderr));
+        stdout
+    }
+
+
+    fn entry_file(&self, filename: &str) -> PathBuf {
+        self.root.join("data").join("mkdesktop").join(filename)
+    }
+
+    fn registered_file(&self, filename: &str) -> PathBuf {
+        self.root.join("data").join("applications").join(filename)
+    }
+
+    fn registrar_log(&self) -> String {
+        fs::read_to_string(self.root.join("registrar.log")).unwrap_or_default()
+    }
+
+
+    fn new_entry(&self, name: &str) -> PathBuf {
+        let program = self.script("tool", "#!/bin/sh\n");
+        let root = self.root.to_string_lossy().to_string();
+        self.ok(&["new", program.to_str().unwrap(), "--name", name, "--path", &root, "-c", "Utility", "-y"]);
+        program
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+
+fn contents(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| panic!("Couldn't read {:?} - {}", path, e))
+}
+
+
+#[test]
+fn new_writes_and_registers_an_entry() {
+    let sandbox = Sandbox::new("new");
+    let program = sandbox.new_entry("Test Tool");
+
+    let file = contents(&sandbox.entry_file("mkdesktop-Test-Tool.desktop"));
+    assert!(file.contains("Name=Test Tool\n"), "{}", file);
+    assert!(file.contains(&format!("Exec={}\n", program.display())), "{}", file);
+    assert!(file.contains("Categories=Utility;\n"), "{}", file);
+
+    assert_eq!(contents(&sandbox.registered_file("mkdesktop-Test-Tool.desktop")), file);
+    assert!(sandbox.registrar_log().starts_with("install "));
+
+    let list = sandbox.ok(&["list"]);
+    assert!(list.contains("(0) Test Tool"), "{}", list);
+
+    // A theme icon name is kept as it is, not taken for a file in the current folder
+    let root = sandbox.root.to_string_lossy().to_string();
+    sandbox.ok(&["new", program.to_str().unwrap(), "--name", "Themed", "--path", &root, "-c", "Utility", "--icon", "utilities-terminal", "-y"]);
+    let themed = contents(&sandbox.entry_file("mkdesktop-Themed.desktop"));
+    assert!(themed.contains("\nIcon=utilities-terminal\n"), "{}", themed);
+}
+
+
+#[test]
+fn set_and_unset_change_keys_and_reregister() {
+    let sandbox = Sandbox::new("set");
+    sandbox.new_entry("Test Tool");
+
+    sandbox.ok(&["set", "Test Tool", "X-Foo=bar", "Comment=Hello"]);
+    let file = contents(&sandbox.entry_file("mkdesktop-Test-Tool.desktop"));
+    assert!(file.contains("X-Foo=bar\n") && file.contains("Comment=Hello\n"), "{}", file);
+    assert_eq!(contents(&sandbox.registered_file("mkdesktop-Test-Tool.desktop")), file);
+
+    sandbox.ok(&["unset", "0", "X-Foo"]);
+    assert!(!contents(&sandbox.entry_file("mkdesktop-Test-Tool.desktop")).contains("X-Foo"));
+
+    // Categories the entry already had are only warned about when editing with -y
+    sandbox.ok(&["set", "Test Tool", "Categories=Utility;Termin;"]);
+    assert!(sandbox.ok(&["edit", "Test Tool", "-y"]).contains("did you mean TerminalEmulator?"));
+    assert!(contents(&sandbox.entry_file("mkdesktop-Test-Tool.desktop")).contains("Categories=Utility;Termin;\n"));
+    assert!(!sandbox.mkdesktop(&["edit", "Test Tool", "-c", "Utility;Termin", "-y"]).status.success());
+
+    // Renaming moves the file and takes the old one out of the menu
+    sandbox.ok(&["set", "Test Tool", "Name=Renamed"]);
+    assert!(sandbox.entry_file("mkdesktop-Renamed.desktop").exists());
+    assert!(!sandbox.entry_file("mkdesktop-Test-Tool.desktop").exists());
+    assert!(!sandbox.registered_file("mkdesktop-Test-Tool.desktop").exists());
+    assert!(sandbox.registrar_log().contains("uninstall mkdesktop-Test-Tool.desktop"));
+
+    // ...but not onto another entry's file
+    sandbox.new_entry("Other Tool");
+    assert!(!sandbox.mkdesktop(&["set", "Renamed", "Name=Other Tool"]).status.success());
+    assert!(sandbox.entry_file("mkdesktop-Renamed.desktop").exists());
+    assert!(contents(&sandbox.entry_file("mkdesktop-Other-Tool.desktop")).contains("Name=Other Tool\n"));
+}
+
+
+#[test]
+fn rm_unregisters_and_deletes_entries() {
+    let sandbox = Sandbox::new("rm");
+    sandbox.new_entry("First Tool");
+    sandbox.new_entry("Second Tool");
+
+    sandbox.ok(&["rm", "--match", "*tool", "-y"]);
+
+    for filename in &["mkdesktop-First-Tool.desktop", "mkdesktop-Second-Tool.desktop"] {
+        assert!(!sandbox.entry_file(filename).exists());
+        assert!(!sandbox.registered_file(filename).exists());
+    }
+
+    let missing = sandbox.mkdesktop(&["rm", "First Tool"]);
+    assert!(!missing.status.success());
+
+    // An entry named like a subcommand doesn't get the subcommand without a word about it
+    sandbox.new_entry("list");
+    let listing = sandbox.mkdesktop(&["list"]);
+    assert!(String::from_utf8_lossy(&listing.stderr).contains("mkdesktop show/edit \"list\""));
+    let action = contents(&sandbox.entry_file("mkdesktop-list.desktop"));
+    assert!(action.contains("\nExec=mkdesktop rm list\n"), "{}", action);
+
+    // One that doesn't parse is still listed and removable, and the complaint stays out of completions
+    let broken = sandbox.entry_file("mkdesktop-Broken.desktop");
+    fs::write(&broken, "[Desktop Entry]\nType=Application\nName=Broken\nExec=true\nExec=false\n").unwrap();
+    let completions = sandbox.mkdesktop(&["--complete-entries"]);
+    assert!(!String::from_utf8_lossy(&completions.stdout).contains("parse"));
+    assert!(String::from_utf8_lossy(&completions.stdout).contains("Broken"));
+    sandbox.ok(&["rm", "Broken", "-y"]);
+    assert!(!broken.exists());
+}
+
+
+#[test]
+fn reconcile_registers_entries_missing_from_the_menu() {
+    let sandbox = Sandbox::new("reconcile");
+    sandbox.new_entry("Test Tool");
+
+    fs::remove_file(sandbox.registered_file("mkdesktop-Test-Tool.desktop")).unwrap();
+    fs::write(sandbox.registered_file("mkdesktop-Leftover.desktop"), "[Desktop Entry]\nName=Leftover\n").unwrap();
+
+    sandbox.ok(&["reconcile", "-y"]);
+    assert!(sandbox.registered_file("mkdesktop-Test-Tool.desktop").exists());
+    assert!(!sandbox.registered_file("mkdesktop-Leftover.desktop").exists());
+
+    assert!(sandbox.ok(&["reconcile"]).contains("Everything is in sync"));
+}
+
+
+#[test]
+fn doctor_reports_and_fixes_broken_entries() {
+    let sandbox = Sandbox::new("doctor");
+    let program = sandbox.new_entry("Test Tool");
+    assert!(sandbox.ok(&["doctor"]).contains("Everything looks good"));
+
+    // A missing program with nothing to point at instead is only reported
+    fs::remove_file(&program).unwrap();
+    let report = sandbox.mkdesktop(&["doctor"]);
+    assert_eq!(report.status.code(), Some(13));
+    assert!(String::from_utf8_lossy(&report.stdout).contains("doesn't exist"));
+    assert_eq!(sandbox.mkdesktop(&["doctor", "--fix", "-y"]).status.code(), Some(13));
+    assert!(sandbox.entry_file("mkdesktop-Test-Tool.desktop").exists());
+
+    // ...and so is a file that isn't a desktop file at all
+    fs::write(sandbox.entry_file("notes.desktop"), "[Notes]\nText=hello\n").unwrap();
+    assert_eq!(sandbox.mkdesktop(&["doctor", "--fix", "-y"]).status.code(), Some(13));
+    assert!(sandbox.entry_file("notes.desktop").exists());
+    fs::remove_file(sandbox.entry_file("notes.desktop")).unwrap();
+
+    // An entry with no command at all is broken beyond repair
+    let file = sandbox.entry_file("mkdesktop-Test-Tool.desktop");
+    let text = contents(&file).lines().filter(|line| !line.starts_with("Exec=")).collect::<Vec<_>>().join("\n");
+    fs::write(&file, text).unwrap();
+    sandbox.ok(&["doctor", "--fix", "-y"]);
+    assert!(!file.exists());
+    assert!(!sandbox.registered_file("mkdesktop-Test-Tool.desktop").exists());
+}
+
+
+#[test]
+fn links_and_menu_folders_are_written_with_their_own_keys() {
+    let sandbox = Sandbox::new("types");
+
+    sandbox.ok(&["new", "--url", "https://example.com/docs", "--name", "Docs", "-y"]);
+    let link = contents(&sandbox.entry_file("mkdesktop-Docs.desktop"));
+    assert!(link.contains("Type=Link\n") && link.contains("URL=https://example.com/docs\n"), "{}", link);
+    assert!(!link.contains("Exec="), "{}", link);
+    assert!(sandbox.registered_file("mkdesktop-Docs.desktop").exists());
+
+    sandbox.ok(&["new", "--type", "directory", "--name", "Tools", "-y"]);
+    let folder = contents(&sandbox.entry_file("mkdesktop-Tools.directory"));
+    assert!(folder.contains("Type=Directory\n"), "{}", folder);
+    assert_eq!(contents(&sandbox.root.join("data").join("desktop-directories").join("mkdesktop-Tools.directory")), folder);
+
+    let missing_url = sandbox.mkdesktop(&["new", "--type", "link", "--name", "Nowhere", "-y"]);
+    assert_eq!(missing_url.status.code(), Some(11));
+}
+
+
+#[test]
+fn menus_keep_track_of_their_entries() {
+    let sandbox = Sandbox::new("menu");
+    sandbox.new_entry("Test Tool");
+    let fragment = sandbox.root.join("config").join("menus").join("applications-merged").join("mkdesktop-Company-Tools.menu");
+
+    sandbox.ok(&["menu", "create", "Company Tools"]);
+    assert!(sandbox.root.join("data").join("desktop-directories").join("mkdesktop-Company-Tools.directory").exists());
+
+    sandbox.ok(&["menu", "add", "company tools", "Test Tool"]);
+    let xml = contents(&fragment);
+    assert!(xml.contains("<Directory>mkdesktop-Company-Tools.directory</Directory>"), "{}", xml);
+    assert!(xml.contains("<Filename>mkdesktop-Test-Tool.desktop</Filename>"), "{}", xml);
+
+    // Renaming the entry follows it into the menu, removing it takes it out
+    sandbox.ok(&["set", "Test Tool", "Name=Renamed"]);
+    assert!(contents(&fragment).contains("<Filename>mkdesktop-Renamed.desktop</Filename>"));
+    assert!(sandbox.ok(&["menu", "list"]).contains("\tRenamed (mkdesktop-Renamed.desktop)"));
+
+    sandbox.ok(&["rm", "Renamed"]);
+    assert!(!contents(&fragment).contains("<Filename>"));
+
+    sandbox.ok(&["menu", "remove", "Company Tools", "-y"]);
+    assert!(!fragment.exists());
+    assert!(!sandbox.entry_file("mkdesktop-Company-Tools.directory").exists());
+}
+
+
+#[test]
+fn autostart_copies_follow_their_entries() {
+    let sandbox = Sandbox::new("autostart");
+    sandbox.new_entry("Test Tool");
+    let copy = sandbox.root.join("config").join("autostart").join("mkdesktop-Test-Tool.desktop");
+
+    sandbox.ok(&["autostart", "enable", "Test Tool", "--delay", "10"]);
+    let file = contents(&copy);
+    assert!(file.contains("X-GNOME-Autostart-enabled=true\n") && file.contains("X-GNOME-Autostart-Delay=10\n"), "{}", file);
+
+    // Edits reach the copy, and a rename moves it
+    sandbox.ok(&["set", "Test Tool", "Comment=Hello"]);
+    assert!(contents(&copy).contains("Comment=Hello\n"));
+    sandbox.ok(&["set", "Test Tool", "Name=Renamed"]);
+    assert!(!copy.exists());
+    let renamed = sandbox.root.join("config").join("autostart").join("mkdesktop-Renamed.desktop");
+    assert!(contents(&renamed).contains("X-GNOME-Autostart-Delay=10\n"));
+
+    sandbox.ok(&["autostart", "disable", "Renamed"]);
+    assert!(!renamed.exists());
+
+    // Ones that came with the system get overridden instead
+    fs::create_dir_all(sandbox.root.join("xdg").join("autostart")).unwrap();
+    fs::write(sandbox.root.join("xdg").join("autostart").join("applet.desktop"), "[Desktop Entry]\nName=Applet\nExec=applet\n").unwrap();
+    sandbox.ok(&["autostart", "disable", "applet"]);
+    assert!(contents(&sandbox.root.join("config").join("autostart").join("applet.desktop")).contains("Hidden=true\n"));
+    assert!(sandbox.ok(&["autostart", "list"]).contains("Applet (applet.desktop) - disabled"));
+}
+
+
+#[test]
+fn default_applications_go_into_mimeapps_list() {
+    let sandbox = Sandbox::new("mime");
+    fs::create_dir_all(sandbox.root.join("share").join("mime")).unwrap();
+    fs::write(sandbox.root.join("share").join("mime").join("globs2"), "50:text/markdown:*.md\n50:text/plain:*.txt\n").unwrap();
+    sandbox.new_entry("Notes");
+    let entry = sandbox.entry_file("mkdesktop-Notes.desktop");
+    let list = sandbox.root.join("config").join("mimeapps.list");
+
+    sandbox.ok(&["edit", "Notes", "--mime", ".txt", "-y"]);
+    assert!(contents(&entry).contains("MimeType=text/plain;\n"));
+
+    // Types the entry doesn't declare yet are added to it
+    sandbox.ok(&["default", "Notes", ".md"]);
+    assert!(contents(&entry).contains("MimeType=text/plain;text/markdown;\n"));
+    let file = contents(&list);
+    assert!(file.contains("[Default Applications]\ntext/markdown=mkdesktop-Notes.desktop;\n"), "{}", file);
+    assert!(file.contains("[Added Associations]\ntext/markdown=mkdesktop-Notes.desktop;\n"), "{}", file);
+
+    sandbox.ok(&["default", "Notes", "--remove", "text/plain"]);
+    assert!(contents(&list).contains("[Removed Associations]\ntext/plain=mkdesktop-Notes.desktop;\n"));
+    assert_eq!(sandbox.ok(&["default", "Notes"]), "text/plain\ntext/markdown (default)\n");
+
+    // Editing keeps the types unless --mime is given, and an empty one clears them
+    sandbox.ok(&["edit", "Notes", "-y"]);
+    assert!(contents(&entry).contains("MimeType=text/plain;text/markdown;\n"));
+    sandbox.ok(&["edit", "Notes", "--mime", "", "-y"]);
+    assert!(!contents(&entry).contains("MimeType"), "{}", contents(&entry));
+
+    sandbox.ok(&["rm", "Notes"]);
+    assert!(!contents(&list).contains("mkdesktop-Notes"));
+}
+
+
+#[test]
+fn scheme_handlers_are_registered_and_resolved() {
+    let sandbox = Sandbox::new("scheme");
+    sandbox.new_entry("Our App");
+    let entry = sandbox.entry_file("mkdesktop-Our-App.desktop");
+
+    // Nothing handles it yet
+    assert_eq!(sandbox.mkdesktop(&["scheme", "test", "ourapp://open?id=1"]).status.code(), Some(16));
+
+    sandbox.ok(&["scheme", "register", "ourapp://", "Our App"]);
+    let file = contents(&entry);
+    assert!(file.contains("MimeType=x-scheme-handler/ourapp;\n") && file.contains(" %u\n"), "{}", file);
+    assert!(contents(&sandbox.root.join("config").join("mimeapps.list"))
+        .contains("[Default Applications]\nx-scheme-handler/ourapp=mkdesktop-Our-App.desktop;\n"));
+
+    let resolved = sandbox.ok(&["scheme", "test", "OurApp://open?id=1"]);
+    assert!(resolved.starts_with("Our App (mkdesktop-Our-App.desktop) opens x-scheme-handler/ourapp\n"), "{}", resolved);
+    assert!(resolved.ends_with(" \"OurApp://open?id=1\"\n"), "{}", resolved);
+    assert_eq!(sandbox.ok(&["scheme", "list"]), "ourapp: mkdesktop-Our-App.desktop\n");
+
+    // Registering again leaves the command alone
+    sandbox.ok(&["scheme", "register", "ourapp", "Our App"]);
+    assert_eq!(contents(&entry).matches("%u").count(), 1);
+}
+
+
+#[test]
+fn desktop_copies_follow_their_entries() {
+    let sandbox = Sandbox::new("on-desktop");
+    fs::write(sandbox.root.join("config").join("user-dirs.dirs"), "XDG_DESKTOP_DIR=\"$HOME/Schreibtisch\"\n").unwrap();
+    let program = sandbox.script("tool", "#!/bin/sh\n");
+    sandbox.ok(&["new", program.to_str().unwrap(), "--name", "Desk Tool", "--on-desktop", "-y"]);
+
+    let copy = sandbox.root.join("home").join("Schreibtisch").join("mkdesktop-Desk-Tool.desktop");
+    assert_eq!(contents(&copy), contents(&sandbox.entry_file("mkdesktop-Desk-Tool.desktop")));
+    assert_eq!(fs::metadata(&copy).unwrap().permissions().mode() & 0o111, 0o111);
+
+    sandbox.ok(&["set", "Desk Tool", "Comment=Sits on the desktop"]);
+    assert!(contents(&copy).contains("Comment=Sits on the desktop\n"));
+
+    sandbox.ok(&["edit", "Desk Tool", "--off-desktop", "-y"]);
+    assert!(!copy.exists());
+    sandbox.ok(&["edit", "Desk Tool", "--on-desktop", "-y"]);
+    sandbox.ok(&["rm", "Desk Tool", "-y"]);
+    assert!(!copy.exists());
+}
+
+
+#[test]
+fn shortcuts_follow_their_entries() {
+    let sandbox = Sandbox::new("shortcut");
+    let shortcuts = sandbox.root.join("config").join("kglobalshortcutsrc");
+    fs::write(&shortcuts, "[kwin]\nWalk Through Windows=Alt+Tab,Alt+Tab,Walk Through Windows\n").unwrap();
+    sandbox.new_entry("Key Tool");
+
+    assert_eq!(sandbox.mkdesktop(&["shortcut", "set", "Key Tool", "Alt+Tab"]).status.code(), Some(17));
+    sandbox.ok(&["shortcut", "set", "Key Tool", "<Control><Alt>k"]);
+    assert!(contents(&shortcuts).contains("[services][mkdesktop-Key-Tool.desktop]\n_launch=Ctrl+Alt+K\n"));
+    assert!(sandbox.ok(&["shortcut", "list"]).contains("Key Tool (mkdesktop-Key-Tool.desktop) - Ctrl+Alt+K (KDE)"));
+
+    sandbox.ok(&["edit", "Key Tool", "--name", "Hot Tool", "-y"]);
+    let file = contents(&shortcuts);
+    assert!(file.contains("[services][mkdesktop-Hot-Tool.desktop]\n_launch=Ctrl+Alt+K\n"), "{}", file);
+    assert!(!file.contains("[services][mkdesktop-Key-Tool.desktop]\n_launch"), "{}", file);
+
+    sandbox.ok(&["rm", "Hot Tool", "-y"]);
+    assert!(!contents(&shortcuts).contains("_launch"));
+    assert!(contents(&shortcuts).contains("Walk Through Windows=Alt+Tab"));
+}
+
+
+#[test]
+fn windows_programs_run_through_wine() {
+    let sandbox = Sandbox::new("wine");
+    let prefix = sandbox.root.join("home").join(".wine-tools");
+    fs::create_dir_all(prefix.join("drive_c")).unwrap();
+    fs::write(prefix.join("system.reg"), "WINE REGISTRY Version 2\n").unwrap();
+    let games = sandbox.root.join("games");
+    fs::create_dir_all(&games).unwrap();
+    fs::write(games.join("Tool.exe"), "MZ").unwrap();
+
+    let exe = games.join("Tool.exe");
+    sandbox.ok(&["new", exe.to_str().unwrap(), "--name", "Win Tool", "-c", "Utility", "--wine-prefix", prefix.to_str().unwrap(), "-y", "--", "/safe"]);
+
+    let file = contents(&sandbox.entry_file("mkdesktop-Win-Tool.desktop"));
+    assert!(file.contains(&format!("Exec=env WINEPREFIX={} wine {} /safe\n", prefix.display(), exe.display())), "{}", file);
+    assert!(file.contains(&format!("Path={}\n", games.display())), "{}", file);
+    assert!(file.contains("StartupWMClass=tool.exe\n"), "{}", file);
+}
+
+
+#[test]
+fn flatpaks_are_wrapped_and_forked() {
+    let sandbox = Sandbox::new("flatpak");
+    let app = sandbox.root.join("flatpak").join("app").join("org.example.Tool");
+    fs::create_dir_all(app.join("x86_64").join("stable").join("active")).unwrap();
+    std::os::unix::fs::symlink("x86_64/stable", app.join("current")).unwrap();
+    fs::write(app.join("current").join("active").join("metadata"),
+              "[Application]\nname=org.example.Tool\nruntime=org.example.Platform/x86_64/1\ncommand=tool\n").unwrap();
+    let exports = sandbox.root.join("flatpak").join("exports").join("share").join("applications");
+    fs::create_dir_all(&exports).unwrap();
+    fs::write(exports.join("org.example.Tool.desktop"),
+              "[Desktop Entry]\nType=Application\nName=Example Tool\nIcon=org.example.Tool\nCategories=Utility;\n\
+               Exec=/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=tool org.example.Tool %U\n").unwrap();
+
+    sandbox.ok(&["new", "org.example.Tool", "-y", "--", "--verbose"]);
+    let file = contents(&sandbox.entry_file("mkdesktop-Example-Tool.desktop"));
+    assert!(file.contains("Exec=flatpak run org.example.Tool --verbose\n"), "{}", file);
+    assert!(file.contains("Icon=org.example.Tool\n"), "{}", file);
+    assert!(!file.contains("Path="), "{}", file);
+    assert!(sandbox.ok(&["show", "Example Tool"]).contains("# Flatpak org.example.Tool (stable, system installation"));
+
+    sandbox.ok(&["flatpak", "fork", "org.example.Tool", "--env", "GDK_SCALE=2", "--arg", "--safe-mode"]);
+    let fork = contents(&sandbox.registered_file("org.example.Tool.desktop"));
+    assert!(fork.contains("Exec=/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=tool --env=GDK_SCALE=2 org.example.Tool --safe-mode %U\n"), "{}", fork);
+    assert!(sandbox.ok(&["flatpak", "list"]).contains("org.example.Tool (stable, system, forked)"));
+
+    sandbox.ok(&["flatpak", "fork", "org.example.Tool", "--reset"]);
+    assert!(!sandbox.registered_file("org.example.Tool.desktop").exists());
+}
+
+
+#[test]
+fn system_launchers_are_overridden_and_watched() {
+    let sandbox = Sandbox::new("override");
+    let system = sandbox.root.join("share").join("applications");
+    fs::create_dir_all(&system).unwrap();
+    fs::write(system.join("google-chrome.desktop"), "[Desktop Entry]\nType=Application\nName=Google Chrome\nExec=/usr/bin/google-chrome-stable %U\n").unwrap();
+
+    sandbox.ok(&["override", "google-chrome", "--arg", "--force-device-scale-factor=2", "--arg", "--zoom=50%", "--set", "Name[de]=Chrome"]);
+    let copy = contents(&sandbox.registered_file("google-chrome.desktop"));
+    assert!(copy.contains("Exec=/usr/bin/google-chrome-stable --force-device-scale-factor=2 --zoom=50%% %U\n"), "{}", copy);
+    assert!(copy.contains("Name[de]=Chrome\n"), "{}", copy);
+    assert!(sandbox.ok(&["override"]).contains("google-chrome.desktop - copied from"));
+    assert!(sandbox.ok(&["doctor"]).contains("Everything looks good"));
+
+    fs::write(system.join("google-chrome.desktop"), "[Desktop Entry]\nType=Application\nName=Google Chrome\nExec=/opt/google/chrome/chrome %U\n").unwrap();
+    let output = sandbox.mkdesktop(&["doctor"]);
+    assert_eq!(output.status.code(), Some(13));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("which has changed since"));
+
+    sandbox.ok(&["override", "google-chrome.desktop", "--reset"]);
+    assert!(!sandbox.registered_file("google-chrome.desktop").exists());
+    assert_eq!(sandbox.mkdesktop(&["override", "firefox"]).status.code(), Some(11));
+}
+
+
+#[test]
+fn scan_never_runs_what_it_finds() {
+    let sandbox = Sandbox::new("scan");
+    let marker = sandbox.root.join("ran");
+    let downloads = sandbox.root.join("downloads");
+    fs::create_dir_all(&downloads).unwrap();
+    let appimage = downloads.join("tool-1.2-x86_64.AppImage");
+    fs::write(&appimage, format!("#!/bin/sh\ntouch {}\n", marker.display())).unwrap();
+    fs::set_permissions(&appimage, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // Nobody answers the prompt here, which counts as no
+    assert!(sandbox.ok(&["scan", downloads.to_str().unwrap()]).contains("Nothing was created"));
+    sandbox.ok(&["scan", downloads.to_str().unwrap(), "--yes"]);
+    assert!(sandbox.entry_file("mkdesktop-Tool.desktop").exists());
+    assert!(!marker.exists());
+
+    sandbox.ok(&["edit", "Tool", "--tooltip", "Does things", "--yes"]);
+    assert!(contents(&sandbox.entry_file("mkdesktop-Tool.desktop")).contains("Comment=Does things\n"));
+}